@@ -0,0 +1,176 @@
+//! Cheat sheet of every command, with shortcuts, docs and parameters, grouped by category.
+//!
+//! Written as Markdown or as a standalone HTML page, to print or to put in docs.
+
+use std::collections::BTreeMap;
+
+use crate::{CommandMap, CommandInfo, Keymap};
+
+const TITLE: &str = "Commands and shortcuts";
+/// Category of commands that don't declare one.
+const DEFAULT_CATEGORY: &str = "Other";
+
+/// System names and commands, by category.
+type Categories<'a, ParamType, ContextType> = BTreeMap<&'a str, Vec<(&'a String, &'a CommandInfo<ParamType, ContextType>)>>;
+
+/// Commands by category, then by title.
+fn commands_by_category<ParamType: Clone, ContextType>(
+    commands: &CommandMap<ParamType, ContextType>
+) -> Categories<'_, ParamType, ContextType> {
+    let mut categories: Categories<ParamType, ContextType> = BTreeMap::new();
+    for (system_name, command) in commands.commands.iter() {
+        let category = match command.category.is_empty() {
+            true => DEFAULT_CATEGORY,
+            false => command.category.as_str(),
+        };
+        categories.entry(category).or_default().push((system_name, command));
+    }
+    for commands in categories.values_mut() {
+        commands.sort_by(|a, b| a.1.title.cmp(&b.1.title));
+    }
+    return categories;
+}
+
+/// One line per parameter: "radius (F32, 0.01 to 10, optional): Radius of the sphere".
+fn parameter_descriptions<ParamType: Clone, ContextType>(command: &CommandInfo<ParamType, ContextType>) -> Vec<String> {
+    return command.parameters.iter().map(|param| {
+        let mut details = vec!(param.param_type.clone());
+        if let Some((min, max)) = param.range {
+            details.push(format!("{} to {}", min, max));
+        }
+        if param.optional || param.default.is_some() {
+            details.push("optional".to_string());
+        }
+        return format!("{} ({}): {}", param.name, details.join(", "), param.docs);
+    }).collect();
+}
+
+fn escape_markdown(text: &str) -> String {
+    return text.replace("|", "\\|").replace("\n", " ");
+}
+
+fn escape_html(text: &str) -> String {
+    return text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;");
+}
+
+impl<ParamType: Clone, ContextType> CommandMap<ParamType, ContextType> {
+    /// Markdown tables of commands, one per category. Shortcuts come from `keymap`, so user changes are included.
+    pub fn cheat_sheet_markdown(&self, keymap: &Keymap) -> String {
+        let mut markdown = format!("# {}\n", TITLE);
+
+        for (category, commands) in commands_by_category(self) {
+            markdown += &format!("\n## {}\n\n", category);
+            markdown += "| Command | Shortcut | Description |\n";
+            markdown += "|---|---|---|\n";
+
+            for (system_name, command) in commands {
+                let shortcut = match keymap.shortcut(system_name) {
+                    Some(shortcut) => format!("`{}`", shortcut),
+                    None => "".to_string(),
+                };
+                let mut description = escape_markdown(&command.docs);
+                for parameter in parameter_descriptions(command) {
+                    description += &format!("<br>`{}`", escape_markdown(&parameter));
+                }
+                markdown += &format!(
+                    "| **{}** `{}` | {} | {} |\n",
+                    escape_markdown(&command.title),
+                    system_name,
+                    shortcut,
+                    description
+                );
+            }
+        }
+
+        return markdown;
+    }
+
+    /// Standalone HTML page with the same content as `cheat_sheet_markdown`.
+    pub fn cheat_sheet_html(&self, keymap: &Keymap) -> String {
+        let mut html = format!(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n",
+            "<style>body {{ font-family: sans-serif; }} td, th {{ padding: 4px 8px; text-align: left; vertical-align: top; }} ",
+            "kbd {{ border: 1px solid #aaa; border-radius: 3px; padding: 0 4px; }}</style>\n",
+            "</head>\n<body>\n<h1>{title}</h1>\n"
+        ), title = TITLE);
+
+        for (category, commands) in commands_by_category(self) {
+            html += &format!("<h2>{}</h2>\n<table>\n", escape_html(category));
+            html += "<tr><th>Command</th><th>Shortcut</th><th>Description</th></tr>\n";
+
+            for (system_name, command) in commands {
+                let shortcut = match keymap.shortcut(system_name) {
+                    Some(shortcut) => format!("<kbd>{}</kbd>", escape_html(shortcut)),
+                    None => "".to_string(),
+                };
+                let mut description = escape_html(&command.docs);
+                for parameter in parameter_descriptions(command) {
+                    description += &format!("<br><code>{}</code>", escape_html(&parameter));
+                }
+                html += &format!(
+                    "<tr><td><b>{}</b><br><code>{}</code></td><td>{}</td><td>{}</td></tr>\n",
+                    escape_html(&command.title),
+                    escape_html(system_name),
+                    shortcut,
+                    description
+                );
+            }
+            html += "</table>\n";
+        }
+
+        html += "</body>\n</html>\n";
+        return html;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CommandBuilder, CommandParam};
+    use super::*;
+
+    fn test_commands() -> CommandMap<f32> {
+        let mut commands: CommandMap<f32> = CommandMap::new();
+        CommandBuilder::new()
+            .title("Scale")
+            .system_name("scale")
+            .docs("Start scaling <selection>.")
+            .category("Object")
+            .shortcut("S")
+            .param(CommandParam::new("factor", "f32", "Scale factor").default(2.0).range(0.0, 10.0))
+            .write(&mut commands).unwrap();
+        CommandBuilder::new()
+            .title("Dump tree")
+            .system_name("dump-tree")
+            .docs("Print data | for debugging.")
+            .write(&mut commands).unwrap();
+        return commands;
+    }
+
+    #[test]
+    fn it_writes_markdown_cheat_sheets() {
+        let commands = test_commands();
+        let mut keymap = Keymap::from_commands(&commands);
+        keymap.set_override("scale", "Shift+S");
+
+        let markdown = commands.cheat_sheet_markdown(&keymap);
+        assert!(markdown.contains("## Object\n"));
+        assert!(markdown.contains("| **Scale** `scale` | `Shift+S` | Start scaling <selection>.<br>`factor (f32, 0 to 10, optional): Scale factor` |"));
+        // Commands without category
+        assert!(markdown.contains("## Other\n"));
+        assert!(markdown.contains("| **Dump tree** `dump-tree` |  | Print data \\| for debugging. |"));
+    }
+
+    #[test]
+    fn it_writes_html_cheat_sheets() {
+        let commands = test_commands();
+        let keymap = Keymap::from_commands(&commands);
+
+        let html = commands.cheat_sheet_html(&keymap);
+        assert!(html.contains("<h2>Object</h2>"));
+        assert!(html.contains("<kbd>S</kbd>"));
+        assert!(html.contains("Start scaling &lt;selection&gt;."));
+    }
+}
@@ -0,0 +1,417 @@
+//! Keymap: which shortcut runs which command.
+//!
+//! Commands declare a default shortcut. Users can override it per `system_name`:
+//! rebind a command, unbind it (empty shortcut) or bind a command that has no default shortcut.
+//!
+//! A shortcut bound to more than one command is a conflict. Conflicts are reported, and the
+//! shortcut only runs a command if exactly one of them was bound by the user.
+//!
+//! Shortcuts are sequences of chords separated by spaces, like `G X` or `Ctrl+K Ctrl+S`.
+//! A chord is a key with optional modifiers: `Ctrl`, `Alt`, `Shift` and `Cmd` (Super on other platforms).
+//! `match_sequence` tells whether the chords typed so far run a command or start a longer shortcut.
+//!
+//! ```
+//! use std::collections::BTreeMap;
+//! use command_central::{Keymap, KeymapError, ShortcutMatch};
+//!
+//! let mut keymap = Keymap::new();
+//! keymap.set_default("grab", "G");
+//! keymap.set_default("scale", "S");
+//!
+//! let overrides = BTreeMap::from([("scale".to_string(), "shift+g".to_string())]);
+//! assert!(keymap.load_overrides(overrides).is_empty());
+//! assert_eq!(keymap.command_for("Shift+G"), Some("scale"));
+//!
+//! keymap.set_override("scale", "G");
+//! assert_eq!(keymap.conflicts(), vec!(KeymapError::Conflict {
+//!     shortcut: "G".to_string(),
+//!     system_names: vec!("grab".to_string(), "scale".to_string()),
+//! }));
+//! // The user's binding wins
+//! assert_eq!(keymap.command_for("G"), Some("scale"));
+//!
+//! keymap.set_override("save", "ctrl+k ctrl+s");
+//! let chords = vec!("Ctrl+K".to_string());
+//! assert_eq!(keymap.match_sequence(&chords), ShortcutMatch::Pending { command: None });
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::CommandMap;
+
+/// Modifiers, in the order they are written in shortcuts.
+const MODIFIERS: [&str; 4] = ["Ctrl", "Alt", "Shift", "Cmd"];
+/// Other names of modifiers, accepted in shortcuts.
+const MODIFIER_ALIASES: [(&str, &str); 6] = [
+    ("Control", "Ctrl"),
+    ("Option", "Alt"),
+    ("Command", "Cmd"),
+    ("Super", "Cmd"),
+    ("Meta", "Cmd"),
+    ("⌘", "Cmd"),
+];
+
+/// What the chords typed so far mean.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShortcutMatch<'a> {
+    /// No shortcut starts with these chords.
+    None,
+    /// Run this command.
+    Command(&'a str),
+    /// Longer shortcuts start with these chords. If no other chord is typed,
+    /// `command` is the command bound to the chords typed so far.
+    Pending { command: Option<&'a str> },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeymapError {
+    UnknownCommand(String),
+    Conflict { shortcut: String, system_names: Vec<String> },
+}
+
+impl std::fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeymapError::UnknownCommand(system_name) => write!(f, "Unknown command {} in keymap.", system_name),
+            KeymapError::Conflict { shortcut, system_names } => {
+                write!(f, "{} is bound to several commands: {}.", shortcut, system_names.join(", "))
+            },
+        }
+    }
+}
+
+/// Writes shortcuts the same way, so "shift+d" and "Shift+D" are the same shortcut.
+/// Chords are separated by one space, modifiers are sorted and capitalized, single letter keys are uppercase.
+pub fn normalize_shortcut(shortcut: &str) -> String {
+    return shortcut.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        // Spaces around "+" are not chord separators
+        .replace(" +", "+")
+        .replace("+ ", "+")
+        .split(" ")
+        .map(normalize_chord)
+        .filter(|chord| !chord.is_empty())
+        .collect::<Vec<String>>()
+        .join(" ");
+}
+
+fn normalize_chord(chord: &str) -> String {
+    let mut modifiers: Vec<&str> = Vec::new();
+    let mut key = String::new();
+
+    for part in chord.split("+").map(|part| part.trim()).filter(|part| !part.is_empty()) {
+        let modifier = MODIFIERS.iter()
+            .find(|modifier| modifier.eq_ignore_ascii_case(part))
+            .copied()
+            .or_else(|| MODIFIER_ALIASES.iter()
+                 .find(|(alias, _)| alias.eq_ignore_ascii_case(part))
+                 .map(|(_, modifier)| *modifier));
+
+        match modifier {
+            Some(modifier) => modifiers.push(modifier),
+            None => {
+                let mut chars = part.chars();
+                key = match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                };
+            }
+        }
+    }
+
+    let mut parts: Vec<String> = MODIFIERS.iter()
+        .filter(|modifier| modifiers.contains(modifier))
+        .map(|modifier| modifier.to_string())
+        .collect();
+    if !key.is_empty() {
+        parts.push(key);
+    }
+    return parts.join("+");
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Keymap {
+    /// Shortcuts declared by commands, by system name.
+    defaults: BTreeMap<String, String>,
+    /// Shortcuts chosen by the user, by system name. Empty shortcuts unbind commands.
+    overrides: BTreeMap<String, String>,
+    /// `CommandMap::generation` when defaults were last updated.
+    synced_generation: Option<u64>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Keymap with the default shortcuts of commands.
+    pub fn from_commands<ParamType: Clone, ContextType>(commands: &CommandMap<ParamType, ContextType>) -> Self {
+        let mut keymap = Self::new();
+        keymap.update_defaults(commands);
+        return keymap;
+    }
+
+    pub fn set_default(&mut self, system_name: &str, shortcut: &str) {
+        self.defaults.insert(system_name.to_string(), normalize_shortcut(shortcut));
+    }
+
+    /// Forgets the default shortcut of an unregistered command.
+    /// The user's override is kept, in case the command is registered again.
+    pub fn remove_default(&mut self, system_name: &str) {
+        self.defaults.remove(system_name);
+    }
+
+    /// Updates default shortcuts after commands were registered, overridden or unregistered.
+    /// Does nothing if commands didn't change since the last update, so it can run every frame.
+    pub fn update_defaults<ParamType: Clone, ContextType>(&mut self, commands: &CommandMap<ParamType, ContextType>) {
+        if self.synced_generation == Some(commands.generation()) {
+            return;
+        }
+        self.synced_generation = Some(commands.generation());
+
+        let removed: Vec<String> = self.defaults.keys()
+            .filter(|system_name| !commands.commands.contains_key(*system_name))
+            .cloned()
+            .collect();
+        for system_name in removed.iter() {
+            self.remove_default(system_name);
+        }
+        for (system_name, command) in commands.commands.iter() {
+            let shortcut = normalize_shortcut(&command.shortcut);
+            if self.defaults.get(system_name) != Some(&shortcut) {
+                self.defaults.insert(system_name.clone(), shortcut);
+            }
+        }
+    }
+
+    /// Binds a shortcut chosen by the user. An empty shortcut unbinds the command.
+    pub fn set_override(&mut self, system_name: &str, shortcut: &str) {
+        self.overrides.insert(system_name.to_string(), normalize_shortcut(shortcut));
+    }
+
+    /// Goes back to the default shortcut.
+    pub fn reset(&mut self, system_name: &str) {
+        self.overrides.remove(system_name);
+    }
+
+    pub fn overrides(&self) -> &BTreeMap<String, String> {
+        return &self.overrides;
+    }
+
+    /// Replaces the user's shortcuts, for example with the content of a config file.
+    /// Returns problems to report: overrides of commands that don't exist, and conflicts.
+    /// Overrides of unknown commands are kept, since the command may be registered later.
+    pub fn load_overrides(&mut self, overrides: BTreeMap<String, String>) -> Vec<KeymapError> {
+        self.overrides.clear();
+        let mut errors: Vec<KeymapError> = Vec::new();
+
+        for (system_name, shortcut) in overrides.iter() {
+            if !self.defaults.contains_key(system_name) {
+                errors.push(KeymapError::UnknownCommand(system_name.clone()));
+            }
+            self.set_override(system_name, shortcut);
+        }
+
+        errors.extend(self.conflicts());
+        return errors;
+    }
+
+    /// Shortcut of a command, if it has one.
+    pub fn shortcut(&self, system_name: &str) -> Option<&str> {
+        let shortcut = match self.overrides.get(system_name) {
+            Some(shortcut) => shortcut,
+            None => self.defaults.get(system_name)?
+        };
+        return match shortcut.is_empty() {
+            true => None,
+            false => Some(shortcut.as_str())
+        };
+    }
+
+    /// Shortcut declared by the command, ignoring the user's changes.
+    pub fn default_shortcut(&self, system_name: &str) -> Option<&str> {
+        return self.defaults.get(system_name)
+            .filter(|shortcut| !shortcut.is_empty())
+            .map(|shortcut| shortcut.as_str());
+    }
+
+    pub fn is_overridden(&self, system_name: &str) -> bool {
+        return self.overrides.contains_key(system_name);
+    }
+
+    /// Shortcuts of every bound command, by system name.
+    pub fn bindings(&self) -> BTreeMap<&str, &str> {
+        return self.defaults.keys()
+            .chain(self.overrides.keys())
+            .filter_map(|system_name| Some((system_name.as_str(), self.shortcut(system_name)?)))
+            .collect();
+    }
+
+    /// Commands bound to a shortcut, in system name order.
+    pub fn commands_bound_to(&self, shortcut: &str) -> Vec<&str> {
+        let shortcut = normalize_shortcut(shortcut);
+        return self.bindings().into_iter()
+            .filter(|(_, bound_shortcut)| *bound_shortcut == shortcut)
+            .map(|(system_name, _)| system_name)
+            .collect();
+    }
+
+    /// Command to run for a shortcut.
+    /// When the shortcut is in conflict, only a command bound by the user can run.
+    pub fn command_for(&self, shortcut: &str) -> Option<&str> {
+        let system_names = self.commands_bound_to(shortcut);
+        if system_names.len() == 1 {
+            return Some(system_names[0]);
+        }
+
+        let user_bound: Vec<&str> = system_names.into_iter()
+            .filter(|system_name| self.is_overridden(system_name))
+            .collect();
+        return match user_bound.len() {
+            1 => Some(user_bound[0]),
+            _ => None
+        };
+    }
+
+    /// Finds what the chords typed so far run.
+    pub fn match_sequence(&self, chords: &[String]) -> ShortcutMatch<'_> {
+        let sequence = normalize_shortcut(&chords.join(" "));
+        if sequence.is_empty() {
+            return ShortcutMatch::None;
+        }
+
+        let longer_prefix = format!("{} ", sequence);
+        let command = self.command_for(&sequence);
+        let is_prefix = self.bindings().values().any(|shortcut| shortcut.starts_with(&longer_prefix));
+
+        return match (is_prefix, command) {
+            (true, command) => ShortcutMatch::Pending { command },
+            (false, Some(command)) => ShortcutMatch::Command(command),
+            (false, None) => ShortcutMatch::None,
+        };
+    }
+
+    /// Shortcuts bound to several commands.
+    pub fn conflicts(&self) -> Vec<KeymapError> {
+        let mut commands_by_shortcut: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (system_name, shortcut) in self.bindings() {
+            commands_by_shortcut.entry(shortcut).or_default().push(system_name.to_string());
+        }
+
+        return commands_by_shortcut.into_iter()
+            .filter(|(_, system_names)| system_names.len() > 1)
+            .map(|(shortcut, system_names)| KeymapError::Conflict { shortcut: shortcut.to_string(), system_names })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandBuilder;
+
+    fn default_keymap() -> Keymap {
+        let mut keymap = Keymap::new();
+        keymap.set_default("grab", "G");
+        keymap.set_default("duplicate", "Shift+D");
+        keymap.set_default("dump-tree", "");
+        return keymap;
+    }
+
+    #[test]
+    fn it_normalizes_shortcuts() {
+        assert_eq!(normalize_shortcut("shift+d"), "Shift+D");
+        assert_eq!(normalize_shortcut("Shift + ctrl + z"), "Ctrl+Shift+Z");
+        assert_eq!(normalize_shortcut("escape"), "Escape");
+        assert_eq!(normalize_shortcut(""), "");
+        assert_eq!(normalize_shortcut("control+k  super+s"), "Ctrl+K Cmd+S");
+        assert_eq!(normalize_shortcut("g x"), "G X");
+        assert_eq!(normalize_shortcut("Cmd + Shift + D"), "Shift+Cmd+D");
+    }
+
+    #[test]
+    fn it_matches_sequences() {
+        let mut keymap = default_keymap();
+        keymap.set_default("grab-x", "G X");
+        keymap.set_default("save", "Ctrl+K Ctrl+S");
+
+        let chords = |chords: &[&str]| -> Vec<String> { chords.iter().map(|chord| chord.to_string()).collect() };
+
+        assert_eq!(keymap.match_sequence(&chords(&["G"])), ShortcutMatch::Pending { command: Some("grab") });
+        assert_eq!(keymap.match_sequence(&chords(&["G", "X"])), ShortcutMatch::Command("grab-x"));
+        assert_eq!(keymap.match_sequence(&chords(&["Ctrl+K"])), ShortcutMatch::Pending { command: None });
+        assert_eq!(keymap.match_sequence(&chords(&["Ctrl+K", "Ctrl+S"])), ShortcutMatch::Command("save"));
+        assert_eq!(keymap.match_sequence(&chords(&["Ctrl+K", "S"])), ShortcutMatch::None);
+        assert_eq!(keymap.match_sequence(&chords(&["Shift+D"])), ShortcutMatch::Command("duplicate"));
+    }
+
+    #[test]
+    fn it_rebinds_unbinds_and_adds_shortcuts() {
+        let mut keymap = default_keymap();
+        let errors = keymap.load_overrides(BTreeMap::from([
+            ("grab".to_string(), "M".to_string()),
+            ("duplicate".to_string(), "".to_string()),
+            ("dump-tree".to_string(), "ctrl+t".to_string()),
+        ]));
+
+        assert!(errors.is_empty());
+        assert_eq!(keymap.shortcut("grab"), Some("M"));
+        assert_eq!(keymap.shortcut("duplicate"), None);
+        assert_eq!(keymap.command_for("Ctrl+T"), Some("dump-tree"));
+        assert_eq!(keymap.command_for("G"), None);
+
+        keymap.reset("grab");
+        assert_eq!(keymap.command_for("G"), Some("grab"));
+    }
+
+    #[test]
+    fn it_follows_command_changes() {
+        let mut commands: CommandMap<f32> = CommandMap::new();
+        CommandBuilder::new().system_name("grab").shortcut("G").write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("duplicate").shortcut("Shift+D").write(&mut commands).unwrap();
+        let mut keymap = Keymap::from_commands(&commands);
+        keymap.set_override("duplicate", "Ctrl+D");
+
+        commands.unregister_command("grab").unwrap();
+        CommandBuilder::new().system_name("duplicate").shortcut("D").write_override(&mut commands).unwrap();
+        CommandBuilder::new().system_name("grab-x").shortcut("G").write(&mut commands).unwrap();
+        keymap.update_defaults(&commands);
+
+        assert_eq!(keymap.command_for("G"), Some("grab-x"));
+        assert_eq!(keymap.shortcut("grab"), None);
+        assert_eq!(keymap.default_shortcut("duplicate"), Some("D"));
+        // The user's choice still wins
+        assert_eq!(keymap.shortcut("duplicate"), Some("Ctrl+D"));
+        assert!(keymap.conflicts().is_empty());
+
+        keymap.remove_default("grab-x");
+        assert_eq!(keymap.command_for("G"), None);
+
+        // Unchanged commands are not synced again
+        keymap.update_defaults(&commands);
+        assert_eq!(keymap.command_for("G"), None);
+        commands.unregister_namespace("");
+        keymap.update_defaults(&commands);
+        assert_eq!(keymap.default_shortcut("duplicate"), None);
+    }
+
+    #[test]
+    fn it_reports_conflicts_and_unknown_commands() {
+        let mut keymap = default_keymap();
+        keymap.set_default("other-grab", "G");
+        let errors = keymap.load_overrides(BTreeMap::from([
+            ("missing".to_string(), "Q".to_string()),
+        ]));
+
+        assert_eq!(errors, vec!(
+            KeymapError::UnknownCommand("missing".to_string()),
+            KeymapError::Conflict {
+                shortcut: "G".to_string(),
+                system_names: vec!("grab".to_string(), "other-grab".to_string()),
+            },
+        ));
+        // Neither default wins
+        assert_eq!(keymap.command_for("G"), None);
+    }
+}
@@ -2,103 +2,392 @@
 //! Command Central
 //!
 //! The idea of command central is that any function in an app, every button is tied to a command.
-//! Each command is documented, potentially reusable in scripts.
+//! Each command is documented, and reusable in scripts: commands are run by name, with named arguments.
+//!
+//! Commands declare typed parameters (name, type, docs, default, range) and an optional return value.
+//! `CommandMap::run` checks arguments against these declarations before calling the callback.
+//! Callbacks are plain functions, or closures when they need to capture state.
+//!
+//! Commands can declare conditions, like "something is selected". A command is only available
+//! when all its conditions hold: `run` refuses to run it otherwise, and says why.
+//!
+//! Commands are searched fuzzily, and commands used often or recently rank higher.
+//!
+//! Commands declare whether they change the document, so apps can make an undo step for each command
+//! that does, and none for the others (like commands that only show something).
+//!
+//! Commands declare a category and where they appear in menus, so apps can generate their menus
+//! and a cheat sheet of every command.
+//!
+//! Commands declare a default shortcut. A `Keymap` lets users change shortcuts and detects conflicts.
+//!
+//! Each system name is registered once: adding a command twice is an error, not a panic.
+//! Commands can be replaced or removed later. Plugins put their commands in a namespace,
+//! like `teapots:spawn-teapot`, so they don't clash with the app's commands or with each other.
+//!
+//! Commands are generic over two types:
+//!  - `ParamType`: the value type of parameters and return values. It implements `ParamValue`.
+//!  - `ContextType`: what callbacks act on, for example the app's data.
 //!
 //! This implementation is a pretty early and inefficient version, but it should help getting started.
 //! Later, it would be good to:
 //!  - Find an efficient way to notify commands that does not require checking all of commands.
-//!  - Accept parameters and return values
-//!  - Make it scriptable
 //!
+//! ```
+//! use command_central::{CommandMap, CommandBuilder, CommandParam, CommandArgs};
+//!
+//! fn scale(total: &mut f32, args: &CommandArgs<f32>) -> Option<f32> {
+//!     *total *= args["factor"];
+//!     return Some(*total);
+//! }
+//!
+//! let mut commands: CommandMap<f32, f32> = CommandMap::new();
+//! CommandBuilder::new()
+//!     .title("Scale")
+//!     .system_name("scale")
+//!     .docs("Multiplies the total")
+//!     .param(CommandParam::new("factor", "f32", "Scale factor").default(2.0).range(0.0, 10.0))
+//!     .returns("f32", "New total")
+//!     .callback_with_args(scale)
+//!     .write(&mut commands).unwrap();
+//!
+//! let mut total = 1.0;
+//! // Default arguments
+//! assert_eq!(commands.run("scale", &mut total, &CommandArgs::new()), Ok(Some(2.0)));
+//! // Out of range
+//! let args = CommandArgs::from([("factor".to_string(), 20.0)]);
+//! assert!(commands.run("scale", &mut total, &args).is_err());
+//! ```
 
 // We want a version of HashMap that is ordered by key. Turns our BTreeMap is ordered by key!
 // So, using BTreeMap avoids order constantly flickering, example: when searching.
 use std::collections::BTreeMap;
+use std::sync::Arc;
+
+mod keymap;
+pub use keymap::*;
+mod search;
+pub use search::*;
+mod menus;
+pub use menus::*;
+mod cheat_sheet;
+mod namespaces;
+pub use namespaces::*;
+
+pub type CommandInfoMap<ParamType, ContextType = ()> = BTreeMap<String, CommandInfo<ParamType, ContextType>>;
+/// Arguments of a command, by parameter name.
+pub type CommandArgs<ParamType> = BTreeMap<String, ParamType>;
+
+/// Values that can be passed to commands and returned by them.
+pub trait ParamValue: Clone {
+    /// Name of the type of this value, compared with the type declared by parameters.
+    fn type_name(&self) -> &'static str;
+    /// Numeric components of the value, checked against parameter ranges.
+    /// Empty for non-numeric values.
+    fn numeric_components(&self) -> Vec<f32> {
+        return Vec::new();
+    }
+}
 
-pub type CommandInfoMap<ParamType> = BTreeMap<String, CommandInfo<ParamType>>;
-pub type CommandParamMap<ParamType> = BTreeMap<String, CommandParam<ParamType>>;
+impl ParamValue for f32 {
+    fn type_name(&self) -> &'static str { "f32" }
+    fn numeric_components(&self) -> Vec<f32> { vec!(*self) }
+}
 
-#[derive(Clone, Default)]
-pub struct CommandMap<ParamType: Clone> {
-    pub commands: CommandInfoMap<ParamType>,
+impl ParamValue for i32 {
+    fn type_name(&self) -> &'static str { "i32" }
+    fn numeric_components(&self) -> Vec<f32> { vec!(*self as f32) }
+}
+
+impl ParamValue for bool {
+    fn type_name(&self) -> &'static str { "bool" }
+}
+
+impl ParamValue for String {
+    fn type_name(&self) -> &'static str { "String" }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandError {
+    NotFound(String),
+    /// A command with this system name is already registered.
+    AlreadyDefined(String),
+    NoCallback(String),
+    MissingParam(String),
+    UnknownParam(String),
+    WrongParamType { param: String, expected: String, found: String },
+    OutOfRange { param: String, min: f32, max: f32 },
+    /// A condition of the command does not hold. The reason comes from the condition.
+    Unavailable { system_name: String, reason: String },
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::NotFound(name) => write!(f, "Command {} not found.", name),
+            CommandError::AlreadyDefined(name) => write!(f, "Command {} already defined.", name),
+            CommandError::NoCallback(name) => write!(f, "Command {} has no callback.", name),
+            CommandError::MissingParam(param) => write!(f, "Missing parameter {}.", param),
+            CommandError::UnknownParam(param) => write!(f, "Unknown parameter {}.", param),
+            CommandError::WrongParamType { param, expected, found } => {
+                write!(f, "Parameter {} should be {}, found {}.", param, expected, found)
+            },
+            CommandError::OutOfRange { param, min, max } => {
+                write!(f, "Parameter {} should be between {} and {}.", param, min, max)
+            },
+            CommandError::Unavailable { system_name, reason } => {
+                write!(f, "Command {} is not available: {}.", system_name, reason)
+            },
+        }
+    }
+}
+
+/// Function called when a command runs.
+pub enum CommandCallback<ParamType, ContextType> {
+    /// For commands without parameters or return value.
+    Simple(fn(&mut ContextType)),
+    /// Receives the arguments, with defaults filled in. Returns the command's return value.
+    WithArgs(fn(&mut ContextType, &CommandArgs<ParamType>) -> Option<ParamType>),
+    /// Like `WithArgs`, but can capture state.
+    Closure(Arc<CommandClosure<ParamType, ContextType>>),
+}
+
+pub type CommandClosure<ParamType, ContextType> =
+    dyn Fn(&mut ContextType, &CommandArgs<ParamType>) -> Option<ParamType> + Send + Sync;
+
+// Derived Clone would require ParamType: Clone and ContextType: Clone
+impl<ParamType, ContextType> Clone for CommandCallback<ParamType, ContextType> {
+    fn clone(&self) -> Self {
+        return match self {
+            Self::Simple(callback) => Self::Simple(*callback),
+            Self::WithArgs(callback) => Self::WithArgs(*callback),
+            Self::Closure(callback) => Self::Closure(callback.clone()),
+        };
+    }
+}
+
+pub type CommandPredicate<ContextType> = dyn Fn(&ContextType) -> bool + Send + Sync;
+
+/// When a command can run, for example "the selection is not empty".
+pub struct CommandCondition<ContextType> {
+    /// Shown when the condition does not hold, like "Nothing is selected".
+    pub reason: String,
+    pub predicate: Arc<CommandPredicate<ContextType>>,
+}
+
+// Derived Clone would require ContextType: Clone
+impl<ContextType> Clone for CommandCondition<ContextType> {
+    fn clone(&self) -> Self {
+        return Self {
+            reason: self.reason.clone(),
+            predicate: self.predicate.clone(),
+        };
+    }
+}
+
+pub struct CommandMap<ParamType: Clone, ContextType = ()> {
+    pub commands: CommandInfoMap<ParamType, ContextType>,
+    /// Bumped when commands are added, overridden or unregistered. See `generation`.
+    generation: u64,
+}
+
+// Derived Clone and Default would require ContextType: Clone + Default
+impl<ParamType: Clone, ContextType> Clone for CommandMap<ParamType, ContextType> {
+    fn clone(&self) -> Self {
+        return Self { commands: self.commands.clone(), generation: self.generation };
+    }
+}
+
+impl<ParamType: Clone, ContextType> Default for CommandMap<ParamType, ContextType> {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 
-impl<ParamType: Clone> CommandMap<ParamType> {
+impl<ParamType: Clone, ContextType> CommandMap<ParamType, ContextType> {
     pub fn new() -> Self {
         Self {
-            commands: CommandInfoMap::new()
+            commands: CommandInfoMap::new(),
+            generation: 0,
         }
     }
 
-    pub fn add_command(&mut self, system_name: &String, command: CommandInfo<ParamType>) {
+    /// Changes when commands are added, overridden or unregistered, so users like the keymap
+    /// only follow changes when there are some. Changes made to `commands` directly don't count.
+    pub fn generation(&self) -> u64 {
+        return self.generation;
+    }
+
+    /// Registers a command. Fails if the system name is taken: use `override_command` to replace a command.
+    pub fn add_command(&mut self, system_name: &String, command: CommandInfo<ParamType, ContextType>) -> Result<(), CommandError> {
         if self.commands.contains_key(system_name) {
-            panic!("Command {} already defined.", system_name);
+            return Err(CommandError::AlreadyDefined(system_name.clone()));
         }
 
         self.commands.insert(system_name.clone(), command);
+        self.generation += 1;
+        return Ok(());
+    }
+
+    /// Replaces a registered command. Returns the replaced command.
+    pub fn override_command(
+        &mut self,
+        system_name: &str,
+        command: CommandInfo<ParamType, ContextType>
+    ) -> Result<CommandInfo<ParamType, ContextType>, CommandError> {
+        let existing_command = match self.commands.get_mut(system_name) {
+            Some(existing_command) => existing_command,
+            None => { return Err(CommandError::NotFound(system_name.to_string())); }
+        };
+        let replaced_command = std::mem::replace(existing_command, command);
+        self.generation += 1;
+        return Ok(replaced_command);
+    }
+
+    /// Removes a command. Returns the removed command.
+    pub fn unregister_command(&mut self, system_name: &str) -> Result<CommandInfo<ParamType, ContextType>, CommandError> {
+        let removed_command = self.commands.remove(system_name)
+            .ok_or(CommandError::NotFound(system_name.to_string()))?;
+        self.generation += 1;
+        return Ok(removed_command);
     }
 
     /// Returns a copy of the command
-    pub fn read_command(&mut self, system_name: &String) -> Option<CommandInfo<ParamType>> {
+    pub fn read_command(&mut self, system_name: &String) -> Option<CommandInfo<ParamType, ContextType>> {
         return self.commands.get(system_name).cloned();
     }
 
-    /// Search through commands
-    pub fn search(&mut self, search: &String, limit: usize) -> CommandInfoMap<ParamType> {
-        let search_lower = search.to_lowercase();
-        let mut results: CommandInfoMap<ParamType> = CommandInfoMap::new();
-        for command in self.commands.iter() {
-            let system_name = command.0;
-            let command = command.1;
-
-            if system_name.to_lowercase().contains(&search_lower) ||
-                command.title.to_lowercase().contains(&search_lower) ||
-                command.docs.to_lowercase().contains(&search_lower) {
-                    results.insert(system_name.to_string(), command.clone());
-                }
+    /// Commands matching `search`, fuzzily, best match first.
+    /// Use `rank` to also boost frequently used commands.
+    pub fn search(&mut self, search: &String, limit: usize) -> Vec<(String, CommandInfo<ParamType, ContextType>)> {
+        return self.rank(search, &CommandUsage::default(), limit).into_iter()
+            .map(|system_name| {
+                let command = self.commands[&system_name].clone();
+                return (system_name, command);
+            })
+            .collect();
+    }
+}
 
-            if results.len() == limit {
-                break;
-            }
-        }
-        return results;
+impl<ParamType: ParamValue, ContextType> CommandMap<ParamType, ContextType> {
+    /// Checks conditions and arguments, then runs the command's callback.
+    /// Returns the command's return value.
+    pub fn run(
+        &self,
+        system_name: &str,
+        context: &mut ContextType,
+        args: &CommandArgs<ParamType>
+    ) -> Result<Option<ParamType>, CommandError> {
+        return match self.commands.get(system_name) {
+            Some(command) => command.run(system_name, context, args),
+            None => Err(CommandError::NotFound(system_name.to_string())),
+        };
     }
 }
 
 #[derive(Clone)]
 pub struct CommandParam<ParamType: Clone> {
+    pub name: String,
+    /// Expected `ParamValue::type_name` of arguments.
+    pub param_type: String,
     pub docs: String,
+    /// Used when the argument is not given.
     pub default: Option<ParamType>,
-    pub value: Option<ParamType>
+    /// Optional parameters without default are left out of the arguments when not given.
+    pub optional: bool,
+    /// Inclusive range of every numeric component of the argument.
+    pub range: Option<(f32, f32)>,
 }
 
-impl<ParamType: Clone> Default for CommandParam<ParamType> {
-    fn default() -> Self {
+impl<ParamType: Clone> CommandParam<ParamType> {
+    pub fn new(name: &str, param_type: &str, docs: &str) -> Self {
         return Self {
-            docs: "".to_string(),
+            name: name.to_string(),
+            param_type: param_type.to_string(),
+            docs: docs.to_string(),
             default: None,
-            value: None
+            optional: false,
+            range: None,
         };
     }
+
+    pub fn default(mut self, default: ParamType) -> Self {
+        self.default = Some(default);
+        return self;
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        return self;
+    }
+
+    pub fn range(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min, max));
+        return self;
+    }
 }
 
-#[derive(Clone)]
-pub struct CommandBuilder<ParamType: Clone> {
-    pub command_param_map: CommandParamMap<ParamType>,
+impl<ParamType: ParamValue> CommandParam<ParamType> {
+    /// Checks the type and range of an argument.
+    pub fn validate(&self, value: &ParamType) -> Result<(), CommandError> {
+        if value.type_name() != self.param_type {
+            return Err(CommandError::WrongParamType {
+                param: self.name.clone(),
+                expected: self.param_type.clone(),
+                found: value.type_name().to_string(),
+            });
+        }
+
+        if let Some((min, max)) = self.range {
+            if value.numeric_components().iter().any(|component| *component < min || *component > max) {
+                return Err(CommandError::OutOfRange { param: self.name.clone(), min, max });
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Declared return value of a command.
+#[derive(Clone, Default)]
+pub struct CommandReturn {
+    pub return_type: String,
+    pub docs: String,
+}
+
+pub struct CommandBuilder<ParamType: Clone, ContextType = ()> {
+    pub parameters: Vec<CommandParam<ParamType>>,
     pub system_name: String,
+    /// Prefix of the system name, for commands of plugins. See `namespaced_name`.
+    pub namespace: String,
     pub title: String,
     pub docs: String,
     pub shortcut: String,
+    pub returns: Option<CommandReturn>,
+    pub callback: Option<CommandCallback<ParamType, ContextType>>,
+    pub conditions: Vec<CommandCondition<ContextType>>,
+    pub category: String,
+    pub menu: Option<MenuPlacement>,
+    pub checked: Option<Arc<CommandPredicate<ContextType>>>,
+    pub mutates_document: bool,
 }
 
-impl<ParamType: Clone> CommandBuilder<ParamType> {
+impl<ParamType: Clone, ContextType> CommandBuilder<ParamType, ContextType> {
     pub fn new() -> Self {
         return Self {
             system_name: "".to_string(),
+            namespace: "".to_string(),
             title: "".to_string(),
             docs: "".to_string(),
             shortcut: "".to_string(),
-            command_param_map: CommandParamMap::new(),
+            parameters: Vec::new(),
+            returns: None,
+            callback: None,
+            conditions: Vec::new(),
+            category: "".to_string(),
+            menu: None,
+            checked: None,
+            mutates_document: true,
         };
     }
 
@@ -107,6 +396,13 @@ impl<ParamType: Clone> CommandBuilder<ParamType> {
         return self;
     }
 
+    /// Puts the command in a namespace: `system_name("spawn-teapot")` in namespace "teapots"
+    /// registers "teapots:spawn-teapot".
+    pub fn namespace(&mut self, namespace: &str) -> &mut Self {
+        self.namespace = namespace.into();
+        return self;
+    }
+
     pub fn title(&mut self, title: &str) -> &mut Self {
         self.title = title.into();
         return self;
@@ -125,43 +421,246 @@ impl<ParamType: Clone> CommandBuilder<ParamType> {
         return self;
     }
 
-    /// Hack: we currently use a param to store callbacks.
-    pub fn insert_param(&mut self,  system_name: &str, docs: &str, default: Option<ParamType>) -> &mut Self {
-        self.command_param_map.insert(system_name.to_string(), CommandParam {
+    /// Parameters are kept in declaration order.
+    pub fn param(&mut self, param: CommandParam<ParamType>) -> &mut Self {
+        self.parameters.push(param);
+        return self;
+    }
+
+    pub fn returns(&mut self, return_type: &str, docs: &str) -> &mut Self {
+        self.returns = Some(CommandReturn {
+            return_type: return_type.to_string(),
             docs: docs.to_string(),
-            default: default.clone(),
-            value: default,
-            ..CommandParam::default()
         });
         return self;
     }
 
-    pub fn write(&mut self, commands: &mut CommandMap<ParamType>) {
-        commands.add_command(&self.system_name, CommandInfo {
+    pub fn callback(&mut self, callback: fn(&mut ContextType)) -> &mut Self {
+        self.callback = Some(CommandCallback::Simple(callback));
+        return self;
+    }
+
+    pub fn callback_with_args(
+        &mut self,
+        callback: fn(&mut ContextType, &CommandArgs<ParamType>) -> Option<ParamType>
+    ) -> &mut Self {
+        self.callback = Some(CommandCallback::WithArgs(callback));
+        return self;
+    }
+
+    /// Closures can capture state, for example a handle to something the context does not hold.
+    pub fn closure(
+        &mut self,
+        callback: impl Fn(&mut ContextType, &CommandArgs<ParamType>) -> Option<ParamType> + Send + Sync + 'static
+    ) -> &mut Self {
+        self.callback = Some(CommandCallback::Closure(Arc::new(callback)));
+        return self;
+    }
+
+    /// The command is only available when `predicate` holds. `reason` tells why it is not.
+    /// Commands can have several conditions.
+    pub fn when(
+        &mut self,
+        reason: &str,
+        predicate: impl Fn(&ContextType) -> bool + Send + Sync + 'static
+    ) -> &mut Self {
+        self.conditions.push(CommandCondition {
+            reason: reason.to_string(),
+            predicate: Arc::new(predicate),
+        });
+        return self;
+    }
+
+    /// Group of related commands, like "File" or "Object". Used to sort the cheat sheet.
+    pub fn category(&mut self, category: &str) -> &mut Self {
+        self.category = category.to_string();
+        return self;
+    }
+
+    /// Shows the command in menus. `path` is made of menu names and ends with the label of the item:
+    /// "File/Save" or "Script/Macros/Play macro". See `MenuPlacement` for `order`.
+    pub fn menu(&mut self, path: &str, order: i32) -> &mut Self {
+        self.menu = Some(MenuPlacement {
+            path: path.to_string(),
+            order,
+        });
+        return self;
+    }
+
+    /// For commands that toggle something: menus show a checkbox, checked when `predicate` holds.
+    pub fn checked(&mut self, predicate: impl Fn(&ContextType) -> bool + Send + Sync + 'static) -> &mut Self {
+        self.checked = Some(Arc::new(predicate));
+        return self;
+    }
+
+    /// Commands change the document by default. Commands that don't, like commands that only show
+    /// something or change settings, get no undo step.
+    pub fn mutates_document(&mut self, mutates_document: bool) -> &mut Self {
+        self.mutates_document = mutates_document;
+        return self;
+    }
+
+    /// System name, with namespace.
+    pub fn full_system_name(&self) -> String {
+        return match self.namespace.is_empty() {
+            true => self.system_name.clone(),
+            false => namespaced_name(&self.namespace, &self.system_name),
+        };
+    }
+
+    /// Registers the command. Fails if the system name is taken.
+    #[must_use]
+    pub fn write(&mut self, commands: &mut CommandMap<ParamType, ContextType>) -> Result<(), CommandError> {
+        return commands.add_command(&self.full_system_name(), self.build());
+    }
+
+    /// Replaces the registered command with the same system name. Returns the replaced command.
+    pub fn write_override(
+        &mut self,
+        commands: &mut CommandMap<ParamType, ContextType>
+    ) -> Result<CommandInfo<ParamType, ContextType>, CommandError> {
+        return commands.override_command(&self.full_system_name(), self.build());
+    }
+
+    fn build(&self) -> CommandInfo<ParamType, ContextType> {
+        return CommandInfo {
             title: self.title.to_string(),
             docs: self.docs.to_string(),
             shortcut: self.shortcut.clone(),
-            parameters: self.command_param_map.clone(),
-            ..CommandInfo::default()
-        });
+            parameters: self.parameters.clone(),
+            returns: self.returns.clone(),
+            callback: self.callback.clone(),
+            conditions: self.conditions.clone(),
+            category: self.category.clone(),
+            menu: self.menu.clone(),
+            checked: self.checked.clone(),
+            mutates_document: self.mutates_document,
+        };
     }
 }
 
-#[derive(Clone)]
-pub struct CommandInfo<ParamType: Clone> {
+pub struct CommandInfo<ParamType: Clone, ContextType = ()> {
     pub title: String,
     pub docs: String,
     pub shortcut: String,
-    pub parameters: CommandParamMap<ParamType>,
+    pub parameters: Vec<CommandParam<ParamType>>,
+    pub returns: Option<CommandReturn>,
+    pub callback: Option<CommandCallback<ParamType, ContextType>>,
+    pub conditions: Vec<CommandCondition<ContextType>>,
+    pub category: String,
+    pub menu: Option<MenuPlacement>,
+    pub checked: Option<Arc<CommandPredicate<ContextType>>>,
+    pub mutates_document: bool,
+}
+
+impl<ParamType: Clone, ContextType> CommandInfo<ParamType, ContextType> {
+    pub fn param(&self, name: &str) -> Option<&CommandParam<ParamType>> {
+        return self.parameters.iter().find(|param| param.name == name);
+    }
+
+    /// Why the command can't run now, if it can't.
+    pub fn unavailable_reason(&self, context: &ContextType) -> Option<&str> {
+        return self.conditions.iter()
+            .find(|condition| !(condition.predicate)(context))
+            .map(|condition| condition.reason.as_str());
+    }
+
+    pub fn is_available(&self, context: &ContextType) -> bool {
+        return self.unavailable_reason(context).is_none();
+    }
+
+    /// Whether what the command toggles is on. None for commands that don't toggle anything.
+    pub fn is_checked(&self, context: &ContextType) -> Option<bool> {
+        return self.checked.as_ref().map(|predicate| predicate(context));
+    }
+}
+
+impl<ParamType: ParamValue, ContextType> CommandInfo<ParamType, ContextType> {
+    /// Validates arguments and fills in defaults.
+    pub fn resolve_args(&self, args: &CommandArgs<ParamType>) -> Result<CommandArgs<ParamType>, CommandError> {
+        for name in args.keys() {
+            if self.param(name).is_none() {
+                return Err(CommandError::UnknownParam(name.clone()));
+            }
+        }
+
+        let mut resolved_args = CommandArgs::new();
+
+        for param in self.parameters.iter() {
+            let value = match (args.get(&param.name), &param.default) {
+                (Some(value), _) => value.clone(),
+                (None, Some(default)) => default.clone(),
+                (None, None) if param.optional => { continue; },
+                (None, None) => { return Err(CommandError::MissingParam(param.name.clone())); }
+            };
+            param.validate(&value)?;
+            resolved_args.insert(param.name.clone(), value);
+        }
+
+        return Ok(resolved_args);
+    }
+
+    /// Checks conditions and arguments, then runs the callback.
+    /// Useful to run a copy of a command while the context holds the command map.
+    pub fn run(
+        &self,
+        system_name: &str,
+        context: &mut ContextType,
+        args: &CommandArgs<ParamType>
+    ) -> Result<Option<ParamType>, CommandError> {
+        if let Some(reason) = self.unavailable_reason(context) {
+            return Err(CommandError::Unavailable {
+                system_name: system_name.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+        let args = self.resolve_args(args)?;
+
+        return match &self.callback {
+            Some(CommandCallback::Simple(callback)) => {
+                callback(context);
+                Ok(None)
+            },
+            Some(CommandCallback::WithArgs(callback)) => Ok(callback(context, &args)),
+            Some(CommandCallback::Closure(callback)) => Ok(callback(context, &args)),
+            None => Err(CommandError::NoCallback(system_name.to_string())),
+        };
+    }
 }
 
-impl<ParamType: Clone> Default for CommandInfo<ParamType> {
+// Derived Clone would require ContextType: Clone
+impl<ParamType: Clone, ContextType> Clone for CommandInfo<ParamType, ContextType> {
+    fn clone(&self) -> Self {
+        return Self {
+            title: self.title.clone(),
+            docs: self.docs.clone(),
+            shortcut: self.shortcut.clone(),
+            parameters: self.parameters.clone(),
+            returns: self.returns.clone(),
+            callback: self.callback.clone(),
+            conditions: self.conditions.clone(),
+            category: self.category.clone(),
+            menu: self.menu.clone(),
+            checked: self.checked.clone(),
+            mutates_document: self.mutates_document,
+        };
+    }
+}
+
+impl<ParamType: Clone, ContextType> Default for CommandInfo<ParamType, ContextType> {
     fn default() -> Self {
         return Self {
             title: "".to_string(),
             docs: "".to_string(),
             shortcut: "".to_string(),
-            parameters: BTreeMap::new(),
+            parameters: Vec::new(),
+            returns: None,
+            callback: None,
+            conditions: Vec::new(),
+            category: "".to_string(),
+            menu: None,
+            checked: None,
+            mutates_document: true,
         };
     }
 }
@@ -177,38 +676,64 @@ mod tests {
             title: "Test Command".to_string(),
             docs: "Here are some docs about the command".to_string(),
             ..CommandInfo::default()
-        });
+        }).unwrap();
         let command = commands.read_command(&"test-command".to_string()).unwrap();
         assert_eq!(command.title, "Test Command".to_string());
+        // Commands change the document unless they say otherwise
+        assert!(command.mutates_document);
     }
 
-    // This test is useful, but it causes other tests to panic.
-    // Let's keep around for development
-    #[ignore]
     #[test]
-    #[should_panic]
     fn handles_not_found_commands() {
         let mut commands: CommandMap<f32> = CommandMap::new();
-        commands.read_command(&"not-existing-command".to_string()).unwrap();
+        assert!(commands.read_command(&"not-existing-command".to_string()).is_none());
+        assert_eq!(
+            commands.unregister_command("not-existing-command").err(),
+            Some(CommandError::NotFound("not-existing-command".to_string()))
+        );
     }
 
-    // This test is useful, but it causes other tests to panic.
-    // Let's keep around for development
-    #[ignore]
     #[test]
-    #[should_panic]
     fn it_detects_if_command_already_exists() {
         let mut commands: CommandMap<f32> = CommandMap::new();
         commands.add_command(&"test-command-duplicated".to_string(), CommandInfo {
             title: "Test Command".to_string(),
             docs: "Here are some docs about the command".to_string(),
             ..CommandInfo::default()
-        });
-        commands.add_command(&"test-command-duplicated".to_string(), CommandInfo {
-            title: "Test Command".to_string(),
+        }).unwrap();
+        let result = commands.add_command(&"test-command-duplicated".to_string(), CommandInfo {
+            title: "Other Test Command".to_string(),
             docs: "Here are some docs about the command".to_string(),
             ..CommandInfo::default()
         });
+        assert_eq!(result, Err(CommandError::AlreadyDefined("test-command-duplicated".to_string())));
+        // The first command is kept
+        assert_eq!(commands.commands["test-command-duplicated"].title, "Test Command");
+    }
+
+    #[test]
+    fn it_overrides_and_unregisters_commands() {
+        let mut commands: CommandMap<f32> = CommandMap::new();
+        CommandBuilder::new().title("Spawn sphere").system_name("spawn-sphere").write(&mut commands).unwrap();
+
+        let replaced = CommandBuilder::new()
+            .title("Spawn fancy sphere")
+            .system_name("spawn-sphere")
+            .write_override(&mut commands)
+            .unwrap();
+        assert_eq!(replaced.title, "Spawn sphere");
+        assert_eq!(commands.commands["spawn-sphere"].title, "Spawn fancy sphere");
+
+        // Only registered commands can be overridden
+        let result = CommandBuilder::new().system_name("spawn-teapot").write_override(&mut commands);
+        assert_eq!(result.err(), Some(CommandError::NotFound("spawn-teapot".to_string())));
+
+        let removed = commands.unregister_command("spawn-sphere").unwrap();
+        assert_eq!(removed.title, "Spawn fancy sphere");
+        assert!(commands.commands.is_empty());
+
+        // The name can be used again
+        assert!(CommandBuilder::new().system_name("spawn-sphere").write(&mut commands).is_ok());
     }
 
     #[test]
@@ -220,13 +745,14 @@ mod tests {
             title: "A command to search".to_string(),
             docs: "Here are some docs about the command".to_string(),
             ..CommandInfo::default()
-        });
+        }).unwrap();
 
         // Note that case is changed to check that search is case insensitive.
         let results = commands.search(&"to-SEARCH-1".to_string(), 5);
 
         assert_eq!(results.len(), 1);
-        assert_eq!(results["command-to-search-1"].title, "A command to search");
+        assert_eq!(results[0].0, "command-to-search-1");
+        assert_eq!(results[0].1.title, "A command to search");
     }
 
     #[test]
@@ -238,13 +764,14 @@ mod tests {
             title: "A command to search by title".to_string(),
             docs: "Here are some docs about the command".to_string(),
             ..CommandInfo::default()
-        });
+        }).unwrap();
 
         // Note that case is changed to check that search is case insensitive.
         let results = commands.search(&"search by TITLE".to_string(), 5);
 
         assert_eq!(results.len(), 1);
-        assert_eq!(results["command-to-search-2"].title, "A command to search by title");
+        assert_eq!(results[0].0, "command-to-search-2");
+        assert_eq!(results[0].1.title, "A command to search by title");
     }
 
     #[test]
@@ -257,12 +784,13 @@ mod tests {
             title: "A third command to search by docs".to_string(),
             docs: "Here are some docs about THIS epic COMMAND".to_string(),
             ..CommandInfo::default()
-        });
+        }).unwrap();
 
         let results = commands.search(&"THIS EPIC COMMAND".to_string(), 5);
 
         assert_eq!(results.len(), 1);
-        assert_eq!(results["command-to-search-3"].title, "A third command to search by docs");
+        assert_eq!(results[0].0, "command-to-search-3");
+        assert_eq!(results[0].1.title, "A third command to search by docs");
     }
 
     #[test]
@@ -273,24 +801,163 @@ mod tests {
         // Note that case is changed to check that search is case insensitive.
         commands.add_command(&sys_name, CommandInfo {
             ..CommandInfo::default()
-        });
+        }).unwrap();
 
         let sys_name = "command-to-search-4-B".to_string();
 
         // Note that case is changed to check that search is case insensitive.
         commands.add_command(&sys_name, CommandInfo {
             ..CommandInfo::default()
-        });
+        }).unwrap();
 
         let sys_name = "command-to-search-4-C".to_string();
 
         // Note that case is changed to check that search is case insensitive.
         commands.add_command(&sys_name, CommandInfo {
             ..CommandInfo::default()
-        });
+        }).unwrap();
 
         let results = commands.search(&"command-to-search-4".to_string(), 2);
 
         assert_eq!(results.len(), 2);
     }
+
+    #[test]
+    fn search_results_are_in_rank_order() {
+        let mut commands: CommandMap<f32> = CommandMap::new();
+        CommandBuilder::new().system_name("a-do-up").title("Do up").write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("b-delete").title("Delete").write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("z-duplicate").title("Duplicate").write(&mut commands).unwrap();
+
+        let search = "dup".to_string();
+        let expected = commands.rank(&search, &CommandUsage::default(), 5);
+        let results: Vec<String> = commands.search(&search, 5).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(results, expected);
+        // The consecutive match comes first, even though its name sorts last.
+        assert_eq!(results, vec!("z-duplicate", "a-do-up"));
+    }
+
+    fn add(total: &mut f32, args: &CommandArgs<f32>) -> Option<f32> {
+        *total += args["amount"];
+        return Some(*total);
+    }
+
+    fn reset(total: &mut f32) {
+        *total = 0.0;
+    }
+
+    fn test_commands() -> CommandMap<f32, f32> {
+        let mut commands: CommandMap<f32, f32> = CommandMap::new();
+        CommandBuilder::new()
+            .system_name("add")
+            .param(CommandParam::new("amount", "f32", "Amount to add").range(-10.0, 10.0))
+            .returns("f32", "New total")
+            .callback_with_args(add)
+            .write(&mut commands).unwrap();
+        CommandBuilder::new()
+            .system_name("reset")
+            .callback(reset)
+            .write(&mut commands).unwrap();
+        CommandBuilder::new()
+            .system_name("no-callback")
+            .write(&mut commands).unwrap();
+        return commands;
+    }
+
+    #[test]
+    fn it_runs_commands_with_arguments() {
+        let commands = test_commands();
+        let mut total = 1.0;
+        let args = CommandArgs::from([("amount".to_string(), 2.0)]);
+
+        assert_eq!(commands.run("add", &mut total, &args), Ok(Some(3.0)));
+        assert_eq!(commands.run("reset", &mut total, &CommandArgs::new()), Ok(None));
+        assert_eq!(total, 0.0);
+    }
+
+    #[test]
+    fn it_validates_arguments() {
+        let commands = test_commands();
+        let mut total = 1.0;
+
+        assert_eq!(
+            commands.run("add", &mut total, &CommandArgs::new()),
+            Err(CommandError::MissingParam("amount".to_string()))
+        );
+        assert_eq!(
+            commands.run("add", &mut total, &CommandArgs::from([("amount".to_string(), 20.0)])),
+            Err(CommandError::OutOfRange { param: "amount".to_string(), min: -10.0, max: 10.0 })
+        );
+        assert_eq!(
+            commands.run("add", &mut total, &CommandArgs::from([("other".to_string(), 1.0)])),
+            Err(CommandError::UnknownParam("other".to_string()))
+        );
+        assert_eq!(
+            commands.run("missing", &mut total, &CommandArgs::new()),
+            Err(CommandError::NotFound("missing".to_string()))
+        );
+        assert_eq!(
+            commands.run("no-callback", &mut total, &CommandArgs::new()),
+            Err(CommandError::NoCallback("no-callback".to_string()))
+        );
+        // Nothing ran
+        assert_eq!(total, 1.0);
+    }
+
+    #[test]
+    fn it_runs_closures() {
+        let mut commands = test_commands();
+        let step = 5.0;
+        CommandBuilder::new()
+            .system_name("step")
+            .closure(move |total: &mut f32, _| {
+                *total += step;
+                return Some(*total);
+            })
+            .write(&mut commands).unwrap();
+
+        let mut total = 1.0;
+        assert_eq!(commands.run("step", &mut total, &CommandArgs::new()), Ok(Some(6.0)));
+
+        // Copies of commands share the closure
+        let command = commands.read_command(&"step".to_string()).unwrap();
+        assert_eq!(command.run("step", &mut total, &CommandArgs::new()), Ok(Some(11.0)));
+    }
+
+    #[test]
+    fn it_checks_conditions() {
+        let mut commands = test_commands();
+        CommandBuilder::new()
+            .system_name("halve")
+            .when("Total is zero", |total: &f32| *total != 0.0)
+            .when("Total is negative", |total: &f32| *total > 0.0)
+            .closure(|total: &mut f32, _| {
+                *total /= 2.0;
+                return Some(*total);
+            })
+            .write(&mut commands).unwrap();
+
+        let command = commands.read_command(&"halve".to_string()).unwrap();
+        assert!(command.is_available(&4.0));
+        assert_eq!(command.unavailable_reason(&0.0), Some("Total is zero"));
+        assert_eq!(command.unavailable_reason(&-1.0), Some("Total is negative"));
+
+        let mut total = 0.0;
+        assert_eq!(commands.run("halve", &mut total, &CommandArgs::new()), Err(CommandError::Unavailable {
+            system_name: "halve".to_string(),
+            reason: "Total is zero".to_string(),
+        }));
+        total = 4.0;
+        assert_eq!(commands.run("halve", &mut total, &CommandArgs::new()), Ok(Some(2.0)));
+    }
+
+    #[test]
+    fn it_checks_parameter_types() {
+        let param: CommandParam<i32> = CommandParam::new("count", "f32", "");
+        assert_eq!(param.validate(&1), Err(CommandError::WrongParamType {
+            param: "count".to_string(),
+            expected: "f32".to_string(),
+            found: "i32".to_string(),
+        }));
+    }
 }
@@ -0,0 +1,169 @@
+//! Menus generated from commands.
+//!
+//! Commands placed with `CommandBuilder::menu` are gathered into a tree of menus, that apps
+//! draw with their UI library. Items are sorted by order. Orders in different hundreds are
+//! different groups, with a separator between them: 0, 1 and 2, then a separator, then 100.
+//!
+//! ```
+//! use command_central::{CommandMap, CommandBuilder, MenuItem};
+//!
+//! let mut commands: CommandMap<f32> = CommandMap::new();
+//! CommandBuilder::new().system_name("open").menu("File/Open", 1).write(&mut commands).unwrap();
+//! CommandBuilder::new().system_name("save").menu("File/Save", 0).write(&mut commands).unwrap();
+//! CommandBuilder::new().system_name("play-macro").menu("Script/Macros/Play", 100).write(&mut commands).unwrap();
+//!
+//! let menus = commands.menus();
+//! assert_eq!(menus[0].label, "File");
+//! assert_eq!(menus[0].items[0], MenuItem::Command { system_name: "save".to_string(), label: "Save".to_string() });
+//! assert_eq!(menus[1].label, "Script");
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::CommandMap;
+
+/// Where a command appears in menus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MenuPlacement {
+    /// Menu names followed by the item label, separated by "/".
+    pub path: String,
+    /// Items are sorted by order. Each hundred is a group of items.
+    pub order: i32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MenuItem {
+    Command { system_name: String, label: String },
+    Submenu(Menu),
+    Separator,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Menu {
+    pub label: String,
+    pub items: Vec<MenuItem>,
+}
+
+/// A command placed in a menu. `path` is what remains of its menu path below the current menu.
+struct MenuEntry<'a> {
+    path: Vec<&'a str>,
+    order: i32,
+    system_name: &'a str,
+}
+
+const GROUP_SIZE: i32 = 100;
+
+fn build_menu(label: &str, entries: Vec<MenuEntry>) -> Menu {
+    // Submenus are placed at the order of their first item.
+    let mut items: Vec<(i32, MenuItem)> = Vec::new();
+    let mut submenus: BTreeMap<&str, Vec<MenuEntry>> = BTreeMap::new();
+
+    for entry in entries {
+        match entry.path.as_slice() {
+            [item_label] => items.push((entry.order, MenuItem::Command {
+                system_name: entry.system_name.to_string(),
+                label: item_label.to_string(),
+            })),
+            [submenu_label, ..] => {
+                let submenu_label = *submenu_label;
+                submenus.entry(submenu_label).or_default().push(MenuEntry {
+                    path: entry.path[1..].to_vec(),
+                    order: entry.order,
+                    system_name: entry.system_name,
+                });
+            },
+            [] => {},
+        }
+    }
+
+    for (submenu_label, entries) in submenus {
+        let order = entries.iter().map(|entry| entry.order).min().unwrap_or(0);
+        items.push((order, MenuItem::Submenu(build_menu(submenu_label, entries))));
+    }
+
+    // Stable sort: commands keep system name order, and come before submenus with the same order.
+    items.sort_by_key(|(order, _)| *order);
+
+    let mut menu = Menu {
+        label: label.to_string(),
+        items: Vec::new(),
+    };
+    let mut previous_group: Option<i32> = None;
+    for (order, item) in items {
+        let group = order.div_euclid(GROUP_SIZE);
+        if previous_group.is_some() && previous_group != Some(group) {
+            menu.items.push(MenuItem::Separator);
+        }
+        previous_group = Some(group);
+        menu.items.push(item);
+    }
+    return menu;
+}
+
+impl<ParamType: Clone, ContextType> CommandMap<ParamType, ContextType> {
+    /// Top level menus, by label. Commands placed directly at the top level, without menu,
+    /// are ignored: menu bars only show menus.
+    pub fn menus(&self) -> Vec<Menu> {
+        let mut top_level: BTreeMap<&str, Vec<MenuEntry>> = BTreeMap::new();
+
+        for (system_name, command) in self.commands.iter() {
+            let placement = match &command.menu {
+                Some(placement) => placement,
+                None => { continue; }
+            };
+            let path: Vec<&str> = placement.path.split("/").map(|part| part.trim()).collect();
+            if path.len() < 2 {
+                continue;
+            }
+            top_level.entry(path[0]).or_default().push(MenuEntry {
+                path: path[1..].to_vec(),
+                order: placement.order,
+                system_name,
+            });
+        }
+
+        return top_level.into_iter()
+            .map(|(label, entries)| build_menu(label, entries))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandBuilder;
+
+    fn command(system_name: &str, label: &str) -> MenuItem {
+        return MenuItem::Command { system_name: system_name.to_string(), label: label.to_string() };
+    }
+
+    #[test]
+    fn it_builds_menus() {
+        let mut commands: CommandMap<f32> = CommandMap::new();
+        CommandBuilder::new().system_name("save").menu("File/Save", 0).write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("open").menu("File/Open", 1).write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("record-session").menu("File/Session/Record", 100).write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("replay-session").menu("File/Session/Replay", 101).write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("undo").menu("Edit/Undo", 0).write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("dump-tree").write(&mut commands).unwrap();
+
+        let menus = commands.menus();
+        assert_eq!(menus.len(), 2);
+        assert_eq!(menus[0], Menu {
+            label: "Edit".to_string(),
+            items: vec!(command("undo", "Undo")),
+        });
+        assert_eq!(menus[1], Menu {
+            label: "File".to_string(),
+            items: vec!(
+                command("save", "Save"),
+                command("open", "Open"),
+                MenuItem::Separator,
+                MenuItem::Submenu(Menu {
+                    label: "Session".to_string(),
+                    items: vec!(command("record-session", "Record"), command("replay-session", "Replay")),
+                }),
+            ),
+        });
+    }
+}
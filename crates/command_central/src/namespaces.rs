@@ -0,0 +1,103 @@
+//! Namespaces of commands, so plugins can register commands without clashing.
+//!
+//! A namespaced system name is the namespace, a `:` and the name: `teapots:spawn-teapot`.
+//! Commands of the app itself have no namespace.
+//!
+//! ```
+//! use command_central::{CommandMap, CommandBuilder, CommandError};
+//!
+//! let mut commands: CommandMap<f32> = CommandMap::new();
+//! CommandBuilder::new().system_name("spawn-teapot").namespace("teapots").write(&mut commands).unwrap();
+//! CommandBuilder::new().system_name("paint-teapot").namespace("teapots").write(&mut commands).unwrap();
+//! CommandBuilder::new().system_name("spawn-teapot").namespace("kettles").write(&mut commands).unwrap();
+//!
+//! // Registering twice is an error
+//! let result = CommandBuilder::new().system_name("spawn-teapot").namespace("teapots").write(&mut commands);
+//! assert_eq!(result, Err(CommandError::AlreadyDefined("teapots:spawn-teapot".to_string())));
+//!
+//! let names: Vec<&String> = commands.commands_in_namespace("teapots").map(|(name, _)| name).collect();
+//! assert_eq!(names, vec!("teapots:paint-teapot", "teapots:spawn-teapot"));
+//!
+//! // Unloading a plugin
+//! assert_eq!(commands.unregister_namespace("teapots").len(), 2);
+//! assert_eq!(commands.commands.len(), 1);
+//! ```
+
+use crate::{CommandMap, CommandInfo};
+
+pub const NAMESPACE_SEPARATOR: char = ':';
+
+/// System name of a command in a namespace.
+pub fn namespaced_name(namespace: &str, name: &str) -> String {
+    return format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, name);
+}
+
+/// Namespace and name of a system name. Commands without namespace are in the "" namespace.
+pub fn split_namespace(system_name: &str) -> (&str, &str) {
+    return system_name.split_once(NAMESPACE_SEPARATOR).unwrap_or(("", system_name));
+}
+
+impl<ParamType: Clone, ContextType> CommandMap<ParamType, ContextType> {
+    /// Commands of a namespace, by system name.
+    pub fn commands_in_namespace<'a>(
+        &'a self,
+        namespace: &'a str
+    ) -> impl Iterator<Item = (&'a String, &'a CommandInfo<ParamType, ContextType>)> {
+        return self.commands.iter()
+            .filter(move |(system_name, _)| split_namespace(system_name).0 == namespace);
+    }
+
+    /// Namespaces with at least one command, in order. Commands without namespace are not listed.
+    pub fn namespaces(&self) -> Vec<&str> {
+        let mut namespaces: Vec<&str> = self.commands.keys()
+            .map(|system_name| split_namespace(system_name).0)
+            .filter(|namespace| !namespace.is_empty())
+            .collect();
+        namespaces.dedup();
+        return namespaces;
+    }
+
+    /// Removes every command of a namespace. Returns the system names of removed commands.
+    pub fn unregister_namespace(&mut self, namespace: &str) -> Vec<String> {
+        let system_names: Vec<String> = self.commands_in_namespace(namespace)
+            .map(|(system_name, _)| system_name.clone())
+            .collect();
+        for system_name in system_names.iter() {
+            self.commands.remove(system_name);
+        }
+        if !system_names.is_empty() {
+            self.generation += 1;
+        }
+        return system_names;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandBuilder;
+
+    #[test]
+    fn it_splits_namespaces() {
+        assert_eq!(namespaced_name("teapots", "spawn-teapot"), "teapots:spawn-teapot");
+        assert_eq!(split_namespace("teapots:spawn-teapot"), ("teapots", "spawn-teapot"));
+        assert_eq!(split_namespace("spawn-sphere"), ("", "spawn-sphere"));
+    }
+
+    #[test]
+    fn it_lists_commands_by_namespace() {
+        let mut commands: CommandMap<f32> = CommandMap::new();
+        CommandBuilder::new().system_name("spawn-sphere").write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("spawn-teapot").namespace("teapots").write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("spawn").namespace("kettles").write(&mut commands).unwrap();
+        CommandBuilder::new().system_name("paint").namespace("kettles").write(&mut commands).unwrap();
+
+        assert_eq!(commands.namespaces(), vec!("kettles", "teapots"));
+        let app_commands: Vec<&String> = commands.commands_in_namespace("").map(|(name, _)| name).collect();
+        assert_eq!(app_commands, vec!("spawn-sphere"));
+
+        assert_eq!(commands.unregister_namespace("kettles"), vec!("kettles:paint", "kettles:spawn"));
+        assert_eq!(commands.namespaces(), vec!("teapots"));
+        assert!(commands.unregister_namespace("kettles").is_empty());
+    }
+}
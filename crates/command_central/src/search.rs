@@ -0,0 +1,203 @@
+//! Ranked command search.
+//!
+//! Search terms are matched fuzzily: the letters of a term must appear in order, but not
+//! necessarily next to each other, so "spsph" finds "spawn-sphere". Matches at the start of
+//! words and consecutive letters score higher. Every term must match the system name, the title
+//! or the docs. Titles and system names count more than docs.
+//!
+//! `CommandUsage` remembers which commands were run, so that frequent and recent commands
+//! rank higher.
+//!
+//! ```
+//! use command_central::{CommandMap, CommandBuilder, CommandUsage};
+//!
+//! let mut commands: CommandMap<f32> = CommandMap::new();
+//! CommandBuilder::new().title("Spawn Sphere").system_name("spawn-sphere").write(&mut commands).unwrap();
+//! CommandBuilder::new().title("Spawn Box").system_name("spawn-box").write(&mut commands).unwrap();
+//!
+//! let mut usage = CommandUsage::default();
+//! assert_eq!(commands.rank("spsph", &usage, 5), vec!("spawn-sphere"));
+//!
+//! // Used commands come first
+//! usage.record("spawn-box");
+//! assert_eq!(commands.rank("spawn", &usage, 5), vec!("spawn-box", "spawn-sphere"));
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::{CommandMap, CommandInfo};
+
+const CONSECUTIVE_BONUS: i32 = 5;
+const WORD_START_BONUS: i32 = 8;
+const SUBSTRING_BONUS: i32 = 20;
+/// Matches in system names and titles count this many times more than matches in docs.
+const NAME_WEIGHT: i32 = 3;
+/// Commands used in the last few runs get a boost, larger for the latest ones.
+const RECENT_RUNS: u64 = 20;
+const MAX_FREQUENCY_BOOST: i32 = 20;
+
+/// Score of `pattern` in `text`, or None if the letters of `pattern` are not all in `text`, in order.
+/// Case insensitive.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i32> {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    if pattern.is_empty() {
+        return Some(0);
+    }
+
+    let mut score = 0;
+    let mut pattern_index = 0;
+    let mut previous_match: Option<usize> = None;
+
+    for (text_index, character) in text.iter().enumerate() {
+        if pattern_index == pattern.len() {
+            break;
+        }
+        if *character != pattern[pattern_index] {
+            continue;
+        }
+
+        score += 1;
+        if previous_match.is_some() && previous_match == text_index.checked_sub(1) {
+            score += CONSECUTIVE_BONUS;
+        }
+        let is_word_start = text_index == 0 || !text[text_index - 1].is_alphanumeric();
+        if is_word_start {
+            score += WORD_START_BONUS;
+        }
+        previous_match = Some(text_index);
+        pattern_index += 1;
+    }
+
+    if pattern_index < pattern.len() {
+        return None;
+    }
+
+    let pattern: String = pattern.iter().collect();
+    let text: String = text.iter().collect();
+    if text.contains(&pattern) {
+        score += SUBSTRING_BONUS;
+    }
+
+    return Some(score);
+}
+
+#[derive(Clone, Debug, Default)]
+struct UsageEntry {
+    count: u32,
+    /// Value of `CommandUsage::runs` when the command last ran.
+    last_run: u64,
+}
+
+/// How often and how recently commands were run.
+#[derive(Clone, Debug, Default)]
+pub struct CommandUsage {
+    entries: BTreeMap<String, UsageEntry>,
+    /// Number of commands run so far. Recency is counted in runs, not time.
+    runs: u64,
+}
+
+impl CommandUsage {
+    pub fn record(&mut self, system_name: &str) {
+        self.runs += 1;
+        let entry = self.entries.entry(system_name.to_string()).or_default();
+        entry.count += 1;
+        entry.last_run = self.runs;
+    }
+
+    pub fn count(&self, system_name: &str) -> u32 {
+        return self.entries.get(system_name).map(|entry| entry.count).unwrap_or(0);
+    }
+
+    /// Score added to search matches of a command.
+    pub fn boost(&self, system_name: &str) -> i32 {
+        let entry = match self.entries.get(system_name) {
+            Some(entry) => entry,
+            None => { return 0; }
+        };
+        let frequency = (entry.count as i32 * 2).min(MAX_FREQUENCY_BOOST);
+        let runs_since = self.runs - entry.last_run;
+        let recency = RECENT_RUNS.saturating_sub(runs_since) as i32;
+        return frequency + recency;
+    }
+}
+
+/// Score of a search in a command, or None if a search term is not found.
+fn command_score<ParamType: Clone, ContextType>(
+    terms: &[&str],
+    system_name: &str,
+    command: &CommandInfo<ParamType, ContextType>
+) -> Option<i32> {
+    let mut score = 0;
+    for term in terms {
+        let name_score = [system_name, command.title.as_str()].iter()
+            .filter_map(|text| fuzzy_score(term, text))
+            .max()
+            .map(|score| score * NAME_WEIGHT);
+        let docs_score = fuzzy_score(term, &command.docs);
+        score += name_score.max(docs_score)?;
+    }
+    return Some(score);
+}
+
+impl<ParamType: Clone, ContextType> CommandMap<ParamType, ContextType> {
+    /// System names of commands matching `search`, best first.
+    /// An empty search lists the most used commands.
+    pub fn rank(&self, search: &str, usage: &CommandUsage, limit: usize) -> Vec<String> {
+        let terms: Vec<&str> = search.split_whitespace().collect();
+        let mut results: Vec<(i32, &String)> = self.commands.iter()
+            .filter_map(|(system_name, command)| {
+                let score = command_score(&terms, system_name, command)?;
+                return Some((score + usage.boost(system_name), system_name));
+            })
+            .collect();
+
+        // Stable sort: equal scores stay in system name order.
+        results.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+        return results.into_iter()
+            .take(limit)
+            .map(|(_, system_name)| system_name.clone())
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandBuilder;
+
+    #[test]
+    fn it_scores_fuzzy_matches() {
+        assert_eq!(fuzzy_score("xyz", "spawn-sphere"), None);
+        assert_eq!(fuzzy_score("", "spawn-sphere"), Some(0));
+        assert!(fuzzy_score("SPSPH", "spawn-sphere").is_some());
+
+        // Word starts and consecutive letters are better
+        assert!(fuzzy_score("ps", "paint-select") > fuzzy_score("ps", "apples"));
+        assert!(fuzzy_score("sph", "spawn-sphere") > fuzzy_score("sph", "s-p-h"));
+    }
+
+    #[test]
+    fn it_ranks_commands() {
+        let mut commands: CommandMap<f32> = CommandMap::new();
+        CommandBuilder::new().title("Scale").system_name("scale").docs("Start scaling selection.").write(&mut commands).unwrap();
+        CommandBuilder::new().title("Save").system_name("save").docs("Save the scene to a file.").write(&mut commands).unwrap();
+        CommandBuilder::new().title("Select all/none").system_name("select_all_or_none").docs("").write(&mut commands).unwrap();
+
+        let mut usage = CommandUsage::default();
+        assert_eq!(commands.rank("save", &usage, 5), vec!("save"));
+        // Title matches come before docs matches
+        assert_eq!(commands.rank("sel", &usage, 5)[0], "select_all_or_none");
+        // Every term must match
+        assert_eq!(commands.rank("scale quit", &usage, 5), Vec::<String>::new());
+        assert_eq!(commands.rank("s", &usage, 2).len(), 2);
+
+        // Frequent and recent commands come first
+        usage.record("select_all_or_none");
+        usage.record("select_all_or_none");
+        assert_eq!(commands.rank("s", &usage, 5)[0], "select_all_or_none");
+        assert_eq!(commands.rank("", &usage, 1), vec!("select_all_or_none"));
+        assert_eq!(usage.count("select_all_or_none"), 2);
+    }
+}
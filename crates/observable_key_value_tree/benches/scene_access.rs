@@ -0,0 +1,145 @@
+//! Per-frame cost of typical scene accesses on a 256-object scene.
+//!
+//! Run with `cargo bench -p observable_key_value_tree`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use observable_key_value_tree::{ObservableKVTree, CanBeNone};
+
+const OBJECT_COUNT: usize = 256;
+const FRAMES: u32 = 2000;
+
+/// Roughly the size of an SDF object in the editor.
+#[derive(Clone,Default)]
+struct Object {
+    uuid: u128,
+    transform: [f32; 16],
+    color: [f32; 4],
+    params: [f32; 8],
+    object_type: i32,
+}
+
+#[derive(Clone,Default)]
+enum BenchValue {
+    Objects(Vec<Object>),
+    F32(f32),
+    #[default]
+    None,
+}
+
+impl CanBeNone<BenchValue> for BenchValue {
+    fn none() -> BenchValue {
+        return BenchValue::None;
+    }
+
+    fn is_none_value(value: &BenchValue) -> bool {
+        return matches!(value, BenchValue::None);
+    }
+}
+
+fn build_scene() -> ObservableKVTree<BenchValue> {
+    let mut tree = ObservableKVTree::<BenchValue>::default();
+    let objects: Vec<Object> = (0..OBJECT_COUNT).map(|index| Object {
+        uuid: index as u128,
+        object_type: 1,
+        ..Object::default()
+    }).collect();
+
+    tree.set_path("scene.sdf_objects", BenchValue::Objects(objects));
+
+    // Per-object properties, as stored by editor state.
+    for index in 0..OBJECT_COUNT {
+        tree.set_path(&format!("scene.objects.{}.radius", index), BenchValue::F32(1.0));
+        tree.set_path(&format!("scene.objects.{}.smoothness", index), BenchValue::F32(0.1));
+    }
+
+    tree.make_undo_redo_snapshot();
+    tree.reset_update_cycle();
+
+    return tree;
+}
+
+fn bench(name: &str, mut frame: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        frame();
+    }
+    let per_frame: Duration = start.elapsed() / FRAMES;
+    println!("{:<50} {:>12.2?} per frame", name, per_frame);
+}
+
+fn count_objects(value: &BenchValue) -> usize {
+    return match value {
+        BenchValue::Objects(objects) => objects.len(),
+        _ => 0
+    };
+}
+
+fn main() {
+    let mut tree = build_scene();
+
+    println!("Scene with {} objects, {} frames per case.", OBJECT_COUNT, FRAMES);
+
+    // Interaction systems read the object list several times per frame.
+    bench("4x get_path(\"scene.sdf_objects\")", || {
+        for _ in 0..4 {
+            black_box(count_objects(&tree.get_path("scene.sdf_objects")));
+        }
+    });
+
+    bench("4x get_ref(\"scene.sdf_objects\")", || {
+        for _ in 0..4 {
+            black_box(count_objects(tree.get_ref("scene.sdf_objects").unwrap()));
+        }
+    });
+
+    bench("get_tree(\"scene\")", || {
+        black_box(tree.get_tree("scene"));
+    });
+
+    bench("get_tree_ref(\"scene\")", || {
+        black_box(tree.get_tree_ref("scene"));
+    });
+
+    // Rendering reads per-object properties.
+    bench("get_ref of every object radius", || {
+        let radius: f32 = (0..OBJECT_COUNT).map(|index| {
+            match tree.get_ref(&format!("scene.objects.{}.radius", index)) {
+                Some(BenchValue::F32(radius)) => *radius,
+                _ => 0.0
+            }
+        }).sum();
+        black_box(radius);
+    });
+
+    // Grabbing an object: one write per frame, then the update cycle is reset.
+    bench("move one object with get_path + set_path", || {
+        let mut value = tree.get_path("scene.sdf_objects");
+        if let BenchValue::Objects(objects) = &mut value {
+            objects[0].transform[12] += 0.01;
+        }
+        tree.set_path("scene.sdf_objects", value);
+        tree.reset_update_cycle();
+    });
+
+    bench("move one object with get_mut", || {
+        if let BenchValue::Objects(objects) = &mut *tree.get_mut("scene.sdf_objects") {
+            objects[0].transform[12] += 0.01;
+        }
+        tree.reset_update_cycle();
+    });
+
+    bench("move one object + undo snapshot", || {
+        if let BenchValue::Objects(objects) = &mut *tree.get_mut("scene.sdf_objects") {
+            objects[0].transform[12] += 0.01;
+        }
+        tree.make_undo_redo_snapshot();
+        tree.reset_update_cycle();
+    });
+
+    // Keep fields used, so the object has a realistic size.
+    let objects = tree.get_ref("scene.sdf_objects").unwrap();
+    if let BenchValue::Objects(objects) = objects {
+        black_box((objects[0].uuid, objects[0].color, objects[0].params, objects[0].object_type));
+    }
+}
@@ -0,0 +1,88 @@
+//! Typed access to subtrees.
+//!
+//! `TreeValue` converts a Rust type to and from the tree's value type.
+//! `TreeBinding` maps the fields of a struct to the children of a subtree.
+//! With the `derive` feature, `#[derive(TreeBinding)]` implements it and generates typed
+//! accessors for each field, so path names are checked at compile time.
+//! See the observable_key_value_tree_derive crate.
+
+use crate::{ObservableKVTree, CanBeNone, ExampleValueType};
+
+/// Conversion between a Rust type and the tree's value type.
+pub trait TreeValue<ValueType>: Sized {
+    /// Returns None if the value holds another type.
+    fn from_tree_value(value: &ValueType) -> Option<Self>;
+    fn to_tree_value(&self) -> ValueType;
+}
+
+/// Borrowing access to a Rust type stored in the tree's value type.
+/// Useful for large values, such as vectors, that should not be copied.
+pub trait TreeValueRef<ValueType> {
+    /// Returns None if the value holds another type.
+    fn from_tree_value_ref(value: &ValueType) -> Option<&Self>;
+}
+
+/// Maps the fields of a struct to the children of a subtree.
+pub trait TreeBinding<ValueType: Default + Clone + CanBeNone<ValueType>>: Sized {
+    /// Names of the children of the subtree, in field order.
+    const FIELDS: &'static [&'static str];
+
+    /// Returns None if a field is missing or holds another type.
+    fn read_from(tree: &ObservableKVTree<ValueType>, path: &str) -> Option<Self>;
+    /// Writes every field with `set_path`.
+    fn write_to(&self, tree: &mut ObservableKVTree<ValueType>, path: &str);
+    /// True if any field was updated during this update cycle.
+    fn was_updated_in(tree: &ObservableKVTree<ValueType>, path: &str) -> bool;
+}
+
+/// Path of a field of a binding at `path`.
+pub fn binding_field_path(path: &str, field: &str) -> String {
+    return match path.is_empty() {
+        true => field.to_string(),
+        false => format!("{}.{}", path, field)
+    };
+}
+
+impl TreeValue<ExampleValueType> for i32 {
+    fn from_tree_value(value: &ExampleValueType) -> Option<Self> {
+        return match value {
+            ExampleValueType::I32(value) => Some(*value),
+            _ => None
+        };
+    }
+
+    fn to_tree_value(&self) -> ExampleValueType {
+        return ExampleValueType::I32(*self);
+    }
+}
+
+impl TreeValueRef<ExampleValueType> for i32 {
+    fn from_tree_value_ref(value: &ExampleValueType) -> Option<&Self> {
+        return match value {
+            ExampleValueType::I32(value) => Some(value),
+            _ => None
+        };
+    }
+}
+
+impl TreeValue<ExampleValueType> for f32 {
+    fn from_tree_value(value: &ExampleValueType) -> Option<Self> {
+        return match value {
+            ExampleValueType::F32(value) => Some(*value),
+            _ => None
+        };
+    }
+
+    fn to_tree_value(&self) -> ExampleValueType {
+        return ExampleValueType::F32(*self);
+    }
+}
+
+impl TreeValueRef<ExampleValueType> for f32 {
+    fn from_tree_value_ref(value: &ExampleValueType) -> Option<&Self> {
+        return match value {
+            ExampleValueType::F32(value) => Some(value),
+            _ => None
+        };
+    }
+}
@@ -0,0 +1,345 @@
+//! Computed values: values derived from other paths of the tree.
+//!
+//! A computed value is declared with its input paths and a pure function of the input values.
+//! The tree stores the result at the computed path, so it is read with `get_path` and observed
+//! with `was_path_updated` or update channels like any other value.
+//! It is recomputed when a write changes the version of one of its inputs.
+//! Writing an input's child (or parent) counts as writing the input.
+//!
+//! Values computed from every child of a path, like a list of objects stored by id, are declared
+//! with `add_computed_from_subtrees`: the function receives the input subtrees instead of their values.
+//!
+//! Computed values are not part of undo history: undoing a change to an input recomputes them.
+//! Inputs may be other computed values, as long as they don't form a cycle: declaring a value
+//! that would depend on itself is refused with `TreeError::ComputedCycle`.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType};
+//!
+//! fn sum(inputs: &[&ExampleValueType]) -> ExampleValueType {
+//!     let sum: i32 = inputs.iter().map(|value| match value {
+//!         ExampleValueType::I32(value) => *value,
+//!         _ => 0
+//!     }).sum();
+//!     return ExampleValueType::from(sum);
+//! }
+//!
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! data.add_computed("stats.sum", &["scene.a", "scene.b"], sum).unwrap();
+//! data.set_path("scene.a", ExampleValueType::from(1));
+//! data.set_path("scene.b", ExampleValueType::from(2));
+//! assert_eq!(data.get_path("stats.sum").unwrap_i32(), 3);
+//! // Computed values can't be written directly
+//! assert!(data.try_set_path("stats.sum", ExampleValueType::from(1)).is_err());
+//! ```
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::{ObservableKVTree, CanBeNone, TreeError};
+
+#[derive(Clone,Debug)]
+pub enum ComputeFunction<ValueType: Default + Clone + CanBeNone<ValueType>> {
+    /// Receives the input values in the order of `inputs`. Missing inputs are none.
+    Values(fn(&[&ValueType]) -> ValueType),
+    /// Receives the input subtrees in the order of `inputs`. Missing inputs are empty trees.
+    Subtrees(fn(&[&ObservableKVTree<ValueType>]) -> ValueType),
+}
+
+#[derive(Clone,Debug)]
+pub struct ComputedValue<ValueType: Default + Clone + CanBeNone<ValueType>> {
+    pub inputs: Vec<String>,
+    pub compute: ComputeFunction<ValueType>,
+    /// Versions of the inputs when the value was last computed.
+    input_versions: Vec<Option<u64>>,
+}
+
+/// True if one path is the other, or a parent of the other.
+fn paths_overlap(a: &str, b: &str) -> bool {
+    let is_parent = |parent: &str, child: &str| {
+        child.len() > parent.len() && child.starts_with(parent) && child.as_bytes()[parent.len()] == b'.'
+    };
+    return a == b || is_parent(a, b) || is_parent(b, a);
+}
+
+impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueType> {
+    /// Declares a value at `path` computed from the values at `inputs`, and computes it.
+    /// Replaces any computed value previously declared at this path.
+    pub fn add_computed(
+        &mut self,
+        path: &str,
+        inputs: &[&str],
+        compute: fn(&[&ValueType]) -> ValueType
+    ) -> Result<(), TreeError> {
+        return self.add_computed_function(path, inputs, ComputeFunction::Values(compute));
+    }
+
+    /// Like `add_computed`, but the function receives the subtrees at `inputs`.
+    pub fn add_computed_from_subtrees(
+        &mut self,
+        path: &str,
+        inputs: &[&str],
+        compute: fn(&[&ObservableKVTree<ValueType>]) -> ValueType
+    ) -> Result<(), TreeError> {
+        return self.add_computed_function(path, inputs, ComputeFunction::Subtrees(compute));
+    }
+
+    fn add_computed_function(
+        &mut self,
+        path: &str,
+        inputs: &[&str],
+        compute: ComputeFunction<ValueType>
+    ) -> Result<(), TreeError> {
+        if self.depends_on(inputs, path) {
+            return Err(TreeError::ComputedCycle(path.to_string()));
+        }
+
+        self.computed_values.insert(path.to_string(), ComputedValue {
+            inputs: inputs.iter().map(|input| input.to_string()).collect(),
+            compute,
+            input_versions: Vec::new(),
+        });
+        self.recompute(path);
+        return Ok(());
+    }
+
+    /// True if values at `inputs` depend on `path`, directly or through other computed values.
+    /// The value computed at `path` itself is ignored, since it is being replaced.
+    fn depends_on(&self, inputs: &[&str], path: &str) -> bool {
+        let mut inputs: Vec<String> = inputs.iter().map(|input| input.to_string()).collect();
+        let mut visited: BTreeSet<&String> = BTreeSet::new();
+
+        while let Some(input) = inputs.pop() {
+            if paths_overlap(&input, path) {
+                return true;
+            }
+            for (computed_path, computed) in self.computed_values.iter() {
+                if computed_path != path && paths_overlap(computed_path, &input) && visited.insert(computed_path) {
+                    inputs.extend(computed.inputs.iter().cloned());
+                }
+            }
+        }
+
+        return false;
+    }
+
+    /// The value stays in the tree, but is no longer updated and can be written again.
+    pub fn remove_computed(&mut self, path: &str) -> Option<ComputedValue<ValueType>> {
+        return self.computed_values.remove(path);
+    }
+
+    pub fn is_computed(&self, path: &str) -> bool {
+        return self.computed_values.contains_key(path);
+    }
+
+    /// Recomputes values depending on a path that was just written.
+    /// Computed values that were overwritten along with a subtree are restored.
+    pub(crate) fn update_computed_values(&mut self, changed_path: &str) {
+        self.update_values_computed_from(changed_path, true);
+    }
+
+    fn update_values_computed_from(&mut self, changed_path: &str, restore_overwritten: bool) {
+        if self.computed_values.is_empty() {
+            return;
+        }
+
+        let mut affected_paths: Vec<String> = Vec::new();
+        for (path, computed) in self.computed_values.iter_mut() {
+            if restore_overwritten && paths_overlap(path, changed_path) {
+                computed.input_versions.clear();
+                affected_paths.push(path.clone());
+            } else if computed.inputs.iter().any(|input| paths_overlap(input, changed_path)) {
+                affected_paths.push(path.clone());
+            }
+        }
+
+        for path in affected_paths {
+            self.recompute(&path);
+        }
+    }
+
+    /// Recomputes a value if the version of one of its inputs changed.
+    fn recompute(&mut self, path: &str) {
+        let computed = match self.computed_values.get(path) {
+            Some(computed) => computed,
+            None => { return; }
+        };
+
+        let versions: Vec<Option<u64>> = computed.inputs.iter().map(|input| self.path_version(input)).collect();
+        if versions == computed.input_versions {
+            return;
+        }
+
+        let value = match computed.compute {
+            ComputeFunction::Values(compute) => {
+                let none = ValueType::none();
+                let inputs: Vec<&ValueType> = computed.inputs.iter()
+                    .map(|input| self.get_ref(input).unwrap_or(&none))
+                    .collect();
+                compute(&inputs)
+            },
+            ComputeFunction::Subtrees(compute) => {
+                let empty = ObservableKVTree::default();
+                let inputs: Vec<&ObservableKVTree<ValueType>> = computed.inputs.iter()
+                    .map(|input| self.get_tree_ref(input).unwrap_or(&empty))
+                    .collect();
+                compute(&inputs)
+            },
+        };
+
+        self.computed_values.get_mut(path).unwrap().input_versions = versions;
+        self.set_computed_value(path, Arc::new(value));
+    }
+
+    /// Stores a computed value. Listeners are notified, but the change is not tracked in undo history.
+    fn set_computed_value(&mut self, path: &str, value: Arc<ValueType>) {
+        let old_value = self.get_shared_value(path).unwrap_or_else(|| Arc::new(ValueType::none()));
+
+        let version = self.next_version();
+        self.set_path_with_parts(path.split(".").collect(), ObservableKVTree {
+            value: value.clone(),
+            ..ObservableKVTree::default()
+        }, false, version);
+
+        self.notify_listeners(path, &value, &old_value, false);
+        self.update_values_computed_from(path, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+    use crate::TreeError;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    fn double(inputs: &[&ExampleValueType]) -> ExampleValueType {
+        return match inputs[0] {
+            ExampleValueType::I32(value) => ExampleValueType::from(value * 2),
+            _ => ExampleValueType::None
+        };
+    }
+
+    /// Number of children with a value.
+    fn count_children(inputs: &[&ObservableKVTree<ExampleValueType>]) -> ExampleValueType {
+        let count = inputs[0].children("").filter(|(_, child)| !child.value().is_none()).count();
+        return ExampleValueType::from(count as i32);
+    }
+
+    static CALLS: AtomicI32 = AtomicI32::new(0);
+
+    fn counted_double(inputs: &[&ExampleValueType]) -> ExampleValueType {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        return double(inputs);
+    }
+
+    #[test]
+    fn it_matches_overlapping_paths() {
+        assert!(paths_overlap("scene.a", "scene.a"));
+        assert!(paths_overlap("scene", "scene.a"));
+        assert!(paths_overlap("scene.a.b", "scene.a"));
+        assert!(!paths_overlap("scene.ab", "scene.a"));
+        assert!(!paths_overlap("scene.b", "scene.a"));
+    }
+
+    #[test]
+    fn it_recomputes_when_inputs_change() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.a", ExampleValueType::from(1));
+        data.add_computed("computed.double", &["scene.a"], double).unwrap();
+        assert_eq!(data.get_path("computed.double").unwrap_i32(), 2);
+
+        data.reset_update_cycle();
+        data.set_path("scene.b", ExampleValueType::from(5));
+        assert!(!data.was_path_updated("computed.double"));
+
+        data.set_path("scene.a", ExampleValueType::from(3));
+        assert!(data.was_path_updated("computed.double"));
+        assert_eq!(data.get_path("computed.double").unwrap_i32(), 6);
+
+        // Writing a parent of an input
+        let mut scene = ObservableKVTree::<ExampleValueType>::default();
+        scene.set_path("a", ExampleValueType::from(4));
+        data.set_tree("scene", scene);
+        assert_eq!(data.get_path("computed.double").unwrap_i32(), 8);
+
+        assert_eq!(
+            data.try_set_path("computed.double", ExampleValueType::from(1)),
+            Err(TreeError::Computed("computed.double".to_string()))
+        );
+    }
+
+    #[test]
+    fn it_computes_from_subtrees() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.add_computed_from_subtrees("computed.count", &["scene.objects"], count_children).unwrap();
+        assert_eq!(data.get_path("computed.count").unwrap_i32(), 0);
+
+        data.set_path("scene.objects.a", ExampleValueType::from(1));
+        data.set_path("scene.objects.b", ExampleValueType::from(2));
+        assert_eq!(data.get_path("computed.count").unwrap_i32(), 2);
+
+        data.set_path("scene.objects.a", ExampleValueType::None);
+        assert_eq!(data.get_path("computed.count").unwrap_i32(), 1);
+    }
+
+    #[test]
+    fn it_only_recomputes_on_version_changes() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.a", ExampleValueType::from(1));
+        data.add_computed("computed.double", &["scene"], counted_double).unwrap();
+        let calls = CALLS.load(Ordering::SeqCst);
+
+        // Reading does not recompute
+        data.get_path("computed.double");
+        data.get_path("computed.double");
+        assert_eq!(CALLS.load(Ordering::SeqCst), calls);
+
+        data.set_path("scene.a", ExampleValueType::from(2));
+        assert_eq!(CALLS.load(Ordering::SeqCst), calls + 1);
+    }
+
+    #[test]
+    fn it_refuses_cycles() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        assert_eq!(
+            data.add_computed("computed.a", &["computed"], double),
+            Err(TreeError::ComputedCycle("computed.a".to_string()))
+        );
+
+        data.add_computed("computed.a", &["scene.a"], double).unwrap();
+        data.add_computed("computed.b", &["computed.a"], double).unwrap();
+        assert_eq!(
+            data.add_computed("computed.a", &["computed.b"], double),
+            Err(TreeError::ComputedCycle("computed.a".to_string()))
+        );
+        // The refused value keeps its previous inputs
+        data.set_path("scene.a", ExampleValueType::from(1));
+        assert_eq!(data.get_path("computed.b").unwrap_i32(), 4);
+
+        // Replacing a value with one that doesn't depend on itself is fine
+        data.add_computed("computed.b", &["scene.a"], double).unwrap();
+        data.add_computed("computed.a", &["computed.b"], double).unwrap();
+        assert_eq!(data.get_path("computed.a").unwrap_i32(), 4);
+    }
+
+    #[test]
+    fn it_notifies_and_follows_undo() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.a", ExampleValueType::from(1));
+        data.add_computed("computed.double", &["scene.a"], double).unwrap();
+        data.add_computed("computed.quadruple", &["computed.double"], double).unwrap();
+        data.make_undo_redo_snapshot();
+
+        let receiver = data.create_update_channel();
+        data.set_path("scene.a", ExampleValueType::from(2));
+        data.make_undo_redo_snapshot();
+
+        let paths: Vec<String> = receiver.try_iter().map(|update| update.path).collect();
+        assert_eq!(paths, vec!("scene.a", "computed.double", "computed.quadruple"));
+        assert_eq!(data.get_path("computed.quadruple").unwrap_i32(), 8);
+
+        data.undo();
+        assert_eq!(data.get_path("computed.quadruple").unwrap_i32(), 4);
+    }
+}
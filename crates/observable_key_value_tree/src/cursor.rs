@@ -0,0 +1,122 @@
+//! Update cursors: per-consumer change detection.
+//!
+//! `was_updated` flags are shared by every reader of the tree and cleared for everyone by
+//! `reset_update_cycle`, so a consumer running after the reset misses the changes.
+//! A cursor instead remembers the versions of the paths its consumer has seen.
+//! Each consumer (for example, each Bevy system) owns its cursor, so consumers don't depend on
+//! each other's timing.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType,UpdateCursor};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! let mut renderer_cursor = UpdateCursor::default();
+//! let mut ui_cursor = UpdateCursor::default();
+//!
+//! data.set_path("scene.some.property", ExampleValueType::from(1234));
+//!
+//! // Each cursor sees the change once.
+//! assert_eq!(renderer_cursor.take_change(&data, "scene"), true);
+//! assert_eq!(renderer_cursor.take_change(&data, "scene"), false);
+//! assert_eq!(ui_cursor.take_change(&data, "scene.some.property"), true);
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::{ObservableKVTree, CanBeNone};
+
+#[derive(Default,Clone,Debug)]
+pub struct UpdateCursor {
+    /// Maps paths to the version last seen by this cursor.
+    seen_versions: BTreeMap<String, Option<u64>>,
+}
+
+impl UpdateCursor {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// True if the value at path, or one of its children, changed since this cursor last saw it.
+    /// Paths this cursor never saw count as changed.
+    pub fn has_changed<ValueType: Default + Clone + CanBeNone<ValueType>>(
+        &self,
+        tree: &ObservableKVTree<ValueType>,
+        path: &str
+    ) -> bool {
+        return self.seen_versions.get(path) != Some(&tree.path_version(path));
+    }
+
+    pub fn mark_seen<ValueType: Default + Clone + CanBeNone<ValueType>>(
+        &mut self,
+        tree: &ObservableKVTree<ValueType>,
+        path: &str
+    ) {
+        self.seen_versions.insert(path.to_string(), tree.path_version(path));
+    }
+
+    /// Returns whether the path changed, and marks it as seen.
+    pub fn take_change<ValueType: Default + Clone + CanBeNone<ValueType>>(
+        &mut self,
+        tree: &ObservableKVTree<ValueType>,
+        path: &str
+    ) -> bool {
+        let changed = self.has_changed(tree, path);
+        if changed {
+            self.mark_seen(tree, path);
+        }
+        return changed;
+    }
+
+    /// Returns whether any of the paths changed, and marks all of them as seen.
+    pub fn take_changes<ValueType: Default + Clone + CanBeNone<ValueType>>(
+        &mut self,
+        tree: &ObservableKVTree<ValueType>,
+        paths: &[&str]
+    ) -> bool {
+        let mut changed = false;
+        for path in paths.iter() {
+            changed |= self.take_change(tree, path);
+        }
+        return changed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+
+    #[test]
+    fn cursors_are_independent_from_update_cycles() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        let mut first_cursor = UpdateCursor::new();
+        let mut second_cursor = UpdateCursor::new();
+
+        data.set_path("scene.a", ExampleValueType::from(1));
+        assert!(first_cursor.take_change(&data, "scene.a"));
+
+        // A reset does not hide changes from cursors that did not see them yet.
+        data.reset_update_cycle();
+        assert!(second_cursor.take_change(&data, "scene.a"));
+        assert!(!first_cursor.take_change(&data, "scene.a"));
+
+        data.set_path("scene.b", ExampleValueType::from(1));
+        assert!(!first_cursor.has_changed(&data, "scene.a"));
+        assert!(first_cursor.has_changed(&data, "scene"));
+    }
+
+    #[test]
+    fn it_marks_all_paths_as_seen() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        let mut cursor = UpdateCursor::new();
+
+        data.set_path("scene.a", ExampleValueType::from(1));
+        data.set_path("scene.b", ExampleValueType::from(1));
+        assert!(cursor.take_changes(&data, &["scene.a", "scene.b"]));
+        assert!(!cursor.has_changed(&data, "scene.b"));
+
+        // Removed paths count as changed
+        data.set_tree("scene", ObservableKVTree::default());
+        assert!(cursor.take_changes(&data, &["scene.a", "scene.b"]));
+        assert!(!cursor.take_changes(&data, &["scene.a", "scene.b"]));
+    }
+}
@@ -0,0 +1,278 @@
+//! Structural diff between two trees.
+//!
+//! A diff lists the paths that were added, removed or changed between two trees.
+//! It can be exported as an RFC 6902 JSON Patch, where tree paths are written as JSON pointers
+//! (`scene.some.property` becomes `/scene/some/property`).
+//!
+//! Patches are applied with `set_path`, so they are tracked and can be undone.
+//! Nodes without a value are considered absent, and removing a path sets its value to none.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType};
+//! let mut old = ObservableKVTree::<ExampleValueType>::default();
+//! old.set_path("scene.some.property", ExampleValueType::from(1234));
+//! let mut new = old.clone();
+//! new.set_path("scene.some.property", ExampleValueType::from(2345));
+//!
+//! let diff = old.diff(&new).unwrap();
+//! assert!(diff.changed.contains_key("scene.some.property"));
+//!
+//! old.apply_json_patch(&diff.to_json_patch()).unwrap();
+//! assert_eq!(old.get_path("scene.some.property").unwrap_i32(), 2345);
+//! ```
+
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::collections::BTreeMap;
+
+use crate::{ObservableKVTree, CanBeNone};
+
+/// An operation of an RFC 6902 JSON Patch.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: serde_json::Value },
+    Remove { path: String },
+    Replace { path: String, value: serde_json::Value },
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    /// The JSON pointer can't be converted to a tree path.
+    InvalidPath(String),
+    /// The value can't be converted to the tree's value type.
+    InvalidValue(String, serde_json::Error),
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::InvalidPath(path) => write!(f, "Invalid patch path: {}", path),
+            PatchError::InvalidValue(path, error) => write!(f, "Invalid value at {}: {}", path, error),
+        }
+    }
+}
+
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct TreeDiff {
+    /// Paths only found in the new tree, with their new value.
+    pub added: BTreeMap<String, serde_json::Value>,
+    /// Paths only found in the old tree, with their old value.
+    pub removed: BTreeMap<String, serde_json::Value>,
+    /// Paths found in both trees with different values, with their (old, new) values.
+    pub changed: BTreeMap<String, (serde_json::Value, serde_json::Value)>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        return self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty();
+    }
+
+    pub fn to_json_patch(&self) -> Vec<PatchOperation> {
+        let mut patch: Vec<PatchOperation> = Vec::new();
+
+        for path in self.removed.keys() {
+            patch.push(PatchOperation::Remove { path: path_to_json_pointer(path) });
+        }
+
+        for (path, value) in self.added.iter() {
+            patch.push(PatchOperation::Add { path: path_to_json_pointer(path), value: value.clone() });
+        }
+
+        for (path, (_old_value, new_value)) in self.changed.iter() {
+            patch.push(PatchOperation::Replace { path: path_to_json_pointer(path), value: new_value.clone() });
+        }
+
+        return patch;
+    }
+}
+
+/// Converts `scene.some.property` to `/scene/some/property`.
+pub fn path_to_json_pointer(path: &str) -> String {
+    return path.split(".")
+        .map(|part| format!("/{}", part.replace("~", "~0").replace("/", "~1")))
+        .collect();
+}
+
+/// Converts `/scene/some/property` to `scene.some.property`.
+pub fn json_pointer_to_path(pointer: &str) -> Result<String, PatchError> {
+    let parts = match pointer.strip_prefix("/") {
+        Some(parts) => parts,
+        None => { return Err(PatchError::InvalidPath(pointer.to_string())); }
+    };
+
+    let parts: Vec<String> = parts.split("/")
+        .map(|part| part.replace("~1", "/").replace("~0", "~"))
+        .collect();
+
+    // Tree paths can't contain empty parts or dots inside a part.
+    if parts.iter().any(|part| part.is_empty() || part.contains(".")) {
+        return Err(PatchError::InvalidPath(pointer.to_string()));
+    }
+
+    return Ok(parts.join("."));
+}
+
+impl <ValueType: Default + Clone + CanBeNone<ValueType> + Serialize> ObservableKVTree<ValueType> {
+    /// Computes the changes needed to go from this tree to `other`.
+    pub fn diff(&self, other: &ObservableKVTree<ValueType>) -> Result<TreeDiff, serde_json::Error> {
+        let old_values = self.flatten_values()?;
+        let mut new_values = other.flatten_values()?;
+        let mut diff = TreeDiff::default();
+
+        for (path, old_value) in old_values.into_iter() {
+            match new_values.remove(&path) {
+                Some(new_value) => {
+                    if new_value != old_value {
+                        diff.changed.insert(path, (old_value, new_value));
+                    }
+                },
+                None => {
+                    diff.removed.insert(path, old_value);
+                }
+            }
+        }
+
+        diff.added = new_values;
+
+        return Ok(diff);
+    }
+
+    /// Lists every path holding a value, with the value serialized as JSON.
+    pub(crate) fn flatten_values(&self) -> Result<BTreeMap<String, serde_json::Value>, serde_json::Error> {
+        let none = serde_json::to_value(ValueType::none())?;
+        let mut values: BTreeMap<String, serde_json::Value> = BTreeMap::new();
+        self.flatten_values_with_prefix("", &none, &mut values)?;
+        return Ok(values);
+    }
+
+    fn flatten_values_with_prefix(
+        &self,
+        prefix: &str,
+        none: &serde_json::Value,
+        values: &mut BTreeMap<String, serde_json::Value>
+    ) -> Result<(), serde_json::Error> {
+        for (key, node) in self.subtree.iter() {
+            let path = match prefix.is_empty() {
+                true => key.clone(),
+                false => format!("{}.{}", prefix, key)
+            };
+
+            let value = serde_json::to_value(node.value.as_ref())?;
+            if value != *none {
+                values.insert(path.clone(), value);
+            }
+
+            node.flatten_values_with_prefix(&path, none, values)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl <ValueType: Default + Clone + CanBeNone<ValueType> + DeserializeOwned> ObservableKVTree<ValueType> {
+    /// Applies a JSON Patch through `set_path`, so changes are tracked and can be undone.
+    /// The patch is validated first: if any operation is invalid, nothing is applied.
+    pub fn apply_json_patch(&mut self, patch: &[PatchOperation]) -> Result<(), PatchError> {
+        let mut writes: Vec<(String, ValueType)> = Vec::new();
+
+        for operation in patch.iter() {
+            let write = match operation {
+                PatchOperation::Add { path, value } | PatchOperation::Replace { path, value } => {
+                    let tree_path = json_pointer_to_path(path)?;
+                    let value: ValueType = serde_json::from_value(value.clone())
+                        .map_err(|error| PatchError::InvalidValue(path.clone(), error))?;
+                    (tree_path, value)
+                },
+                PatchOperation::Remove { path } => {
+                    (json_pointer_to_path(path)?, ValueType::none())
+                }
+            };
+            writes.push(write);
+        }
+
+        for (path, value) in writes.into_iter() {
+            self.set_path(&path, value);
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+
+    #[test]
+    fn it_diffs_trees() {
+        let mut old = ObservableKVTree::<ExampleValueType>::default();
+        old.set_path("scene.unchanged", ExampleValueType::from(1));
+        old.set_path("scene.changed", ExampleValueType::from(2));
+        old.set_path("scene.removed", ExampleValueType::from(3));
+
+        let mut new = ObservableKVTree::<ExampleValueType>::default();
+        new.set_path("scene.unchanged", ExampleValueType::from(1));
+        new.set_path("scene.changed", ExampleValueType::from(20));
+        new.set_path("scene.added.deep", ExampleValueType::from(4));
+
+        let diff = old.diff(&new).unwrap();
+
+        assert_eq!(diff.added.keys().collect::<Vec<&String>>(), vec!("scene.added.deep"));
+        assert_eq!(diff.removed.keys().collect::<Vec<&String>>(), vec!("scene.removed"));
+        assert_eq!(diff.changed.keys().collect::<Vec<&String>>(), vec!("scene.changed"));
+        assert!(old.diff(&old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_serializes_json_patch() {
+        let old = ObservableKVTree::<ExampleValueType>::default();
+        let mut new = ObservableKVTree::<ExampleValueType>::default();
+        new.set_path("scene.property", ExampleValueType::from(1));
+
+        let patch = old.diff(&new).unwrap().to_json_patch();
+
+        assert_eq!(
+            serde_json::to_string(&patch).unwrap(),
+            r#"[{"op":"add","path":"/scene/property","value":{"I32":1}}]"#
+        );
+    }
+
+    #[test]
+    fn it_applies_json_patch_as_undoable_change() {
+        let mut old = ObservableKVTree::<ExampleValueType>::default();
+        old.set_path("scene.changed", ExampleValueType::from(1));
+        old.set_path("scene.removed", ExampleValueType::from(2));
+        old.make_undo_redo_snapshot();
+
+        let mut new = ObservableKVTree::<ExampleValueType>::default();
+        new.set_path("scene.changed", ExampleValueType::from(10));
+        new.set_path("scene.added", ExampleValueType::from(3));
+
+        let patch = old.diff(&new).unwrap().to_json_patch();
+        old.apply_json_patch(&patch).unwrap();
+        old.make_undo_redo_snapshot();
+
+        assert!(old.diff(&new).unwrap().is_empty());
+
+        old.undo();
+        assert_eq!(old.get_path("scene.changed").unwrap_i32(), 1);
+        assert_eq!(old.get_path("scene.removed").unwrap_i32(), 2);
+        assert!(old.get_path("scene.added").is_none());
+    }
+
+    #[test]
+    fn it_rejects_invalid_patches() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        let patch: Vec<PatchOperation> = serde_json::from_str(r#"[
+            {"op":"add","path":"/scene/valid","value":{"I32":1}},
+            {"op":"add","path":"/scene/invalid","value":"not a value"}
+        ]"#).unwrap();
+
+        assert!(data.apply_json_patch(&patch).is_err());
+        assert!(data.get_path("scene.valid").is_none());
+
+        assert!(json_pointer_to_path("scene").is_err());
+        assert_eq!(json_pointer_to_path("/scene/a~1b").unwrap(), "scene.a/b");
+        assert_eq!(path_to_json_pointer("scene.a/b"), "/scene/a~1b");
+    }
+}
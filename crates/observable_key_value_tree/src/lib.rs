@@ -15,12 +15,28 @@
 //! Here are the important parts of the API:
 //!  - `data.set_path("scene.some.property", 1234)`
 //!  - `data.get_path("scene.some.property")`
+//!  - `data.get_ref("scene.some.property")`
+//!  - `data.get_mut("scene.some.property")`
 //!  - `data.update_tracker.was_updated()`
 //!  - `data.was_path_updated("scene.some.property")`
+//!  - `cursor.take_change(&data, "scene.some.property")`
 //!  - `data.create_update_channel()`
 //!  - `data.make_undo_redo_snapshot()`
 //!  - `data.undo()`
 //!  - `data.redo()`
+//!  - `data.undo_history_limits`
+//!  - `data.export_undo_history()`, `data.export_undo_history_under(path)`
+//!  - `data.import_undo_history(history)`
+//!  - `data.start_operation_log(clock)`, `data.stop_operation_log()`, `data.recorded_operation_log()`
+//!  - `data.diff(&other)`
+//!  - `data.apply_json_patch(&patch)`
+//!  - `SyncSession::new(site_id, &mut data, prefixes)`
+//!  - `shared.read()`, `shared.queue_set_path(path, value)`, `shared.sync(&mut data)`
+//!  - `#[derive(TreeBinding)]` (with the `derive` feature)
+//!  - `data.children(path)`, `data.walk(path)`, `data.query("scene.**.size")`, `data.query_filtered("scene.**[kind=F32]", kind_of)`
+//!  - `data.version()`, `data.path_version(path)`, `data.changed_since(path, version)`
+//!  - `data.add_computed(path, inputs, compute)`
+//!  - `data.set_schema(schema)`, `data.try_set_path(path, value)`, `data.take_schema_errors()`, `data.get_as::<T>(path)`, `data.get_ref_as::<T>(path)`
 //!
 //! # Examples
 //!
@@ -36,6 +52,23 @@
 //! let value = data.get_path("scene.some.property").unwrap_i32();
 //! ```
 //!
+//! ## Borrowing values:
+//!
+//! `get_path` returns a copy of the value. To avoid copying large values every frame,
+//! borrow them instead. Subtrees are shared between clones of a tree and only copied when written.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! data.set_path("scene.some.property", ExampleValueType::from(1234));
+//! // Reading without copying
+//! let value: &ExampleValueType = data.get_ref("scene.some.property").unwrap();
+//! assert_eq!(value.unwrap_i32(), 1234);
+//! // Writing in place. The change is tracked when the guard is dropped.
+//! *data.get_mut("scene.some.property") = ExampleValueType::from(2345);
+//! assert_eq!(data.was_path_updated("scene.some.property"), true);
+//! ```
+//!
 //! ## Detecting changes with was_updated:
 //!
 //! ```
@@ -53,6 +86,21 @@
 //! assert_eq!(data.was_updated(), false);
 //! ```
 //!
+//! ## Detecting changes with cursors:
+//!
+//! `reset_update_cycle` clears `was_updated` flags for every reader. When several parts of an
+//! application check for changes at different times, give each of them its own cursor.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType,UpdateCursor};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! let mut cursor = UpdateCursor::default();
+//! data.set_path("scene.some.property", ExampleValueType::from(1234));
+//! // True once per change, for this cursor only
+//! assert_eq!(cursor.take_change(&data, "scene.some.property"), true);
+//! assert_eq!(cursor.take_change(&data, "scene.some.property"), false);
+//! ```
+//!
 //! ## Detecting changes with mspc channel:
 //!
 //! ```
@@ -86,42 +134,149 @@
 //! assert_eq!(data.get_path("some.property").unwrap_f32(), 102.0);
 //! ```
 //!
+//! ## Bounded undo history
+//!
+//! Values are stored behind an `Arc`, so snapshots share them with the tree and with each other
+//! instead of storing copies. The history can also be bounded: when a limit is exceeded,
+//! the oldest undo steps are compacted into a baseline that can't be undone.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType,UndoHistoryLimits};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! data.undo_history_limits = UndoHistoryLimits {
+//!     max_steps: Some(2),
+//!     ..UndoHistoryLimits::default()
+//! };
+//!
+//! for i in 0..5 {
+//!     data.set_path("some.property", ExampleValueType::from(i));
+//!     data.make_undo_redo_snapshot();
+//! }
+//!
+//! data.undo();
+//! data.undo();
+//! // Only one undo step is kept.
+//! assert_eq!(data.get_path("some.property").unwrap_i32(), 3);
+//! ```
+//!
 //! # Notes
 //!  - We consider a value updated even if it was set to the same value again.
 //!  - We consider the parent nodes as updated if a child value was updated.
 //!  - Nodes can contain a value and a sub tree at the same time.
+//!  - Versions come from a tree-wide 64 bit clock advanced by every write, so versions of
+//!    different paths can be compared.
 //!
 //! # Current drawbacks:
-//!  - Network sync not implemented/tested
-//!  - It's not clear if undo/redo will work well if other sources of updates appear,
-//!    such as  network sync. Currently, it works well locally for one user.
+//!  - Network sync resolves conflicts per path (last writer wins), so concurrent edits
+//!    to the same value are not merged.
 //!  - Not so appropriate for graph structure
 //!  - No granular updates for arrays
 
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use serde::{Serialize, Deserialize};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 use std::sync::mpsc::{channel, Sender, Receiver};
 
+mod operation_log;
+pub use operation_log::*;
+mod diff;
+pub use diff::*;
+mod sync;
+pub use sync::*;
+mod shared;
+pub use shared::*;
+mod binding;
+pub use binding::*;
+mod schema;
+pub use schema::*;
+mod computed;
+pub use computed::*;
+mod cursor;
+pub use cursor::*;
+mod query;
+pub use query::*;
+#[cfg(feature = "derive")]
+pub use observable_key_value_tree_derive::TreeBinding;
+
 #[derive(Default,Clone)]
 pub struct Update<ValueType> {
     pub path: String,
     pub value: ValueType,
     pub old_value: ValueType,
+    /// Written with `set_path_untracked`, like changes of other users: sync sessions don't send it back.
+    pub untracked: bool,
 }
 
-#[derive(Default,Debug,Clone)]
+/// Values are shared with the tree and with other snapshots.
+/// Storing a value in a snapshot never copies it.
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
 pub struct Snapshot<ValueType> {
-    new_values: BTreeMap<String, ValueType>,
-    old_values: BTreeMap<String, ValueType>,
-    version: i32,
+    new_values: BTreeMap<String, Arc<ValueType>>,
+    old_values: BTreeMap<String, Arc<ValueType>>,
+    version: u64,
+    /// What changed, like "Duplicate". Empty for unlabeled snapshots.
+    #[serde(default)]
+    label: String,
 }
 
 impl<ValueType> Snapshot<ValueType> {
     fn clear(&mut self) {
+        self.clear_values();
+        self.version = u64::default();
+        self.label.clear();
+    }
+
+    fn clear_values(&mut self) {
         self.new_values.clear();
         self.old_values.clear();
-        self.version = i32::default();
+    }
+
+    /// Keeps only values at `path` or under it.
+    fn retain_values_under(&mut self, path: &str) {
+        let is_under = |value_path: &String| {
+            value_path == path || value_path.starts_with(&format!("{}.", path))
+        };
+        self.new_values.retain(|value_path, _| is_under(value_path));
+        self.old_values.retain(|value_path, _| is_under(value_path));
+    }
+}
+
+/// Undo/redo state of a tree, in a form that can be saved alongside a document.
+/// See `export_undo_history` and `import_undo_history`.
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct UndoHistory<ValueType> {
+    snapshots: Vec<Snapshot<ValueType>>,
+    versions: Vec<u64>,
+    current_version_index: Option<i32>,
+    /// Changes made since the last snapshot.
+    pending_changes: Snapshot<ValueType>,
+}
+
+/// Only the newest refused writes are kept when they are not taken. See `take_schema_errors`.
+const MAX_KEPT_SCHEMA_ERRORS: usize = 100;
+
+/// Limits applied to the undo history every time an undo/redo snapshot is made.
+/// When a limit is exceeded, the oldest undo steps are compacted into a baseline.
+#[derive(Clone,Debug)]
+pub struct UndoHistoryLimits<ValueType> {
+    /// Maximum number of undo steps kept in history.
+    pub max_steps: Option<usize>,
+    /// Maximum estimated memory (in bytes) used by the snapshots.
+    pub max_memory: Option<usize>,
+    /// Estimates the memory used by a value.
+    /// The default only counts the value itself, not what it points to on the heap.
+    pub estimate_value_size: fn(&ValueType) -> usize,
+}
+
+impl<ValueType> Default for UndoHistoryLimits<ValueType> {
+    fn default() -> Self {
+        return Self {
+            max_steps: None,
+            max_memory: None,
+            estimate_value_size: |_value: &ValueType| std::mem::size_of::<ValueType>(),
+        };
     }
 }
 
@@ -129,35 +284,33 @@ impl<ValueType> Snapshot<ValueType> {
 #[derive(Default,Clone,Debug)]
 pub struct LeafVersionTracker {
     updated: bool,
-    version: i32,
-    pub corresponding_previous_version: Option<i32>,
+    version: u64,
+    pub corresponding_previous_version: Option<u64>,
 }
 
 /// Provides the leaf version numbering and 'was_updated' flag.
+/// Versions come from the tree-wide clock: a node's version is the clock value of the last
+/// write to it or to one of its children, so versions of different paths can be compared.
 impl LeafVersionTracker {
     pub fn was_updated(&self) -> bool { self.updated }
-    pub fn version(&self) -> i32 { self.version }
+    pub fn version(&self) -> u64 { self.version }
 
-    fn notify_update(&mut self) {
+    fn notify_update(&mut self, version: u64) {
         self.updated = true;
-        self.version += 1;
+        self.version = self.version.max(version);
     }
 
     fn reset_update_cycle(&mut self) {
         self.updated = false;
     }
-
-    fn clear(&mut self) {
-        self.updated = bool::default();
-        self.version = i32::default();
-    }
 }
 
 #[derive(Default,Serialize,Deserialize,Debug,Clone)]
 pub struct ObservableKVTree <ValueType: Default + Clone + CanBeNone<ValueType>>
 {
-    subtree: BTreeMap<String, ObservableKVTree<ValueType>>,
-    value: ValueType,
+    /// Subtrees are shared with clones of this tree and with snapshots of it (copy-on-write).
+    subtree: BTreeMap<String, Arc<ObservableKVTree<ValueType>>>,
+    value: Arc<ValueType>,
     #[serde(skip)]
     pub update_tracker: LeafVersionTracker,
     #[serde(skip)]
@@ -169,11 +322,64 @@ pub struct ObservableKVTree <ValueType: Default + Clone + CanBeNone<ValueType>>
     #[serde(skip)]
     pub snapshot_change_accumulator: Snapshot<ValueType>,
     #[serde(skip)]
-    pub last_snapshot_version: i32,
+    pub last_snapshot_version: u64,
     #[serde(skip)]
-    pub versions: Vec<i32>,
+    pub versions: Vec<u64>,
     #[serde(skip)]
     pub current_version_index: Option<i32>,
+    #[serde(skip)]
+    pub undo_history_limits: UndoHistoryLimits<ValueType>,
+    /// When set, every write is recorded. See `start_operation_log`.
+    #[serde(skip)]
+    pub operation_log: Option<OperationLog<ValueType>>,
+    /// Log of the last stopped recording, kept until a new recording starts.
+    #[serde(skip)]
+    pub last_operation_log: Option<OperationLog<ValueType>>,
+    /// When set, writes are checked against it. See `set_schema`.
+    #[serde(skip)]
+    schema: Option<Arc<Schema<ValueType>>>,
+    /// Writes refused by the schema in methods that don't return errors, like `set_path`.
+    /// See `take_schema_errors`.
+    #[serde(skip)]
+    schema_errors: Vec<TreeError>,
+    /// Maps paths to values derived from other paths. See `add_computed`.
+    #[serde(skip)]
+    computed_values: BTreeMap<String, ComputedValue<ValueType>>,
+}
+
+/// Mutable access to a value of the tree. See `ObservableKVTree::get_mut`.
+pub struct ValueMut<'a, ValueType: Default + Clone + CanBeNone<ValueType>> {
+    tree: &'a mut ObservableKVTree<ValueType>,
+    path: String,
+    value: Option<ValueType>,
+    /// Set once the value is borrowed mutably. Values that were only read are not written back.
+    modified: bool,
+}
+
+impl<'a, ValueType: Default + Clone + CanBeNone<ValueType>> Deref for ValueMut<'a, ValueType> {
+    type Target = ValueType;
+
+    fn deref(&self) -> &ValueType {
+        return self.value.as_ref().unwrap();
+    }
+}
+
+impl<'a, ValueType: Default + Clone + CanBeNone<ValueType>> DerefMut for ValueMut<'a, ValueType> {
+    fn deref_mut(&mut self) -> &mut ValueType {
+        self.modified = true;
+        return self.value.as_mut().unwrap();
+    }
+}
+
+impl<'a, ValueType: Default + Clone + CanBeNone<ValueType>> Drop for ValueMut<'a, ValueType> {
+    fn drop(&mut self) {
+        if !self.modified {
+            return;
+        }
+        if let Some(value) = self.value.take() {
+            self.tree.set_path(&self.path, value);
+        }
+    }
 }
 
 /// Shortcut to verify if a path was modified.
@@ -183,7 +389,7 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
     }
 
     pub fn was_path_updated(&self, path: &str) -> bool {
-        match self.get_tree(&path) {
+        match self.get_node(&path) {
             Some(value) => {
                 return value.update_tracker.was_updated();
             },
@@ -191,90 +397,314 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
         };
     }
 
-    pub fn path_version(&self, path: &str) -> i32 {
-        match self.get_tree(&path) {
-            Some(value) => {
-                return value.update_tracker.version();
-            },
-            _ => { return -1; }
-        };
+    /// Clock value of the last write to this path or to one of its children.
+    /// None if there is no node at this path.
+    pub fn path_version(&self, path: &str) -> Option<u64> {
+        return self.get_node(&path).map(|node| node.update_tracker.version());
+    }
+
+    /// Current value of the tree-wide clock: the version of the last write to the tree.
+    /// Every write advances the clock, so any version is older than the versions of later writes.
+    pub fn version(&self) -> u64 {
+        return self.update_tracker.version();
+    }
+
+    /// Version the next write will be stamped with.
+    pub(crate) fn next_version(&self) -> u64 {
+        return self.update_tracker.version() + 1;
     }
 }
 
 pub trait CanBeNone<T: Default> {
     fn none() -> T;
+    /// Setting a path without children to a none value removes its node.
+    fn is_none_value(value: &T) -> bool;
 }
 
 impl<T> CanBeNone<Option<T>> for Option<T> {
     fn none() -> Option<T> {
         None
     }
+
+    fn is_none_value(value: &Option<T>) -> bool {
+        return value.is_none();
+    }
 }
 
 impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueType>
 {
     ///  ---------------------  GETTING/SETTING VALUES  ---------------------
 
+    /// Values refused by the schema are not set and the error is kept, see `take_schema_errors`.
+    /// Use `try_set_path` to handle the error.
     pub fn set_path(&mut self, path: &str, value: ValueType) {
-        let old_value = self.get_path(path);
-
-        self.set_path_without_notifying(path, value.clone());
-
-        for listener in self.update_listeners.iter() {
-            _ = listener.send(Update{
-                path: path.to_string(),
-                value: value.clone(),
-                old_value: old_value.clone(),
-            });
+        if let Err(error) = self.try_set_path(path, value) {
+            self.keep_schema_error(error);
         }
     }
 
+    /// Sets a value, or returns an error if the schema expects another kind of value at this path.
+    pub fn try_set_path(&mut self, path: &str, value: ValueType) -> Result<(), TreeError> {
+        return self.set_shared_path(path, Arc::new(value), true, true);
+    }
+
     /// This method is like set path, but it will not notify mspc channels.
     /// was_updated is still set, changes are still accumulated as part of snapshots.
     /// version numbers are still incremented.
     pub fn set_path_without_notifying(&mut self, path: &str, value: ValueType) {
+        if let Err(error) = self.set_shared_path(path, Arc::new(value), false, true) {
+            self.keep_schema_error(error);
+        }
+    }
+
+    /// This method sets a value without accumulating the change in the undo/redo snapshots.
+    /// It is meant for changes coming from other sources, such as other users in a sync session:
+    /// undoing only reverts local changes.
+    /// Channels are notified with an `untracked` update, was_updated is still set
+    /// and version numbers are still incremented.
+    pub fn set_path_untracked(&mut self, path: &str, value: ValueType) {
+        if let Err(error) = self.set_shared_path(path, Arc::new(value), true, false) {
+            self.keep_schema_error(error);
+        }
+    }
+
+    /// Sets a value that may be shared with snapshots without copying it.
+    fn set_shared_path(&mut self, path: &str, value: Arc<ValueType>, notify: bool, track_undo: bool) -> Result<(), TreeError> {
+        if self.computed_values.contains_key(path) {
+            return Err(TreeError::Computed(path.to_string()));
+        }
+        if let Some(schema) = self.schema.as_ref() {
+            schema.check(path, value.as_ref())?;
+        }
+
+        let old_value = self.get_shared_value(path).unwrap_or_else(|| Arc::new(ValueType::none()));
+
         let parts = path.split(".");
-        self.update_snapshot_accumulator(path, value.clone());
+        if track_undo {
+            self.update_snapshot_accumulator(path, value.clone());
+        }
+        let version = self.next_version();
         self.set_path_with_parts(parts.collect(), ObservableKVTree {
-            value,
+            value: value.clone(),
             ..ObservableKVTree::default()
-        }, false);
+        }, false, version);
+
+        if notify {
+            self.notify_listeners(path, &value, &old_value, !track_undo);
+        }
+
+        if let Some(operation_log) = self.operation_log.as_mut() {
+            operation_log.record(Operation::SetPath {
+                path: path.to_string(),
+                old_value,
+                value,
+                notified: notify,
+            }, version);
+        }
+
+        self.update_computed_values(path);
+
+        return Ok(());
+    }
+
+    /// Listeners whose receiver was dropped are removed.
+    fn notify_listeners(&mut self, path: &str, value: &Arc<ValueType>, old_value: &Arc<ValueType>, untracked: bool) {
+        self.update_listeners.retain(|listener| {
+            return listener.send(Update{
+                path: path.to_string(),
+                value: value.as_ref().clone(),
+                old_value: old_value.as_ref().clone(),
+                untracked,
+            }).is_ok();
+        });
+    }
+
+    /// Notifies a write of a subtree as writes of each value it changed.
+    /// `old_values` are the values under `path` before the write.
+    fn notify_subtree_listeners(&mut self, path: &str, mut old_values: BTreeMap<String, Arc<ValueType>>) {
+        let mut new_values: Vec<(String, Arc<ValueType>)> = Vec::new();
+        if let Some(node) = self.get_node(path) {
+            new_values.push((path.to_string(), node.value.clone()));
+            node.for_each_shared_value(path, &mut |path, value| new_values.push((path.to_string(), value.clone())));
+        }
+
+        let none = Arc::new(ValueType::none());
+        for (path, value) in new_values {
+            let old_value = old_values.remove(&path).unwrap_or_else(|| none.clone());
+            if !(ValueType::is_none_value(&value) && ValueType::is_none_value(&old_value)) {
+                self.notify_listeners(&path, &value, &old_value, false);
+            }
+        }
+        // Values that are not in the new subtree were removed.
+        for (path, old_value) in old_values {
+            if !ValueType::is_none_value(&old_value) {
+                self.notify_listeners(&path, &none, &old_value, false);
+            }
+        }
+    }
+
+    /// Values of the node at `path` and of its children, by path.
+    fn shared_values_under(&self, path: &str) -> BTreeMap<String, Arc<ValueType>> {
+        let mut values: BTreeMap<String, Arc<ValueType>> = BTreeMap::new();
+        if let Some(node) = self.get_node(path) {
+            values.insert(path.to_string(), node.value.clone());
+            node.for_each_shared_value(path, &mut |path, value| { values.insert(path.to_string(), value.clone()); });
+        }
+        return values;
     }
 
 
 
     /// Set the whole subtree at given path
     /// This is useful to deserialize the tree.
+    /// Channels are notified of each value the new subtree adds, changes or removes.
+    /// Trees refused by the schema are not set and the errors are kept, see `take_schema_errors`.
+    /// Use `try_set_tree` to handle the errors.
     pub fn set_tree(&mut self, path: &str, value: ObservableKVTree<ValueType>) {
+        if let Err(errors) = self.try_set_tree(path, value) {
+            for error in errors {
+                self.keep_schema_error(error);
+            }
+        }
+    }
+
+    /// Sets the whole subtree at given path, or returns the values refused by the schema.
+    /// The subtree is checked before anything is written, so the tree is unchanged on errors.
+    /// Missing required values are then filled with their default.
+    pub fn try_set_tree(&mut self, path: &str, value: ObservableKVTree<ValueType>) -> Result<(), Vec<TreeError>> {
+        let schema = match self.schema.clone() {
+            Some(schema) => schema,
+            None => {
+                self.set_tree_unchecked(path, value);
+                return Ok(());
+            }
+        };
+
+        let mut errors: Vec<TreeError> = Vec::new();
+        let mut check = |path: &str, value: &ValueType| {
+            if let Err(error) = schema.check(path, value) {
+                errors.push(error);
+            }
+        };
+        check(path, value.value.as_ref());
+        value.for_each_value(path, &mut check);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        self.set_tree_unchecked(path, value);
+        self.fill_required_values(&schema);
+        return Ok(());
+    }
+
+    fn set_tree_unchecked(&mut self, path: &str, value: ObservableKVTree<ValueType>) {
+        let logged_tree = match self.operation_log {
+            Some(_) => Some(value.clone()),
+            None => None
+        };
+
+        let old_values = match self.update_listeners.is_empty() {
+            true => BTreeMap::new(),
+            false => self.shared_values_under(path)
+        };
+
         let parts = path.split(".");
-        self.set_path_with_parts(parts.collect(), value, true);
-        self.notify_change();
+        let version = self.next_version();
+        self.set_path_with_parts(parts.collect(), value, true, version);
+
+        if !self.update_listeners.is_empty() {
+            self.notify_subtree_listeners(path, old_values);
+        }
+
+        if let (Some(operation_log), Some(tree)) = (self.operation_log.as_mut(), logged_tree) {
+            operation_log.record(Operation::SetTree {
+                path: path.to_string(),
+                tree,
+            }, version);
+        }
+
+        self.update_computed_values(path);
+    }
+
+    fn keep_schema_error(&mut self, error: TreeError) {
+        if self.schema_errors.len() >= MAX_KEPT_SCHEMA_ERRORS {
+            self.schema_errors.remove(0);
+        }
+        self.schema_errors.push(error);
+    }
+
+    /// Returns the writes refused by the schema since the last call, oldest first.
+    /// Only errors of methods that can't return them are kept, like `set_path` and `set_tree`.
+    pub fn take_schema_errors(&mut self) -> Vec<TreeError> {
+        return std::mem::take(&mut self.schema_errors);
     }
 
     /// Get the whole subtree at given path
     /// This is useful to serialize the tree.
     pub fn get_path(&self, path: &str) -> ValueType {
-        match self.get_path_with_parts(&path.split(".").collect()) {
-            Some(data) => data.value,
+        match self.get_node(path) {
+            Some(data) => data.value.as_ref().clone(),
             _ => ValueType::none()
         }
     }
 
+    /// Subtrees are shared, so this copies the children of the node, not the whole subtree.
     pub fn get_tree(& self, path: &str) -> Option<ObservableKVTree<ValueType>> {
-        return self.get_path_with_parts(&path.split(".").collect());
+        return self.get_node(path).cloned();
     }
 
-    fn set_path_with_parts(&mut self, parts: Vec<&str>, value: ObservableKVTree<ValueType>, override_subtree: bool) {
+    /// Borrows the value at given path, without copying it.
+    /// Returns None if there is no node at this path.
+    pub fn get_ref(&self, path: &str) -> Option<&ValueType> {
+        return self.get_node(path).map(|node| node.value.as_ref());
+    }
+
+    /// Borrows the subtree at given path, without copying it.
+    pub fn get_tree_ref(&self, path: &str) -> Option<&ObservableKVTree<ValueType>> {
+        return self.get_node(path);
+    }
+
+    /// Gives mutable access to the value at given path.
+    /// The value is written back with `set_path` when the returned guard is dropped,
+    /// so the change is tracked like any other write. Values that were only read are not written back.
+    /// The value is copied once, since the previous value may be shared with snapshots.
+    pub fn get_mut(&mut self, path: &str) -> ValueMut<'_, ValueType> {
+        let value = self.get_path(path);
+        return ValueMut {
+            tree: self,
+            path: path.to_string(),
+            value: Some(value),
+            modified: false,
+        };
+    }
+
+    /// Returns the stored value without copying it.
+    fn get_shared_value(&self, path: &str) -> Option<Arc<ValueType>> {
+        return self.get_node(path).map(|node| node.value.clone());
+    }
+
+    /// Every node written, and its parents, are stamped with `version`.
+    fn set_path_with_parts(&mut self, parts: Vec<&str>, value: ObservableKVTree<ValueType>, override_subtree: bool, version: u64) {
+        if parts.len() == 1 && !override_subtree && ValueType::is_none_value(&value.value) {
+            // Removed values don't leave empty nodes behind. Undo sets the old value back.
+            let has_children = self.subtree.get(parts[0]).is_some_and(|node| !node.subtree.is_empty());
+            if !has_children {
+                self.subtree.remove(parts[0]);
+                self.notify_change(version);
+                return;
+            }
+        }
+
         if parts.len() == 1 {
             if !self.subtree.contains_key(parts[0]) {
-                self.subtree.insert(parts[0].to_string(), ObservableKVTree::default());
+                self.subtree.insert(parts[0].to_string(), Arc::new(ObservableKVTree::default()));
             }
 
             let mut notified_update = false;
 
-            let leaf = &mut self.subtree.get_mut(parts[0]).unwrap();
+            let leaf = Arc::make_mut(self.subtree.get_mut(parts[0]).unwrap());
             leaf.value = value.value;
-            leaf.update_tracker.notify_update();
+            leaf.update_tracker.notify_update(version);
 
             if override_subtree {
                 let mut keys_to_remove: Vec<String> = Vec::new();
@@ -294,84 +724,95 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
                         leaf.subtree.insert(key.clone(), subvalue.clone());
                     } else {
                         let parts: Vec<&str> = vec!(key);
-                        leaf.set_path_with_parts(parts, subvalue.clone(), override_subtree);
+                        leaf.set_path_with_parts(parts, subvalue.as_ref().clone(), override_subtree, version);
                         // Prevent a double update
                         notified_update = true;
                     }
                 }
 
                 if !notified_update {
-                    leaf.update_tracker.notify_update();
+                    leaf.update_tracker.notify_update(version);
                 }
 
+                self.notify_change(version);
                 return;
             }
         }
         else {
             if !self.subtree.contains_key(parts[0]) {
-                self.subtree.insert(parts[0].to_string(), ObservableKVTree::default());
+                self.subtree.insert(parts[0].to_string(), Arc::new(ObservableKVTree::default()));
             }
-            let subtree = &mut self.subtree.get_mut(parts[0]).unwrap();
-            subtree.set_path_with_parts(parts[1..].to_vec(), value, override_subtree);
+            let subtree = Arc::make_mut(self.subtree.get_mut(parts[0]).unwrap());
+            subtree.set_path_with_parts(parts[1..].to_vec(), value, override_subtree, version);
         }
 
-        self.notify_change();
+        self.notify_change(version);
+    }
+
+    fn get_node(&self, path: &str) -> Option<&ObservableKVTree<ValueType>> {
+        let parts: Vec<&str> = path.split(".").collect();
+        return self.get_node_with_parts(&parts);
     }
 
-    fn get_path_with_parts(&self, parts: &Vec<&str>) -> Option<ObservableKVTree<ValueType>> {
+    fn get_node_with_parts(&self, parts: &[&str]) -> Option<&ObservableKVTree<ValueType>> {
+        let subtree = self.subtree.get(parts[0])?;
         if parts.len() == 1 {
-            return self.subtree.get(parts[0]).cloned();
-        }
-        else {
-            if !self.subtree.contains_key(parts[0]) {
-                return None;
-            }
-            let subtree = &self.subtree.get(parts[0]).unwrap();
-            let value = match subtree.get_path_with_parts(&parts[1..].to_vec()) {
-                Some(value) => value,
-                _ => { return None },
-            };
-            return Some(value);
+            return Some(subtree.as_ref());
         }
+        return subtree.get_node_with_parts(&parts[1..]);
     }
 
+    /// Removes every value, along with undo history, listeners, computed values and the schema.
+    /// The version clock is not reset: clearing is a change, and later writes get newer versions
+    /// than the ones seen before, so update cursors keep working.
     pub fn clear(&mut self) {
+        let version = self.next_version();
         self.subtree.clear();
-        self.value = ValueType::none();
-        self.update_tracker.clear();
+        self.value = Arc::new(ValueType::none());
+        self.notify_change(version);
+        self.update_tracker.corresponding_previous_version = None;
         self.update_listeners.clear();
         self.snapshot_change_accumulator.clear();
         self.snapshots.clear();
+        self.versions.clear();
+        self.current_version_index = None;
+        self.computed_values.clear();
+        self.schema = None;
+        self.schema_errors.clear();
     }
 
     ///  ---------------------  SNAPSHOT MANAGEMENT  ---------------------
 
-    pub fn make_snapshot(&mut self) -> i32 {
+    pub fn make_snapshot(&mut self) -> u64 {
         let version = self.update_tracker.version;
         self.snapshots.push(Snapshot {
             version,
             old_values: self.snapshot_change_accumulator.old_values.clone(),
-            new_values: self.snapshot_change_accumulator.new_values.clone()
+            new_values: self.snapshot_change_accumulator.new_values.clone(),
+            label: String::new(),
         });
         self.snapshot_change_accumulator.clear();
         self.last_snapshot_version = version;
+        // The tree is now at the state of this new snapshot,
+        // not at the state of a previously visited one.
+        self.update_tracker.corresponding_previous_version = None;
         return version;
     }
 
-    pub fn last_snapshot_version(&mut self) -> Option<i32> {
+    pub fn last_snapshot_version(&mut self) -> Option<u64> {
         return match self.snapshots.last() {
             Some(snapshot) => { Some(snapshot.version) },
             _ => { None }
         };
     }
 
-    pub fn revert_snapshot_version(&mut self, version: i32) {
+    pub fn revert_snapshot_version(&mut self, version: u64) {
         let snapshot: Option<Snapshot<ValueType>> = self.snapshots.iter().find(|snapshot| snapshot.version == version).cloned();
 
         match snapshot {
             Some(snapshot) => {
                 for (path, old_value) in snapshot.old_values.iter() {
-                    self.set_path(path.as_str(), old_value.to_owned());
+                    _ = self.set_shared_path(path.as_str(), old_value.clone(), true, true);
                 }
             },
             None => {
@@ -380,7 +821,7 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
         }
     }
 
-    pub fn go_to_snapshot_with_version(&mut self, version: i32) {
+    pub fn go_to_snapshot_with_version(&mut self, version: u64) {
         let snapshot: Option<Snapshot<ValueType>> = self.snapshots.iter().find(|snapshot| snapshot.version == version).cloned();
 
         match snapshot {
@@ -404,7 +845,7 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
         }
     }
 
-    pub fn rewind_to_version(&mut self, version: i32) {
+    pub fn rewind_to_version(&mut self, version: u64) {
         let current_version = match self.update_tracker.corresponding_previous_version {
             Some(version) => version,
             None => self.update_tracker.version
@@ -428,7 +869,7 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
         self.update_tracker.corresponding_previous_version = Some(version);
     }
 
-    pub fn fast_forward_to_version(&mut self, version: i32) {
+    pub fn fast_forward_to_version(&mut self, version: u64) {
         let current_version = match self.update_tracker.corresponding_previous_version {
             Some(version) => version,
             None => self.update_tracker.version
@@ -455,21 +896,23 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
     // Reverts a snapshot version and returns the reverted snapshot (if found)
     pub fn apply_snapshot(&mut self, snapshot: &Snapshot<ValueType>) {
         for (path, new_value) in snapshot.new_values.iter() {
-            self.set_path(path.as_str(), new_value.to_owned())
+            _ = self.set_shared_path(path.as_str(), new_value.clone(), true, true);
         }
     }
 
     pub fn revert_snapshot(&mut self, snapshot: &Snapshot<ValueType>) {
         for (path, old_value) in snapshot.old_values.iter() {
-            self.set_path(path.as_str(), old_value.to_owned())
+            _ = self.set_shared_path(path.as_str(), old_value.clone(), true, true);
         }
     }
 
     // After setting a path, this method updates
     // the accumulator to set the old_value and the new_value
-    fn update_snapshot_accumulator(&mut self, path: &str, value: ValueType) {
-        let old_value: ValueType = self.snapshot_change_accumulator.old_values.get(path).unwrap_or(&self.get_path(path)).clone();
-        self.snapshot_change_accumulator.old_values.insert(path.to_owned(), old_value);
+    fn update_snapshot_accumulator(&mut self, path: &str, value: Arc<ValueType>) {
+        if !self.snapshot_change_accumulator.old_values.contains_key(path) {
+            let old_value = self.get_shared_value(path).unwrap_or_else(|| Arc::new(ValueType::none()));
+            self.snapshot_change_accumulator.old_values.insert(path.to_owned(), old_value);
+        }
         self.snapshot_change_accumulator.new_values.insert(path.to_owned(), value);
     }
 
@@ -487,6 +930,112 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
 
         let len = self.versions.len();
         self.current_version_index = Some(len as i32 - 1);
+
+        self.enforce_undo_history_limits();
+    }
+
+    /// Like `make_undo_redo_snapshot`, with a label describing the undo step, like "Duplicate".
+    pub fn make_labeled_undo_redo_snapshot(&mut self, label: &str) {
+        self.make_undo_redo_snapshot();
+        if let Some(snapshot) = self.snapshots.last_mut() {
+            snapshot.label = label.to_string();
+        }
+    }
+
+    /// Paths changed since the last snapshot.
+    pub fn pending_change_paths(&self) -> impl Iterator<Item = &String> {
+        return self.snapshot_change_accumulator.new_values.keys();
+    }
+
+    fn undo_step_label(&self, version_index: i32) -> Option<&str> {
+        if version_index < 1 {
+            // The first version is where history starts, not a step
+            return None;
+        }
+        let version = self.versions.get(version_index as usize)?;
+        return self.snapshots.iter()
+            .find(|snapshot| snapshot.version == *version)
+            .map(|snapshot| snapshot.label.as_str());
+    }
+
+    /// Label of the step `undo` would revert, if any.
+    pub fn undo_label(&self) -> Option<&str> {
+        let current_version_index = self.current_version_index.unwrap_or(self.versions.len() as i32 - 1);
+        return self.undo_step_label(current_version_index);
+    }
+
+    /// Label of the step `redo` would apply, if any.
+    pub fn redo_label(&self) -> Option<&str> {
+        let current_version_index = self.current_version_index.unwrap_or(self.versions.len() as i32 - 1);
+        return self.undo_step_label(current_version_index + 1);
+    }
+
+    /// Compacts the oldest undo steps until the history fits in `undo_history_limits`.
+    /// The current state always stays reachable.
+    pub fn enforce_undo_history_limits(&mut self) {
+        loop {
+            let steps = self.versions.len();
+            let too_many_steps = match self.undo_history_limits.max_steps {
+                Some(max_steps) => steps > max_steps.max(1),
+                None => false
+            };
+            let too_much_memory = match self.undo_history_limits.max_memory {
+                Some(max_memory) => self.estimate_undo_history_memory() > max_memory,
+                None => false
+            };
+
+            if !too_many_steps && !too_much_memory {
+                return;
+            }
+
+            if !self.compact_oldest_undo_step() {
+                return;
+            }
+        }
+    }
+
+    /// Merges the oldest undo step into the baseline.
+    /// The baseline is the oldest state we can go back to. Since we never revert past it,
+    /// it does not need to store any values.
+    /// Returns false if there was nothing to compact.
+    pub fn compact_oldest_undo_step(&mut self) -> bool {
+        let current_version_index = self.current_version_index.unwrap_or(self.versions.len() as i32 - 1);
+
+        if self.versions.len() < 2 || current_version_index < 1 {
+            return false;
+        }
+
+        let new_baseline_version = self.versions[1];
+        let baseline_position = match self.snapshots.iter().position(|snapshot| snapshot.version == new_baseline_version) {
+            Some(position) => position,
+            None => { return false; }
+        };
+
+        self.snapshots.drain(0..baseline_position);
+        self.snapshots[0].clear_values();
+        self.versions.remove(0);
+        self.current_version_index = Some(current_version_index - 1);
+
+        return true;
+    }
+
+    /// Estimates the memory used by snapshots (in bytes).
+    /// Values shared between snapshots are only counted once.
+    pub fn estimate_undo_history_memory(&self) -> usize {
+        let estimate_value_size = self.undo_history_limits.estimate_value_size;
+        let mut counted_values: HashSet<*const ValueType> = HashSet::new();
+        let mut total: usize = 0;
+
+        for snapshot in self.snapshots.iter().chain(std::iter::once(&self.snapshot_change_accumulator)) {
+            for (path, value) in snapshot.old_values.iter().chain(snapshot.new_values.iter()) {
+                total += path.len();
+                if counted_values.insert(Arc::as_ptr(value)) {
+                    total += estimate_value_size(value);
+                }
+            }
+        }
+
+        return total;
     }
 
     pub fn undo(&mut self) {
@@ -535,6 +1084,59 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
     }
 
 
+    /// Forgets every undo step, like when another document is opened.
+    /// The current state becomes the oldest state undo goes back to.
+    pub fn clear_undo_history(&mut self) {
+        self.snapshot_change_accumulator.clear();
+        self.snapshots.clear();
+        self.versions.clear();
+        self.current_version_index = None;
+        self.update_tracker.corresponding_previous_version = None;
+        self.make_undo_redo_snapshot();
+    }
+
+    /// Copies the undo/redo history so it can be saved with the document.
+    /// Values are shared with the tree, so this is cheap.
+    pub fn export_undo_history(&self) -> UndoHistory<ValueType> {
+        return UndoHistory {
+            snapshots: self.snapshots.clone(),
+            versions: self.versions.clone(),
+            current_version_index: self.current_version_index,
+            pending_changes: self.snapshot_change_accumulator.clone(),
+        };
+    }
+
+    /// Like `export_undo_history`, but undo steps only contain values at `path` or under it.
+    /// Steps are kept even when nothing is left in them, so the current step stays the same.
+    pub fn export_undo_history_under(&self, path: &str) -> UndoHistory<ValueType> {
+        let mut history = self.export_undo_history();
+        for snapshot in history.snapshots.iter_mut() {
+            snapshot.retain_values_under(path);
+        }
+        history.pending_changes.retain_values_under(path);
+        return history;
+    }
+
+    /// Replaces the undo/redo history with one previously exported.
+    /// The tree is expected to contain the values it had when the history was exported.
+    pub fn import_undo_history(&mut self, history: UndoHistory<ValueType>) {
+        let current_version = history.current_version_index
+            .and_then(|index| history.versions.get(index as usize))
+            .cloned();
+        let newest_version = history.snapshots.iter().map(|snapshot| snapshot.version).max().unwrap_or(0);
+
+        self.snapshots = history.snapshots;
+        self.versions = history.versions;
+        self.current_version_index = history.current_version_index;
+        self.snapshot_change_accumulator = history.pending_changes;
+        self.last_snapshot_version = self.snapshots.last().map(|snapshot| snapshot.version).unwrap_or(0);
+
+        // Snapshot versions are used to find snapshots: make sure new snapshots get newer versions
+        // than the imported ones.
+        self.update_tracker.version = self.update_tracker.version.max(newest_version);
+        self.update_tracker.corresponding_previous_version = current_version;
+    }
+
     pub fn dump_undo_state(&mut self) {
         let versions = &self.versions;
         let current_version_index = self.current_version_index;
@@ -545,10 +1147,42 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
         }
     }
 
+    ///  --------------------- OPERATION LOG ---------------------
+
+    /// Starts recording every write in `operation_log`.
+    /// The current content of the tree is recorded first, so that replaying the log
+    /// into an empty tree reproduces the session.
+    pub fn start_operation_log(&mut self, clock: fn() -> f64) {
+        let mut operation_log = OperationLog::new(clock);
+        let version = self.update_tracker.version;
+
+        for (key, subtree) in self.subtree.iter() {
+            operation_log.record(Operation::SetTree {
+                path: key.clone(),
+                tree: subtree.as_ref().clone(),
+            }, version);
+        }
+
+        self.operation_log = Some(operation_log);
+        self.last_operation_log = None;
+    }
+
+    /// Stops recording and returns the recorded operations.
+    /// They are also kept in `last_operation_log`, so they can still be saved.
+    pub fn stop_operation_log(&mut self) -> Option<OperationLog<ValueType>> {
+        self.last_operation_log = self.operation_log.take();
+        return self.last_operation_log.clone();
+    }
+
+    /// The log being recorded, or else the log of the last stopped recording.
+    pub fn recorded_operation_log(&self) -> Option<&OperationLog<ValueType>> {
+        return self.operation_log.as_ref().or(self.last_operation_log.as_ref());
+    }
+
     ///  --------------------- UPDATE NOTIFICATION MANAGEMENT ---------------------
 
-    fn notify_change(&mut self) {
-        self.update_tracker.notify_update();
+    fn notify_change(&mut self, version: u64) {
+        self.update_tracker.notify_update(version);
     }
 
     pub fn create_update_channel(&mut self) -> Receiver<Update<ValueType>> {
@@ -560,7 +1194,11 @@ impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueT
     pub fn reset_update_cycle(&mut self) {
         self.update_tracker.reset_update_cycle();
         for (_, node) in self.subtree.iter_mut() {
-            node.reset_update_cycle();
+            // Parents of updated nodes are always updated, so other subtrees can be skipped
+            // without copying them.
+            if node.was_updated() {
+                Arc::make_mut(node).reset_update_cycle();
+            }
         }
     }
 }
@@ -591,6 +1229,10 @@ impl CanBeNone<ExampleValueType> for ExampleValueType {
     fn none() -> ExampleValueType {
         return ExampleValueType::None;
     }
+
+    fn is_none_value(value: &ExampleValueType) -> bool {
+        return value.is_none();
+    }
 }
 
 impl Default for ExampleValueType {
@@ -600,6 +1242,15 @@ impl Default for ExampleValueType {
 }
 
 impl ExampleValueType {
+    /// Name of the variant, for schemas.
+    pub fn kind(&self) -> &'static str {
+        return match &self {
+            Self::I32(_) => "I32",
+            Self::F32(_) => "F32",
+            Self::None => "None",
+        };
+    }
+
     pub fn unwrap_i32(&self) -> i32 {
         match &self {
             Self::I32(value) => *value,
@@ -671,31 +1322,32 @@ mod tests {
         let scene = data.get_tree("scene").unwrap();
         let mut data2 = ObservableKVTree::<ExampleValueType>::default();
 
-        assert_eq!(data2.path_version("scene.some.very.deep.property"), -1);
-        assert_eq!(data2.path_version("scene.some.very.deep"), -1);
-        assert_eq!(data2.path_version("scene.some.very"), -1);
-        assert_eq!(data2.path_version("scene.some"), -1);
-        assert_eq!(data2.path_version("scene"), -1);
+        assert_eq!(data2.path_version("scene.some.very.deep.property"), None);
+        assert_eq!(data2.path_version("scene.some.very.deep"), None);
+        assert_eq!(data2.path_version("scene.some.very"), None);
+        assert_eq!(data2.path_version("scene.some"), None);
+        assert_eq!(data2.path_version("scene"), None);
 
         data2.set_tree("scene", scene.clone());
 
-        // TODO: I would expect this to start at 0
-        // Not so important because at least versions are increasing.
-        assert_eq!(data2.path_version("scene.some.very.deep.property"), 2);
-        assert_eq!(data2.path_version("scene.some.very.deep"), 1);
-        assert_eq!(data2.path_version("scene.some.very"), 1);
-        assert_eq!(data2.path_version("scene.some"), 1);
-        assert_eq!(data2.path_version("scene"), 1);
+        // Every node of the new subtree is stamped with the clock value of the write.
+        assert_eq!(data2.version(), 1);
+        assert_eq!(data2.path_version("scene.some.very.deep.property"), Some(1));
+        assert_eq!(data2.path_version("scene.some.very.deep"), Some(1));
+        assert_eq!(data2.path_version("scene.some.very"), Some(1));
+        assert_eq!(data2.path_version("scene.some"), Some(1));
+        assert_eq!(data2.path_version("scene"), Some(1));
 
         assert_eq!(data2.get_path("scene.some.very.deep.property").unwrap_i32(), 1234);
 
         data2.set_path("scene.some.very.deep", ExampleValueType::I32(5555));
 
-        assert_eq!(data2.path_version("scene.some.very.deep.property"), 2);
-        assert_eq!(data2.path_version("scene.some.very.deep"), 2);
-        assert_eq!(data2.path_version("scene.some.very"), 2);
-        assert_eq!(data2.path_version("scene.some"), 2);
-        assert_eq!(data2.path_version("scene"), 2);
+        assert_eq!(data2.path_version("scene.some.very.deep.property"), Some(1));
+        assert_eq!(data2.path_version("scene.some.very.deep"), Some(2));
+        assert_eq!(data2.path_version("scene.some.very"), Some(2));
+        assert_eq!(data2.path_version("scene.some"), Some(2));
+        assert_eq!(data2.path_version("scene"), Some(2));
+        assert_eq!(data2.version(), 2);
     }
 
     #[test]
@@ -718,6 +1370,42 @@ mod tests {
         assert_eq!(update.value.unwrap_i32(), 3456);
     }
 
+    #[test]
+    fn clearing_keeps_versions_increasing() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        let mut cursor = UpdateCursor::new();
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+        data.set_schema(Schema::new(ExampleValueType::kind).optional("scene.property", "I32")).unwrap();
+        assert!(cursor.take_change(&data, "scene"));
+        let version = data.version();
+
+        data.clear();
+        assert!(data.version() > version);
+        assert!(data.schema().is_none());
+        assert!(data.versions.is_empty());
+
+        // Without the schema, other kinds of values can be set
+        data.set_path("scene.property", ExampleValueType::from(2.0));
+        assert_eq!(data.get_path("scene.property").unwrap_f32(), 2.0);
+        assert!(data.path_version("scene").unwrap() > version);
+        assert!(cursor.take_change(&data, "scene"));
+    }
+
+    #[test]
+    fn it_forgets_dropped_update_channels() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        let receiver = data.create_update_channel();
+        for _ in 0..3 {
+            let _dropped_receiver = data.create_update_channel();
+        }
+        assert_eq!(data.update_listeners.len(), 4);
+
+        data.set_path("scene.property", ExampleValueType::from(1));
+        assert_eq!(data.update_listeners.len(), 1);
+        assert_eq!(receiver.recv().unwrap().value.unwrap_i32(), 1);
+    }
+
     #[test]
     fn it_gets_none_when_not_set() {
         let data = ObservableKVTree::<ExampleValueType>::default();
@@ -846,4 +1534,294 @@ mod tests {
         // Before this point, nothing is available for undo
         assert_eq!(data.get_path("scene.some.deep.property").unwrap_f32(), 123.4);
     }
+
+    #[test]
+    fn labels_undo_steps() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+        assert_eq!(data.undo_label(), None);
+
+        data.set_path("scene.property", ExampleValueType::from(2));
+        assert_eq!(data.pending_change_paths().collect::<Vec<&String>>(), vec!("scene.property"));
+        data.make_labeled_undo_redo_snapshot("Set property");
+        assert_eq!(data.pending_change_paths().count(), 0);
+        assert_eq!(data.undo_label(), Some("Set property"));
+        assert_eq!(data.redo_label(), None);
+
+        data.undo();
+        assert_eq!(data.undo_label(), None);
+        assert_eq!(data.redo_label(), Some("Set property"));
+    }
+
+    #[test]
+    fn performs_undo_after_new_changes() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.property", ExampleValueType::from(2));
+        data.make_undo_redo_snapshot();
+        data.undo();
+
+        data.set_path("scene.property", ExampleValueType::from(3));
+        data.make_undo_redo_snapshot();
+        data.undo();
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 1);
+        data.redo();
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 3);
+    }
+
+    #[test]
+    fn exports_undo_history_under_a_path() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.set_path("editor.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.property", ExampleValueType::from(2));
+        data.set_path("editor.property", ExampleValueType::from(2));
+        data.make_undo_redo_snapshot();
+
+        let history = data.export_undo_history_under("scene");
+        let paths: Vec<&String> = history.snapshots.iter()
+            .flat_map(|snapshot| snapshot.new_values.keys().chain(snapshot.old_values.keys()))
+            .collect();
+        assert!(!paths.is_empty());
+        assert!(paths.iter().all(|path| path.starts_with("scene.")));
+        assert_eq!(history.snapshots.len(), data.export_undo_history().snapshots.len());
+
+        let mut restored = ObservableKVTree::<ExampleValueType>::default();
+        restored.set_tree("scene", data.get_tree("scene").unwrap());
+        restored.import_undo_history(history);
+        restored.undo();
+        assert_eq!(restored.get_path("scene.property").unwrap_i32(), 1);
+        assert!(restored.get_path("editor.property").is_none());
+    }
+
+    #[test]
+    fn saves_and_restores_undo_history() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.property", ExampleValueType::from(2));
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.property", ExampleValueType::from(3));
+        data.make_undo_redo_snapshot();
+        data.undo();
+
+        let serialized_scene = serde_json::to_string(&data.get_tree("scene").unwrap()).unwrap();
+        let serialized_history = serde_json::to_string(&data.export_undo_history()).unwrap();
+
+        let mut restored = ObservableKVTree::<ExampleValueType>::default();
+        restored.set_tree("scene", serde_json::from_str(&serialized_scene).unwrap());
+        restored.import_undo_history(serde_json::from_str(&serialized_history).unwrap());
+        assert_eq!(restored.get_path("scene.property").unwrap_i32(), 2);
+
+        restored.undo();
+        assert_eq!(restored.get_path("scene.property").unwrap_i32(), 1);
+        restored.redo();
+        restored.redo();
+        assert_eq!(restored.get_path("scene.property").unwrap_i32(), 3);
+
+        // New changes still get their own undo step
+        restored.set_path("scene.property", ExampleValueType::from(4));
+        restored.make_undo_redo_snapshot();
+        restored.undo();
+        assert_eq!(restored.get_path("scene.property").unwrap_i32(), 3);
+    }
+
+    #[test]
+    fn compacts_undo_history_to_max_steps() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.undo_history_limits = UndoHistoryLimits {
+            max_steps: Some(3),
+            ..UndoHistoryLimits::default()
+        };
+
+        for i in 0..6 {
+            data.set_path("scene.some.deep.property", ExampleValueType::from(i));
+            data.make_undo_redo_snapshot();
+        }
+
+        assert_eq!(data.versions.len(), 3);
+
+        data.undo();
+        data.undo();
+        assert_eq!(data.get_path("scene.some.deep.property").unwrap_i32(), 3);
+        // The baseline can't be undone.
+        data.undo();
+        assert_eq!(data.get_path("scene.some.deep.property").unwrap_i32(), 3);
+
+        data.redo();
+        data.redo();
+        assert_eq!(data.get_path("scene.some.deep.property").unwrap_i32(), 5);
+    }
+
+    #[test]
+    fn compacts_undo_history_to_max_memory() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.undo_history_limits = UndoHistoryLimits {
+            max_memory: Some(5000),
+            estimate_value_size: |_value| 1000,
+            ..UndoHistoryLimits::default()
+        };
+
+        for i in 0..10 {
+            data.set_path("scene.property", ExampleValueType::from(i));
+            data.make_undo_redo_snapshot();
+        }
+
+        assert!(data.estimate_undo_history_memory() <= 5000);
+        assert!(data.versions.len() > 1);
+
+        data.undo();
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 8);
+    }
+
+    #[test]
+    fn snapshots_share_values() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.property", ExampleValueType::from(2));
+        data.make_undo_redo_snapshot();
+
+        let previous_new_value = &data.snapshots[0].new_values["scene.property"];
+        let next_old_value = &data.snapshots[1].old_values["scene.property"];
+        let stored_value = data.get_shared_value("scene.property").unwrap();
+
+        assert!(Arc::ptr_eq(previous_new_value, next_old_value));
+        assert!(Arc::ptr_eq(&data.snapshots[1].new_values["scene.property"], &stored_value));
+    }
+
+    #[test]
+    fn it_clears_undo_history() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+
+        data.set_path("scene.property", ExampleValueType::from(2));
+        data.clear_undo_history();
+        data.undo();
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 2);
+
+        data.set_path("scene.property", ExampleValueType::from(3));
+        data.make_undo_redo_snapshot();
+        data.undo();
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 2);
+    }
+
+    #[test]
+    fn setting_none_removes_nodes() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.objects.a", ExampleValueType::from(1));
+        data.set_path("scene.objects.b", ExampleValueType::from(2));
+        data.make_undo_redo_snapshot();
+        data.set_path("scene.objects.a", ExampleValueType::None);
+        data.make_undo_redo_snapshot();
+
+        let keys: Vec<&str> = data.children("scene.objects").map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!("b"));
+
+        data.undo();
+        assert_eq!(data.get_path("scene.objects.a").unwrap_i32(), 1);
+        data.redo();
+        assert!(data.get_ref("scene.objects.a").is_none());
+
+        // Undoing the creation removes the nodes too
+        data.undo();
+        data.undo();
+        assert_eq!(data.children("scene.objects").count(), 0);
+
+        // Nodes with children are kept
+        data.set_path("scene", ExampleValueType::None);
+        assert!(data.get_ref("scene").is_some());
+    }
+
+    #[test]
+    fn editing_one_object_stores_one_object() {
+        // Objects stored one per path, like the scene of claydash.
+        const OBJECT_SIZE: usize = 1000;
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.undo_history_limits = UndoHistoryLimits {
+            estimate_value_size: |_value| OBJECT_SIZE,
+            ..UndoHistoryLimits::default()
+        };
+
+        for i in 0..100 {
+            data.set_path(&format!("scene.objects.{}", i), ExampleValueType::from(i));
+        }
+        data.make_undo_redo_snapshot();
+
+        for edit in 0..10 {
+            let memory_before = data.estimate_undo_history_memory();
+            data.set_path("scene.objects.42", ExampleValueType::from(1000 + edit));
+            data.make_undo_redo_snapshot();
+            let growth = data.estimate_undo_history_memory() - memory_before;
+
+            // The new value, and the path in both snapshot maps
+            assert!(growth >= OBJECT_SIZE && growth < OBJECT_SIZE + 100, "grew by {}", growth);
+        }
+    }
+
+    #[test]
+    fn it_borrows_values() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.property", ExampleValueType::from(1));
+
+        assert_eq!(data.get_ref("scene.property").unwrap().unwrap_i32(), 1);
+        assert!(data.get_ref("scene.missing").is_none());
+        assert!(data.get_tree_ref("scene").unwrap().get_ref("property").is_some());
+    }
+
+    #[test]
+    fn it_tracks_changes_made_with_get_mut() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+        data.reset_update_cycle();
+        let receiver = data.create_update_channel();
+        let initial_version = data.path_version("scene.property");
+
+        *data.get_mut("scene.property") = ExampleValueType::from(2);
+        data.make_undo_redo_snapshot();
+
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 2);
+        assert!(data.was_path_updated("scene.property"));
+        assert!(data.path_version("scene.property") > initial_version);
+        assert_eq!(receiver.try_recv().unwrap().value.unwrap_i32(), 2);
+
+        data.undo();
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 1);
+
+        // Only reading through the guard is not a write
+        data.reset_update_cycle();
+        let version = data.path_version("scene.property");
+        assert_eq!(data.get_mut("scene.property").unwrap_i32(), 1);
+        assert!(!data.was_path_updated("scene.property"));
+        assert_eq!(data.path_version("scene.property"), version);
+    }
+
+    #[test]
+    fn clones_share_subtrees_until_written() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.a.property", ExampleValueType::from(1));
+        data.set_path("scene.b.property", ExampleValueType::from(2));
+        data.reset_update_cycle();
+
+        let clone = data.clone();
+        data.set_path("scene.a.property", ExampleValueType::from(3));
+
+        assert_eq!(clone.get_path("scene.a.property").unwrap_i32(), 1);
+        assert_eq!(data.get_path("scene.a.property").unwrap_i32(), 3);
+        // The subtree that was not written is still shared.
+        assert!(std::ptr::eq(clone.get_tree_ref("scene.b").unwrap(), data.get_tree_ref("scene.b").unwrap()));
+        assert!(!std::ptr::eq(clone.get_tree_ref("scene.a").unwrap(), data.get_tree_ref("scene.a").unwrap()));
+    }
 }
@@ -0,0 +1,262 @@
+//! Append-only log of every write made to an ObservableKVTree.
+//!
+//! The log can be written to disk as JSON lines and replayed into a fresh tree
+//! to reproduce a session exactly. This is useful to reproduce bugs and to render
+//! time-lapses of modeling sessions: `replay_until` applies operations at the pace
+//! they were recorded.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType,OperationLog};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! data.start_operation_log(|| 0.0);
+//! data.set_path("scene.some.property", ExampleValueType::from(1234));
+//!
+//! let json_lines = data.operation_log.as_ref().unwrap().to_json_lines().unwrap();
+//! let log = OperationLog::<ExampleValueType>::from_json_lines(&json_lines).unwrap();
+//!
+//! let mut replayed = ObservableKVTree::<ExampleValueType>::default();
+//! log.replay(&mut replayed);
+//! assert_eq!(replayed.get_path("scene.some.property").unwrap_i32(), 1234);
+//! ```
+
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::sync::Arc;
+
+use crate::{ObservableKVTree, CanBeNone};
+
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub enum Operation<ValueType: Default + Clone + CanBeNone<ValueType>> {
+    SetPath {
+        path: String,
+        old_value: Arc<ValueType>,
+        value: Arc<ValueType>,
+        /// False when the value was set with `set_path_without_notifying`.
+        notified: bool,
+    },
+    SetTree {
+        path: String,
+        tree: ObservableKVTree<ValueType>,
+    },
+}
+
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct LoggedOperation<ValueType: Default + Clone + CanBeNone<ValueType>> {
+    pub operation: Operation<ValueType>,
+    /// Root version of the tree after the operation.
+    pub version: u64,
+    /// Time of the operation, as given by the log's clock.
+    pub timestamp: f64,
+}
+
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct OperationLog<ValueType: Default + Clone + CanBeNone<ValueType>> {
+    pub operations: Vec<LoggedOperation<ValueType>>,
+    /// Returns the current time in seconds.
+    /// The tree does not know about time, so the application provides the clock.
+    #[serde(skip, default = "default_clock")]
+    clock: fn() -> f64,
+}
+
+fn default_clock() -> fn() -> f64 {
+    return || 0.0;
+}
+
+impl<ValueType: Default + Clone + CanBeNone<ValueType>> Default for OperationLog<ValueType> {
+    fn default() -> Self {
+        return Self::new(default_clock());
+    }
+}
+
+impl<ValueType: Default + Clone + CanBeNone<ValueType>> OperationLog<ValueType> {
+    pub fn new(clock: fn() -> f64) -> Self {
+        return Self {
+            operations: Vec::new(),
+            clock,
+        };
+    }
+
+    pub fn record(&mut self, operation: Operation<ValueType>, version: u64) {
+        self.operations.push(LoggedOperation {
+            operation,
+            version,
+            timestamp: (self.clock)(),
+        });
+    }
+
+    /// Applies all operations to the tree, in order.
+    pub fn replay(&self, tree: &mut ObservableKVTree<ValueType>) {
+        self.replay_until(tree, 0, f64::INFINITY);
+    }
+
+    /// Applies operations from index `start` that were recorded at most `elapsed` seconds
+    /// after the first operation of the log.
+    /// Returns the index of the next operation to apply, to continue the replay later.
+    pub fn replay_until(&self, tree: &mut ObservableKVTree<ValueType>, start: usize, elapsed: f64) -> usize {
+        let first_timestamp = match self.operations.first() {
+            Some(logged_operation) => logged_operation.timestamp,
+            None => { return 0; }
+        };
+        let mut next = start;
+
+        for logged_operation in self.operations.iter().skip(start) {
+            if logged_operation.timestamp - first_timestamp > elapsed {
+                break;
+            }
+            match &logged_operation.operation {
+                Operation::SetPath { path, value, notified, .. } => {
+                    match notified {
+                        true => tree.set_path(path, value.as_ref().clone()),
+                        false => tree.set_path_without_notifying(path, value.as_ref().clone()),
+                    }
+                },
+                Operation::SetTree { path, tree: subtree } => {
+                    tree.set_tree(path, subtree.clone());
+                },
+            }
+            next += 1;
+        }
+
+        return next;
+    }
+
+    /// True once `replay_until` returned this index.
+    pub fn is_replayed(&self, next: usize) -> bool {
+        return next >= self.operations.len();
+    }
+}
+
+impl<ValueType: Default + Clone + CanBeNone<ValueType> + Serialize + DeserializeOwned> OperationLog<ValueType> {
+    /// One operation per line, so new operations can be appended to a file.
+    /// Use `start` to only serialize operations that were not written yet.
+    pub fn to_json_lines_from(&self, start: usize) -> Result<String, serde_json::Error> {
+        let mut json_lines = String::new();
+
+        for logged_operation in self.operations.iter().skip(start) {
+            json_lines += &serde_json::to_string(logged_operation)?;
+            json_lines += "\n";
+        }
+
+        return Ok(json_lines);
+    }
+
+    pub fn to_json_lines(&self) -> Result<String, serde_json::Error> {
+        return self.to_json_lines_from(0);
+    }
+
+    pub fn from_json_lines(json_lines: &str) -> Result<Self, serde_json::Error> {
+        let mut log = Self::default();
+
+        for line in json_lines.lines().filter(|line| !line.trim().is_empty()) {
+            log.operations.push(serde_json::from_str(line)?);
+        }
+
+        return Ok(log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+
+    #[test]
+    fn it_records_updates() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.start_operation_log(|| 12.5);
+        data.set_path("scene.property", ExampleValueType::from(2));
+
+        let log = data.operation_log.as_ref().unwrap();
+        let last_operation = log.operations.last().unwrap();
+
+        match &last_operation.operation {
+            Operation::SetPath { path, old_value, value, notified } => {
+                assert_eq!(path, "scene.property");
+                assert_eq!(old_value.unwrap_i32(), 1);
+                assert_eq!(value.unwrap_i32(), 2);
+                assert!(notified);
+            },
+            _ => panic!("Expected a SetPath operation."),
+        }
+        assert_eq!(Some(last_operation.version), data.path_version("scene"));
+        assert_eq!(last_operation.timestamp, 12.5);
+    }
+
+    #[test]
+    fn it_keeps_the_stopped_log() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        assert!(data.recorded_operation_log().is_none());
+
+        data.start_operation_log(|| 0.0);
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.stop_operation_log();
+        data.set_path("scene.property", ExampleValueType::from(2));
+        assert!(data.operation_log.is_none());
+        let stopped_log = data.recorded_operation_log().unwrap();
+        assert_eq!(stopped_log.operations.len(), 1);
+        assert!(matches!(stopped_log.operations[0].operation, Operation::SetPath { .. }));
+
+        // The new recording starts with the current content of the tree
+        data.start_operation_log(|| 0.0);
+        assert!(data.last_operation_log.is_none());
+        let new_log = data.recorded_operation_log().unwrap();
+        assert!(matches!(new_log.operations[0].operation, Operation::SetTree { .. }));
+    }
+
+    #[test]
+    fn it_replays_at_the_recorded_pace() {
+        let mut log = OperationLog::<ExampleValueType>::default();
+        for (index, timestamp) in [10.0, 10.5, 12.0].iter().enumerate() {
+            log.operations.push(LoggedOperation {
+                operation: Operation::SetPath {
+                    path: "scene.property".to_string(),
+                    old_value: Arc::new(ExampleValueType::default()),
+                    value: Arc::new(ExampleValueType::from(index as i32)),
+                    notified: true,
+                },
+                version: index as u64,
+                timestamp: *timestamp,
+            });
+        }
+
+        let mut replayed = ObservableKVTree::<ExampleValueType>::default();
+        let next = log.replay_until(&mut replayed, 0, 1.0);
+        assert_eq!(next, 2);
+        assert_eq!(replayed.get_path("scene.property").unwrap_i32(), 1);
+        assert!(!log.is_replayed(next));
+
+        let next = log.replay_until(&mut replayed, next, 2.0);
+        assert_eq!(next, 3);
+        assert_eq!(replayed.get_path("scene.property").unwrap_i32(), 2);
+        assert!(log.is_replayed(next));
+    }
+
+    #[test]
+    fn it_replays_a_session() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.before_recording", ExampleValueType::from(1));
+        data.start_operation_log(|| 0.0);
+
+        data.set_path("scene.a", ExampleValueType::from(1.0));
+        data.make_undo_redo_snapshot();
+        data.set_path_without_notifying("scene.a", ExampleValueType::from(2.0));
+        data.set_path("scene.b.c", ExampleValueType::from(3));
+        data.make_undo_redo_snapshot();
+        data.undo();
+
+        let mut loaded_scene = ObservableKVTree::<ExampleValueType>::default();
+        loaded_scene.set_path("loaded", ExampleValueType::from(4));
+        data.set_tree("other", loaded_scene);
+
+        let json_lines = data.operation_log.as_ref().unwrap().to_json_lines().unwrap();
+        let log = OperationLog::<ExampleValueType>::from_json_lines(&json_lines).unwrap();
+
+        let mut replayed = ObservableKVTree::<ExampleValueType>::default();
+        log.replay(&mut replayed);
+
+        assert_eq!(
+            serde_json::to_string(&replayed).unwrap(),
+            serde_json::to_string(&data).unwrap()
+        );
+    }
+}
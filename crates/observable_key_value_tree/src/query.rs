@@ -0,0 +1,384 @@
+//! Iterating over and searching the tree.
+//!
+//!  - `data.children(path)`: direct children of a node, in key order.
+//!  - `data.walk(path)`: every node under a path, depth-first, with their full path.
+//!  - `data.query(pattern)`: nodes whose path matches a pattern.
+//!  - `data.query_where(pattern, predicate)`: same, keeping only nodes whose value matches.
+//!  - `data.query_filtered(query, kind_of)`: same, with the filter written in the query,
+//!    for callers that can't pass a closure, like scripts.
+//!  - `data.changed_since(path, version)`: nodes written after a version of the tree.
+//!
+//! In patterns, `*` matches any one part of a path and `**` matches any number of parts,
+//! including none.
+//!
+//! Filters are written after the pattern, in brackets:
+//!  - `scene.**[kind=F32]`: values of this kind (as returned by `kind_of`).
+//!  - `scene.**[kind!=None]`: values of any other kind.
+//!  - `scene.**[value=2.0]`: values equal to this JSON. Enum values also match their content,
+//!    so `2.0` matches `{"F32": 2.0}`.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! data.set_path("scene.objects.a.size", ExampleValueType::from(1.0));
+//! data.set_path("scene.objects.b.size", ExampleValueType::from(2.0));
+//! data.set_path("scene.objects.b.parts.c.size", ExampleValueType::from(3.0));
+//!
+//! let keys: Vec<&str> = data.children("scene.objects").map(|(key, _)| key).collect();
+//! assert_eq!(keys, vec!("a", "b"));
+//!
+//! let sizes: Vec<String> = data.query("scene.objects.*.size").into_iter().map(|(path, _)| path).collect();
+//! assert_eq!(sizes, vec!("scene.objects.a.size", "scene.objects.b.size"));
+//!
+//! let all_sizes = data.query("scene.**.size");
+//! assert_eq!(all_sizes.len(), 3);
+//!
+//! let large = data.query_where("scene.**.size", |value| match value {
+//!     ExampleValueType::F32(size) => *size > 1.5,
+//!     _ => false
+//! });
+//! assert_eq!(large.len(), 2);
+//!
+//! let floats = data.query_filtered("scene.**[kind=F32]", ExampleValueType::kind).unwrap();
+//! assert_eq!(floats.len(), 3);
+//! let large = data.query_filtered("scene.**[value=2.0]", ExampleValueType::kind).unwrap();
+//! assert_eq!(large[0].0, "scene.objects.b.size");
+//! ```
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{ObservableKVTree, CanBeNone, TreeError};
+
+/// Filter written after the pattern of a query. See `ObservableKVTree::query_filtered`.
+#[derive(Clone,Debug,PartialEq)]
+pub enum ValueFilter {
+    Kind(String),
+    NotKind(String),
+    Value(Value),
+}
+
+impl ValueFilter {
+    fn matches<ValueType: Serialize>(&self, value: &ValueType, kind_of: fn(&ValueType) -> &'static str) -> bool {
+        return match self {
+            ValueFilter::Kind(kind) => kind_of(value) == kind,
+            ValueFilter::NotKind(kind) => kind_of(value) != kind,
+            ValueFilter::Value(expected) => {
+                let value = match serde_json::to_value(value) {
+                    Ok(value) => value,
+                    Err(_) => { return false; }
+                };
+                // Externally tagged enum values, like {"F32": 2.0}, also match their content.
+                let content = match &value {
+                    Value::Object(map) if map.len() == 1 => map.values().next(),
+                    _ => None
+                };
+                value == *expected || content == Some(expected)
+            }
+        };
+    }
+}
+
+/// Splits a query into its pattern and its optional filter, like `scene.**` and `[kind=F32]`.
+pub fn parse_query(query: &str) -> Result<(&str, Option<ValueFilter>), TreeError> {
+    let invalid = |reason: &str| TreeError::InvalidQuery(format!("{} ({})", query, reason));
+
+    if !query.ends_with(']') {
+        return Ok((query, None));
+    }
+    let start = query.find('[').ok_or_else(|| invalid("missing ["))?;
+    let (pattern, filter) = (&query[..start], &query[start + 1..query.len() - 1]);
+
+    let filter = match filter.split_once("!=") {
+        Some(("kind", kind)) => ValueFilter::NotKind(kind.trim().to_string()),
+        Some(_) => { return Err(invalid("only kind can be compared with !=")); },
+        None => match filter.split_once('=') {
+            Some(("kind", kind)) => ValueFilter::Kind(kind.trim().to_string()),
+            Some(("value", value)) => {
+                ValueFilter::Value(serde_json::from_str(value).map_err(|_| invalid("value is not JSON"))?)
+            },
+            _ => { return Err(invalid("expected kind=, kind!= or value=")); }
+        }
+    };
+
+    return Ok((pattern, Some(filter)));
+}
+
+/// True if every part of the path matches the pattern.
+/// `*` matches any one part, `**` matches any number of parts.
+pub fn path_matches_pattern(path: &str, pattern: &str) -> bool {
+    let path_parts: Vec<&str> = path.split(".").collect();
+    let pattern_parts: Vec<&str> = pattern.split(".").collect();
+    return parts_match_pattern(&path_parts, &pattern_parts);
+}
+
+fn parts_match_pattern(path_parts: &[&str], pattern_parts: &[&str]) -> bool {
+    let (pattern_part, other_pattern_parts) = match pattern_parts.split_first() {
+        Some(split) => split,
+        None => { return path_parts.is_empty(); }
+    };
+
+    if *pattern_part == "**" {
+        // Try matching zero parts, then one more part at a time.
+        for skipped in 0..=path_parts.len() {
+            if parts_match_pattern(&path_parts[skipped..], other_pattern_parts) {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    return match path_parts.split_first() {
+        Some((path_part, other_path_parts)) => {
+            (*pattern_part == "*" || pattern_part == path_part) &&
+                parts_match_pattern(other_path_parts, other_pattern_parts)
+        },
+        None => false
+    };
+}
+
+fn join_path(prefix: &str, key: &str) -> String {
+    return match prefix.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", prefix, key)
+    };
+}
+
+/// Depth-first iterator over the nodes of a subtree. See `ObservableKVTree::walk`.
+pub struct Walk<'a, ValueType: Default + Clone + CanBeNone<ValueType>> {
+    stack: Vec<(String, &'a ObservableKVTree<ValueType>)>,
+}
+
+impl<'a, ValueType: Default + Clone + CanBeNone<ValueType>> Iterator for Walk<'a, ValueType> {
+    type Item = (String, &'a ObservableKVTree<ValueType>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, node) = self.stack.pop()?;
+
+        // Pushed in reverse, so children are visited in key order.
+        for (key, child) in node.subtree.iter().rev() {
+            self.stack.push((join_path(&path, key), child.as_ref()));
+        }
+
+        return Some((path, node));
+    }
+}
+
+impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueType> {
+    /// Value of this node.
+    pub fn value(&self) -> &ValueType {
+        return self.value.as_ref();
+    }
+
+    /// Direct children of the node at given path, with their key, in key order.
+    /// Empty if there is no node at this path.
+    pub fn children<'a>(&'a self, path: &str) -> impl Iterator<Item = (&'a str, &'a ObservableKVTree<ValueType>)> {
+        let node = match path.is_empty() {
+            true => Some(self),
+            false => self.get_node(path)
+        };
+        return node.into_iter().flat_map(|node| {
+            node.subtree.iter().map(|(key, child)| (key.as_str(), child.as_ref()))
+        });
+    }
+
+    /// Every node under given path, depth-first, with its full path.
+    /// The node at `path` itself is not included. An empty path walks the whole tree.
+    pub fn walk(&self, path: &str) -> Walk<'_, ValueType> {
+        let mut walk = Walk { stack: Vec::new() };
+        for (key, child) in self.children(path).collect::<Vec<_>>().into_iter().rev() {
+            walk.stack.push((join_path(path, key), child));
+        }
+        return walk;
+    }
+
+    /// Nodes whose path matches the pattern, in depth-first order.
+    pub fn query(&self, pattern: &str) -> Vec<(String, &ObservableKVTree<ValueType>)> {
+        return self.query_where(pattern, |_| true);
+    }
+
+    /// Nodes whose path matches the pattern and whose value matches the predicate.
+    pub fn query_where(
+        &self,
+        pattern: &str,
+        predicate: impl Fn(&ValueType) -> bool
+    ) -> Vec<(String, &ObservableKVTree<ValueType>)> {
+        // Only walk the part of the tree below the pattern's first wildcard.
+        let parts: Vec<&str> = pattern.split(".").collect();
+        let literal_parts = parts.iter().take_while(|part| **part != "*" && **part != "**").count();
+        let mut results: Vec<(String, &ObservableKVTree<ValueType>)> = Vec::new();
+
+        if literal_parts == parts.len() {
+            if let Some(node) = self.get_node(pattern) {
+                if predicate(node.value()) {
+                    results.push((pattern.to_string(), node));
+                }
+            }
+            return results;
+        }
+
+        let prefix = parts[..literal_parts].join(".");
+        // `walk` only lists children, but "scene.**" also matches "scene".
+        if !prefix.is_empty() && path_matches_pattern(&prefix, pattern) {
+            if let Some(node) = self.get_node(&prefix) {
+                if predicate(node.value()) {
+                    results.push((prefix.clone(), node));
+                }
+            }
+        }
+        for (path, node) in self.walk(&prefix) {
+            if path_matches_pattern(&path, pattern) && predicate(node.value()) {
+                results.push((path, node));
+            }
+        }
+
+        return results;
+    }
+
+    /// Nodes under given path written after `version`, in depth-first order.
+    /// Parents of written nodes are included, since writing a child stamps its parents.
+    /// Removed nodes are not listed.
+    pub fn changed_since(&self, path: &str, version: u64) -> Vec<(String, &ObservableKVTree<ValueType>)> {
+        let mut results: Vec<(String, &ObservableKVTree<ValueType>)> = Vec::new();
+        let mut stack: Vec<(String, &ObservableKVTree<ValueType>)> = self.children(path)
+            .map(|(key, child)| (join_path(path, key), child))
+            .collect();
+        stack.reverse();
+
+        while let Some((path, node)) = stack.pop() {
+            // Parents are stamped with their latest change, so older subtrees are skipped.
+            if node.update_tracker.version() <= version {
+                continue;
+            }
+            for (key, child) in node.subtree.iter().rev() {
+                stack.push((join_path(&path, key), child.as_ref()));
+            }
+            results.push((path, node));
+        }
+
+        return results;
+    }
+}
+
+impl <ValueType: Default + Clone + CanBeNone<ValueType> + Serialize> ObservableKVTree<ValueType> {
+    /// Like `query_where`, with the filter written after the pattern: `scene.**[kind=F32]`.
+    /// `kind_of` names the kind of a value, like the kind function of a schema.
+    pub fn query_filtered(
+        &self,
+        query: &str,
+        kind_of: fn(&ValueType) -> &'static str
+    ) -> Result<Vec<(String, &ObservableKVTree<ValueType>)>, TreeError> {
+        return Ok(match parse_query(query)? {
+            (pattern, Some(filter)) => self.query_where(pattern, |value| filter.matches(value, kind_of)),
+            (pattern, None) => self.query(pattern),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+
+    fn example_tree() -> ObservableKVTree<ExampleValueType> {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.objects.b.size", ExampleValueType::from(2.0));
+        data.set_path("scene.objects.a.size", ExampleValueType::from(1.0));
+        data.set_path("scene.objects.a.count", ExampleValueType::from(1));
+        data.set_path("editor.count", ExampleValueType::from(1));
+        return data;
+    }
+
+    #[test]
+    fn it_matches_wildcards() {
+        assert!(path_matches_pattern("scene.a.size", "scene.*.size"));
+        assert!(path_matches_pattern("scene.size", "scene.**.size"));
+        assert!(path_matches_pattern("scene.a.b.size", "scene.**.size"));
+        assert!(path_matches_pattern("scene.a.b", "scene.**"));
+        assert!(path_matches_pattern("scene.a.b.size", "**.size"));
+        assert!(!path_matches_pattern("scene.a.b.count", "scene.**.size"));
+        assert!(!path_matches_pattern("editor.a.size", "scene.**.size"));
+    }
+
+    #[test]
+    fn it_iterates_over_children() {
+        let data = example_tree();
+        let keys: Vec<&str> = data.children("").map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!("editor", "scene"));
+        assert_eq!(data.children("missing").count(), 0);
+    }
+
+    #[test]
+    fn it_walks_depth_first() {
+        let data = example_tree();
+        let paths: Vec<String> = data.walk("scene").map(|(path, _)| path).collect();
+        assert_eq!(paths, vec!(
+            "scene.objects",
+            "scene.objects.a",
+            "scene.objects.a.count",
+            "scene.objects.a.size",
+            "scene.objects.b",
+            "scene.objects.b.size",
+        ));
+        assert_eq!(data.walk("").count(), 9);
+    }
+
+    #[test]
+    fn it_queries_paths_and_values() {
+        let data = example_tree();
+
+        let paths: Vec<String> = data.query("**.count").into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, vec!("editor.count", "scene.objects.a.count"));
+
+        let paths: Vec<String> = data.query("scene.objects.*").into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, vec!("scene.objects.a", "scene.objects.b"));
+
+        assert_eq!(data.query("scene.objects.a.size").len(), 1);
+        assert_eq!(data.query("scene.missing").len(), 0);
+
+        // `**` matches zero parts, so the prefix itself is included.
+        let paths: Vec<String> = data.query("scene.objects.**").into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths[0], "scene.objects");
+        assert_eq!(paths.len(), 6);
+
+        let sizes = data.query_where("scene.**", |value| matches!(value, ExampleValueType::F32(_)));
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes[1].1.value().unwrap_f32(), 2.0);
+    }
+
+    #[test]
+    fn it_filters_queries_by_value() {
+        let data = example_tree();
+        let paths = |query: &str| -> Vec<String> {
+            return data.query_filtered(query, ExampleValueType::kind).unwrap()
+                .into_iter().map(|(path, _)| path).collect();
+        };
+
+        assert_eq!(paths("**[kind=I32]"), vec!("editor.count", "scene.objects.a.count"));
+        assert_eq!(paths("scene.objects.a.*[kind!=F32]"), vec!("scene.objects.a.count"));
+        assert_eq!(paths("scene.objects.*[kind!=None]"), Vec::<String>::new());
+        assert_eq!(paths("scene.**[value=2.0]"), vec!("scene.objects.b.size"));
+        assert_eq!(paths("scene.**[value={\"I32\":1}]"), vec!("scene.objects.a.count"));
+        assert_eq!(paths("scene.objects.*.size").len(), 2);
+
+        assert!(data.query_filtered("scene.**[size=2]", ExampleValueType::kind).is_err());
+        assert!(data.query_filtered("scene.**[value=oops]", ExampleValueType::kind).is_err());
+        assert!(data.query_filtered("scene.**]", ExampleValueType::kind).is_err());
+    }
+
+    #[test]
+    fn it_lists_changes_since_a_version() {
+        let mut data = example_tree();
+        let version = data.version();
+        data.set_path("scene.objects.b.size", ExampleValueType::from(3.0));
+        data.set_path("editor.count", ExampleValueType::from(2));
+
+        let paths: Vec<String> = data.changed_since("", version).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, vec!("editor", "editor.count", "scene", "scene.objects", "scene.objects.b", "scene.objects.b.size"));
+
+        let paths: Vec<String> = data.changed_since("scene", version).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, vec!("scene.objects", "scene.objects.b", "scene.objects.b.size"));
+
+        assert_eq!(data.changed_since("", data.version()).len(), 0);
+    }
+}
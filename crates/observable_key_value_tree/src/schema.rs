@@ -0,0 +1,426 @@
+//! Optional schema describing which kind of value each path holds.
+//!
+//! Rules map a path pattern to the expected kind of value. In patterns, `*` matches any one
+//! part of a path, so `editor.objects.*.transform` matches `editor.objects.1234.transform`,
+//! and `**` matches any number of parts (see `path_matches_pattern`).
+//! The first matching rule applies. Paths without a rule are not checked.
+//!
+//! The tree does not know about value kinds, so the schema is given a function returning
+//! the kind of a value (typically the name of an enum variant).
+//!
+//! Once a schema is set with `set_schema`, `set_path` and `set_tree` refuse values of the wrong
+//! kind, and missing required values are filled with their default.
+//! Their errors are kept until `take_schema_errors` is called, `try_set_path` and `try_set_tree`
+//! return them instead.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType,Schema};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! let schema = Schema::new(ExampleValueType::kind)
+//!     .required("scene.count", "I32", ExampleValueType::from(0))
+//!     .optional("scene.objects.*.ratio", "F32");
+//! data.set_schema(schema).unwrap();
+//!
+//! // Defaults are filled
+//! assert_eq!(data.get_path("scene.count").unwrap_i32(), 0);
+//! // Wrong kinds are refused
+//! assert!(data.try_set_path("scene.objects.1.ratio", ExampleValueType::from(1)).is_err());
+//! assert!(data.get_path("scene.objects.1.ratio").is_none());
+//! // Typed getters return errors instead of panicking
+//! assert_eq!(data.get_as::<i32>("scene.count"), Ok(0));
+//! assert!(data.get_as::<f32>("scene.count").is_err());
+//! // Borrowing typed getters
+//! assert_eq!(data.get_ref_as::<i32>("scene.count"), Ok(&0));
+//! ```
+
+use std::sync::Arc;
+
+use crate::{ObservableKVTree, CanBeNone, TreeValue, TreeValueRef, path_matches_pattern};
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum TreeError {
+    /// No value is stored at this path.
+    Missing(String),
+    /// The stored value can't be converted to the requested type.
+    WrongType { path: String, expected: &'static str },
+    /// The value does not have the kind expected by the schema.
+    WrongKind { path: String, expected: &'static str, found: &'static str },
+    /// The path holds a computed value, which can't be written.
+    Computed(String),
+    /// The query can't be parsed. See `query_filtered`.
+    InvalidQuery(String),
+    /// The computed value would depend on itself. See `add_computed`.
+    ComputedCycle(String),
+}
+
+impl std::fmt::Display for TreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeError::Missing(path) => write!(f, "No value stored at {}", path),
+            TreeError::WrongType { path, expected } => write!(f, "Value at {} is not a {}", path, expected),
+            TreeError::WrongKind { path, expected, found } => {
+                write!(f, "Value at {} should be {}, found {}", path, expected, found)
+            },
+            TreeError::Computed(path) => write!(f, "Value at {} is computed and can't be set", path),
+            TreeError::InvalidQuery(reason) => write!(f, "Invalid query {}", reason),
+            TreeError::ComputedCycle(path) => write!(f, "Value at {} would be computed from itself", path),
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct SchemaRule<ValueType> {
+    pub pattern: String,
+    pub kind: &'static str,
+    /// Required values are filled with this default when missing.
+    pub default: Option<ValueType>,
+}
+
+#[derive(Clone,Debug)]
+pub struct Schema<ValueType> {
+    kind_of: fn(&ValueType) -> &'static str,
+    rules: Vec<SchemaRule<ValueType>>,
+}
+
+impl<ValueType: Default + Clone + CanBeNone<ValueType>> Schema<ValueType> {
+    pub fn new(kind_of: fn(&ValueType) -> &'static str) -> Self {
+        return Self {
+            kind_of,
+            rules: Vec::new(),
+        };
+    }
+
+    /// Values at matching paths may be missing, but must have this kind when set.
+    pub fn optional(mut self, pattern: &str, kind: &'static str) -> Self {
+        self.rules.push(SchemaRule {
+            pattern: pattern.to_string(),
+            kind,
+            default: None,
+        });
+        return self;
+    }
+
+    /// Values at matching paths must have this kind, and are set to `default` when missing.
+    /// With a `*` in the pattern, the value is required for every existing match of the parent.
+    /// Defaults are not filled for patterns containing `**`.
+    pub fn required(mut self, pattern: &str, kind: &'static str, default: ValueType) -> Self {
+        self.rules.push(SchemaRule {
+            pattern: pattern.to_string(),
+            kind,
+            default: Some(default),
+        });
+        return self;
+    }
+
+    pub fn rules(&self) -> &[SchemaRule<ValueType>] {
+        return &self.rules;
+    }
+
+    pub fn rule_for(&self, path: &str) -> Option<&SchemaRule<ValueType>> {
+        return self.rules.iter().find(|rule| path_matches_pattern(path, &rule.pattern));
+    }
+
+    pub fn kind_of(&self, value: &ValueType) -> &'static str {
+        return (self.kind_of)(value);
+    }
+
+    /// Checks the kind of a value about to be stored at given path.
+    /// Removing an optional value (setting it to none) is allowed.
+    pub fn check(&self, path: &str, value: &ValueType) -> Result<(), TreeError> {
+        let rule = match self.rule_for(path) {
+            Some(rule) => rule,
+            None => { return Ok(()); }
+        };
+
+        let kind = self.kind_of(value);
+        let is_none = kind == self.kind_of(&ValueType::none());
+
+        if kind == rule.kind || (is_none && rule.default.is_none()) {
+            return Ok(());
+        }
+
+        return Err(TreeError::WrongKind {
+            path: path.to_string(),
+            expected: rule.kind,
+            found: kind,
+        });
+    }
+
+    /// Lists every value of the tree that does not match its rule.
+    pub fn find_errors(&self, tree: &ObservableKVTree<ValueType>) -> Vec<TreeError> {
+        let mut errors: Vec<TreeError> = Vec::new();
+
+        tree.for_each_value("", &mut |path, value| {
+            if let Err(error) = self.check(path, value) {
+                errors.push(error);
+            }
+        });
+
+        return errors;
+    }
+
+    /// Lists the required paths that have no value, with their default.
+    pub fn find_missing_values(&self, tree: &ObservableKVTree<ValueType>) -> Vec<(String, ValueType)> {
+        let mut missing: Vec<(String, ValueType)> = Vec::new();
+
+        for rule in self.rules.iter() {
+            let default = match &rule.default {
+                Some(default) => default,
+                None => { continue; }
+            };
+
+            let parts: Vec<&str> = rule.pattern.split(".").collect();
+            let (last_part, parent_parts) = parts.split_last().unwrap();
+            if parts.contains(&"**") {
+                // Can't tell which paths should exist.
+                continue;
+            }
+
+            for parent in matching_paths(tree, "", parent_parts) {
+                let path = match parent.is_empty() {
+                    true => last_part.to_string(),
+                    false => format!("{}.{}", parent, last_part)
+                };
+                let is_missing = match tree.get_ref(&path) {
+                    Some(value) => self.kind_of(value) == self.kind_of(&ValueType::none()),
+                    None => true
+                };
+                if is_missing {
+                    missing.push((path, default.clone()));
+                }
+            }
+        }
+
+        return missing;
+    }
+}
+
+/// Lists paths matching the pattern parts, starting from `prefix`.
+fn matching_paths<ValueType: Default + Clone + CanBeNone<ValueType>>(
+    tree: &ObservableKVTree<ValueType>,
+    prefix: &str,
+    parts: &[&str]
+) -> Vec<String> {
+    let (part, other_parts) = match parts.split_first() {
+        Some(split) => split,
+        None => { return vec!(prefix.to_string()); }
+    };
+
+    let mut paths: Vec<String> = Vec::new();
+
+    // Parents without wildcards are created when missing.
+    if *part != "*" && !tree.subtree.contains_key(*part) {
+        if parts.contains(&"*") {
+            return paths;
+        }
+        let path = match prefix.is_empty() {
+            true => parts.join("."),
+            false => format!("{}.{}", prefix, parts.join("."))
+        };
+        paths.push(path);
+        return paths;
+    }
+
+    for (key, subtree) in tree.subtree.iter() {
+        if *part != "*" && part != key {
+            continue;
+        }
+        let path = match prefix.is_empty() {
+            true => key.clone(),
+            false => format!("{}.{}", prefix, key)
+        };
+        paths.extend(matching_paths(subtree, &path, other_parts));
+    }
+
+    return paths;
+}
+
+impl <ValueType: Default + Clone + CanBeNone<ValueType>> ObservableKVTree<ValueType> {
+    /// Sets the schema, fills missing required values with their default, and checks
+    /// values already in the tree.
+    /// The schema is set even if errors are returned.
+    pub fn set_schema(&mut self, schema: Schema<ValueType>) -> Result<(), Vec<TreeError>> {
+        self.schema = Some(Arc::new(schema));
+        return self.validate();
+    }
+
+    pub fn schema(&self) -> Option<&Schema<ValueType>> {
+        return self.schema.as_deref();
+    }
+
+    /// Fills missing required values with their default and checks all values against the schema.
+    /// Defaults are set without being tracked in undo history.
+    pub fn validate(&mut self) -> Result<(), Vec<TreeError>> {
+        let schema = match self.schema.clone() {
+            Some(schema) => schema,
+            None => { return Ok(()); }
+        };
+
+        self.fill_required_values(&schema);
+
+        let errors = schema.find_errors(self);
+        return match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors)
+        };
+    }
+
+    /// Sets missing required values to their default, without tracking them in undo history.
+    pub(crate) fn fill_required_values(&mut self, schema: &Schema<ValueType>) {
+        for (path, default) in schema.find_missing_values(self) {
+            self.set_path_untracked(&path, default);
+        }
+    }
+
+    /// Like `for_each_value`, with the values shared with the tree.
+    pub(crate) fn for_each_shared_value(&self, prefix: &str, f: &mut dyn FnMut(&str, &Arc<ValueType>)) {
+        for (key, node) in self.subtree.iter() {
+            let path = match prefix.is_empty() {
+                true => key.clone(),
+                false => format!("{}.{}", prefix, key)
+            };
+            f(&path, &node.value);
+            node.for_each_shared_value(&path, f);
+        }
+    }
+
+    /// Calls `f` with the path of every node holding a value, including none values.
+    pub(crate) fn for_each_value(&self, prefix: &str, f: &mut dyn FnMut(&str, &ValueType)) {
+        for (key, node) in self.subtree.iter() {
+            let path = match prefix.is_empty() {
+                true => key.clone(),
+                false => format!("{}.{}", prefix, key)
+            };
+            f(&path, node.value.as_ref());
+            node.for_each_value(&path, f);
+        }
+    }
+
+    /// Returns the value at given path, or an error if it is missing.
+    pub fn try_get_ref(&self, path: &str) -> Result<&ValueType, TreeError> {
+        return self.get_ref(path).ok_or_else(|| TreeError::Missing(path.to_string()));
+    }
+
+    /// Returns the value at given path, converted to `T`.
+    pub fn get_as<T: TreeValue<ValueType>>(&self, path: &str) -> Result<T, TreeError> {
+        let value = self.try_get_ref(path)?;
+        return T::from_tree_value(value).ok_or_else(|| TreeError::WrongType {
+            path: path.to_string(),
+            expected: std::any::type_name::<T>(),
+        });
+    }
+
+    /// Borrows the value at given path as a `T`, without copying it.
+    pub fn get_ref_as<T: TreeValueRef<ValueType> + ?Sized>(&self, path: &str) -> Result<&T, TreeError> {
+        let value = self.try_get_ref(path)?;
+        return T::from_tree_value_ref(value).ok_or_else(|| TreeError::WrongType {
+            path: path.to_string(),
+            expected: std::any::type_name::<T>(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+
+    fn schema() -> Schema<ExampleValueType> {
+        return Schema::new(ExampleValueType::kind)
+            .required("scene.count", "I32", ExampleValueType::from(1))
+            .required("scene.objects.*.size", "F32", ExampleValueType::from(1.0))
+            .optional("scene.objects.*.count", "I32");
+    }
+
+    #[test]
+    fn it_matches_patterns() {
+        assert!(path_matches_pattern("scene.objects.a.size", "scene.objects.*.size"));
+        assert!(path_matches_pattern("scene", "scene"));
+        assert!(!path_matches_pattern("scene.objects.a", "scene.objects.*.size"));
+        assert!(!path_matches_pattern("scene.objects.a.size.x", "scene.objects.*.size"));
+        assert!(!path_matches_pattern("scene.other.a.size", "scene.objects.*.size"));
+    }
+
+    #[test]
+    fn it_checks_kinds_on_set_path() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_schema(schema()).unwrap();
+
+        assert!(data.try_set_path("scene.objects.a.count", ExampleValueType::from(2)).is_ok());
+        assert_eq!(
+            data.try_set_path("scene.objects.a.count", ExampleValueType::from(2.0)),
+            Err(TreeError::WrongKind { path: "scene.objects.a.count".to_string(), expected: "I32", found: "F32" })
+        );
+        // set_path refuses the value too, and keeps the error
+        data.set_path("scene.count", ExampleValueType::from(2.0));
+        assert_eq!(data.get_path("scene.count").unwrap_i32(), 1);
+        assert_eq!(
+            data.take_schema_errors(),
+            vec!(TreeError::WrongKind { path: "scene.count".to_string(), expected: "I32", found: "F32" })
+        );
+        assert!(data.take_schema_errors().is_empty());
+
+        // Optional values can be removed, required ones can't.
+        assert!(data.try_set_path("scene.objects.a.count", ExampleValueType::None).is_ok());
+        assert!(data.try_set_path("scene.count", ExampleValueType::None).is_err());
+
+        // Paths without rules are not checked.
+        assert!(data.try_set_path("editor.anything", ExampleValueType::from(2.0)).is_ok());
+    }
+
+    #[test]
+    fn it_fills_required_values() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.objects.a.count", ExampleValueType::from(3));
+        data.set_path("scene.objects.b.size", ExampleValueType::from(2.0));
+        data.make_undo_redo_snapshot();
+
+        data.set_schema(schema()).unwrap();
+
+        assert_eq!(data.get_path("scene.count").unwrap_i32(), 1);
+        assert_eq!(data.get_path("scene.objects.a.size").unwrap_f32(), 1.0);
+        assert_eq!(data.get_path("scene.objects.b.size").unwrap_f32(), 2.0);
+
+        // Defaults are not part of undo history.
+        data.undo();
+        assert_eq!(data.get_path("scene.objects.a.size").unwrap_f32(), 1.0);
+    }
+
+    #[test]
+    fn it_validates_deserialized_trees() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_schema(schema()).unwrap();
+
+        let invalid: ObservableKVTree<ExampleValueType> = serde_json::from_str(
+            r#"{"subtree":{"objects":{"subtree":{"a":{"subtree":{"size":{"subtree":{},"value":{"I32":1}}},"value":"None"}},"value":"None"}},"value":"None"}"#
+        ).unwrap();
+
+        let version = data.version();
+        let errors = data.try_set_tree("scene", invalid).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(data.get_tree_ref("scene.objects").is_none());
+        // Nothing was written
+        assert_eq!(data.version(), version);
+        assert_eq!(data.get_path("scene.count").unwrap_i32(), 1);
+
+        let mut valid = ObservableKVTree::<ExampleValueType>::default();
+        valid.set_path("objects.a.count", ExampleValueType::from(1));
+        data.try_set_tree("scene", valid).unwrap();
+
+        // Required values missing from the loaded tree are filled.
+        assert_eq!(data.get_path("scene.count").unwrap_i32(), 1);
+        assert_eq!(data.get_path("scene.objects.a.size").unwrap_f32(), 1.0);
+    }
+
+    #[test]
+    fn it_gets_typed_values() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        data.set_path("scene.count", ExampleValueType::from(1));
+
+        assert_eq!(data.get_as::<i32>("scene.count"), Ok(1));
+        assert_eq!(data.get_as::<i32>("scene.missing"), Err(TreeError::Missing("scene.missing".to_string())));
+        assert!(matches!(data.get_as::<f32>("scene.count"), Err(TreeError::WrongType { .. })));
+        assert_eq!(data.get_ref_as::<i32>("scene.count"), Ok(&1));
+        assert!(data.get_ref_as::<f32>("scene.count").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Thread-safe handle to a tree owned by another thread.
+//!
+//! The owner of the tree (typically the main loop) keeps using the tree directly and calls
+//! `sync` once per update cycle. Other threads, such as async tasks, use a `SharedKVTree`:
+//!  - `read()` returns the state published at the last `sync`. It never changes afterwards,
+//!    so readers always see a consistent tree and never wait for a frame to finish.
+//!  - Writes are queued and applied to the tree at the next `sync`.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType,SharedKVTree};
+//! let mut data = ObservableKVTree::<ExampleValueType>::default();
+//! let shared = SharedKVTree::<ExampleValueType>::default();
+//!
+//! let handle = shared.clone();
+//! std::thread::spawn(move || {
+//!     handle.queue_set_path("scene.some.property", ExampleValueType::from(1234));
+//! }).join().unwrap();
+//!
+//! shared.sync(&mut data);
+//! assert_eq!(data.get_path("scene.some.property").unwrap_i32(), 1234);
+//! assert_eq!(shared.read().get_path("scene.some.property").unwrap_i32(), 1234);
+//! ```
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{ObservableKVTree, CanBeNone};
+
+type QueuedWrite<ValueType> = Box<dyn FnOnce(&mut ObservableKVTree<ValueType>) + Send>;
+
+struct SharedState<ValueType: Default + Clone + CanBeNone<ValueType>> {
+    published_tree: RwLock<Arc<ObservableKVTree<ValueType>>>,
+    queued_writes: Mutex<Vec<QueuedWrite<ValueType>>>,
+    /// Root version of the published tree. None until the first `sync`.
+    published_version: Mutex<Option<u64>>,
+}
+
+/// Cloning the handle gives another handle to the same tree.
+pub struct SharedKVTree<ValueType: Default + Clone + CanBeNone<ValueType>> {
+    state: Arc<SharedState<ValueType>>,
+}
+
+impl<ValueType: Default + Clone + CanBeNone<ValueType>> Clone for SharedKVTree<ValueType> {
+    fn clone(&self) -> Self {
+        return Self { state: self.state.clone() };
+    }
+}
+
+impl<ValueType: Default + Clone + CanBeNone<ValueType>> Default for SharedKVTree<ValueType> {
+    fn default() -> Self {
+        return Self {
+            state: Arc::new(SharedState {
+                published_tree: RwLock::new(Arc::new(ObservableKVTree::default())),
+                queued_writes: Mutex::new(Vec::new()),
+                published_version: Mutex::new(None),
+            }),
+        };
+    }
+}
+
+impl<ValueType: Default + Clone + CanBeNone<ValueType> + Send + Sync + 'static> SharedKVTree<ValueType> {
+    /// Returns the tree as it was at the last `sync`.
+    /// Only values are available: the snapshot has no undo history or update listeners.
+    pub fn read(&self) -> Arc<ObservableKVTree<ValueType>> {
+        return self.state.published_tree.read().unwrap().clone();
+    }
+
+    /// Queues a write, applied to the tree at the next `sync`.
+    pub fn queue_write(&self, write: impl FnOnce(&mut ObservableKVTree<ValueType>) + Send + 'static) {
+        self.state.queued_writes.lock().unwrap().push(Box::new(write));
+    }
+
+    pub fn queue_set_path(&self, path: &str, value: ValueType) {
+        let path = path.to_string();
+        self.queue_write(move |tree| tree.set_path(&path, value));
+    }
+
+    pub fn queue_set_tree(&self, path: &str, value: ObservableKVTree<ValueType>) {
+        let path = path.to_string();
+        self.queue_write(move |tree| tree.set_tree(&path, value));
+    }
+
+    /// Applies queued writes to the tree, then publishes its state to readers.
+    /// Called by the owner of the tree, typically once per update cycle.
+    /// Publishing only copies the root node, since subtrees are shared.
+    pub fn sync(&self, tree: &mut ObservableKVTree<ValueType>) {
+        // Take the writes first, so queued writes can queue other writes without a deadlock.
+        let queued_writes = std::mem::take(&mut *self.state.queued_writes.lock().unwrap());
+        for write in queued_writes.into_iter() {
+            write(tree);
+        }
+
+        let mut published_version = self.state.published_version.lock().unwrap();
+        if *published_version == Some(tree.version()) {
+            return;
+        }
+        *published_version = Some(tree.version());
+
+        let published_tree = Arc::new(ObservableKVTree {
+            subtree: tree.subtree.clone(),
+            value: tree.value.clone(),
+            update_tracker: tree.update_tracker.clone(),
+            ..ObservableKVTree::default()
+        });
+        *self.state.published_tree.write().unwrap() = published_tree;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+
+    #[test]
+    fn readers_see_the_last_published_state() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        let shared = SharedKVTree::<ExampleValueType>::default();
+        data.set_path("scene.property", ExampleValueType::from(1));
+        shared.sync(&mut data);
+
+        let snapshot = shared.read();
+        data.set_path("scene.property", ExampleValueType::from(2));
+
+        // Not published yet.
+        assert_eq!(shared.read().get_path("scene.property").unwrap_i32(), 1);
+
+        shared.sync(&mut data);
+        assert_eq!(shared.read().get_path("scene.property").unwrap_i32(), 2);
+        // Snapshots taken before are not affected.
+        assert_eq!(snapshot.get_path("scene.property").unwrap_i32(), 1);
+    }
+
+    #[test]
+    fn it_applies_queued_writes_as_tracked_changes() {
+        let mut data = ObservableKVTree::<ExampleValueType>::default();
+        let shared = SharedKVTree::<ExampleValueType>::default();
+        data.set_path("scene.property", ExampleValueType::from(1));
+        data.make_undo_redo_snapshot();
+
+        let threads: Vec<std::thread::JoinHandle<()>> = (0..4).map(|index| {
+            let handle = shared.clone();
+            std::thread::spawn(move || {
+                handle.queue_set_path(&format!("scene.thread.{}", index), ExampleValueType::from(index));
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        shared.queue_write(|tree| tree.set_path("scene.property", ExampleValueType::from(2)));
+        assert!(data.get_path("scene.thread.0").is_none());
+
+        shared.sync(&mut data);
+        data.make_undo_redo_snapshot();
+
+        for index in 0..4 {
+            assert_eq!(data.get_path(&format!("scene.thread.{}", index)).unwrap_i32(), index);
+        }
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 2);
+
+        data.undo();
+        assert_eq!(data.get_path("scene.property").unwrap_i32(), 1);
+        assert!(data.get_path("scene.thread.0").is_none());
+    }
+}
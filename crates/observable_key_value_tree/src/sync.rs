@@ -0,0 +1,358 @@
+//! Multi-user sync on top of update channels.
+//!
+//! A `SyncSession` turns local writes into `SyncMessage`s and applies messages coming from
+//! other users. The transport is up to the application: messages are plain serde types,
+//! typically sent as JSON to a relay server that forwards them to every other user.
+//!
+//! Concurrent edits are resolved per path with a last-writer-wins rule: every message carries
+//! a Lamport timestamp, and all sites agree on the same winner regardless of the order in
+//! which messages arrive. See `SyncMessage::wins_over`.
+//!
+//! Remote changes are applied with `set_path_untracked`, so each user's undo history only
+//! contains their own changes.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree,ExampleValueType,SyncSession};
+//! let mut alice = ObservableKVTree::<ExampleValueType>::default();
+//! let mut bob = ObservableKVTree::<ExampleValueType>::default();
+//! let mut alice_session = SyncSession::new("alice", &mut alice, &["scene"]);
+//! let mut bob_session = SyncSession::new("bob", &mut bob, &["scene"]);
+//!
+//! alice.set_path("scene.some.property", ExampleValueType::from(1234));
+//!
+//! for message in alice_session.collect_local_changes().unwrap() {
+//!     bob_session.apply_remote_change(&mut bob, &message).unwrap();
+//! }
+//! assert_eq!(bob.get_path("scene.some.property").unwrap_i32(), 1234);
+//! ```
+
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::collections::BTreeMap;
+use std::sync::mpsc::Receiver;
+
+use crate::{ObservableKVTree, CanBeNone, Update};
+
+/// A change made to one path by one site.
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
+pub struct SyncMessage {
+    pub site_id: String,
+    /// Lamport timestamp: one more than the newest timestamp the author had seen.
+    pub timestamp: u64,
+    pub path: String,
+    pub value: serde_json::Value,
+}
+
+impl SyncMessage {
+    /// Decides which of two writes to the same path is kept.
+    ///
+    /// A write made after seeing another one has a larger timestamp, so it always wins.
+    /// Concurrent writes are ordered by timestamp too, however many writes each site made,
+    /// and the site id breaks ties so every site picks the same winner.
+    pub fn wins_over(&self, other: &SyncMessage) -> bool {
+        return (self.timestamp, &self.site_id) > (other.timestamp, &other.site_id);
+    }
+}
+
+pub struct SyncSession<ValueType> {
+    pub site_id: String,
+    /// Newest Lamport timestamp made or seen by this site.
+    clock: u64,
+    /// Only paths starting with one of these prefixes are synced.
+    synced_prefixes: Vec<String>,
+    /// Last write applied to each synced path, used to resolve concurrent writes.
+    latest_writes: BTreeMap<String, SyncMessage>,
+    updates: Receiver<Update<ValueType>>,
+}
+
+impl <ValueType: Default + Clone + CanBeNone<ValueType> + Serialize + DeserializeOwned> SyncSession<ValueType> {
+    /// Starts listening to local changes made under `synced_prefixes`.
+    pub fn new(site_id: &str, tree: &mut ObservableKVTree<ValueType>, synced_prefixes: &[&str]) -> Self {
+        return Self {
+            site_id: site_id.to_string(),
+            clock: 0,
+            synced_prefixes: synced_prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+            latest_writes: BTreeMap::new(),
+            updates: tree.create_update_channel(),
+        };
+    }
+
+    pub fn clock(&self) -> u64 {
+        return self.clock;
+    }
+
+    fn is_synced(&self, path: &str) -> bool {
+        return self.synced_prefixes.iter().any(|prefix| {
+            path == prefix || path.starts_with(&format!("{}.", prefix))
+        });
+    }
+
+    fn make_message(&mut self, path: &str, value: &ValueType) -> Result<SyncMessage, serde_json::Error> {
+        self.clock += 1;
+        let message = SyncMessage {
+            site_id: self.site_id.clone(),
+            timestamp: self.clock,
+            path: path.to_string(),
+            value: serde_json::to_value(value)?,
+        };
+        self.latest_writes.insert(path.to_string(), message.clone());
+        return Ok(message);
+    }
+
+    /// Turns local changes made since the last call into messages for other sites.
+    pub fn collect_local_changes(&mut self) -> Result<Vec<SyncMessage>, serde_json::Error> {
+        let mut messages: Vec<SyncMessage> = Vec::new();
+
+        while let Ok(update) = self.updates.try_recv() {
+            // Untracked updates come from other sites and are not sent back.
+            if !update.untracked && self.is_synced(&update.path) {
+                messages.push(self.make_message(&update.path, &update.value)?);
+            }
+        }
+
+        return Ok(messages);
+    }
+
+    /// Creates messages for every value currently stored under the synced prefixes.
+    /// Useful when joining a session, so other sites get the local state.
+    pub fn publish_current_state(&mut self, tree: &ObservableKVTree<ValueType>) -> Result<Vec<SyncMessage>, serde_json::Error> {
+        let none = serde_json::to_value(ValueType::none())?;
+        let mut messages: Vec<SyncMessage> = Vec::new();
+
+        for prefix in self.synced_prefixes.clone() {
+            let subtree = match tree.get_tree(&prefix) {
+                Some(subtree) => subtree,
+                None => { continue; }
+            };
+
+            let mut values = subtree.flatten_values()?;
+            let own_value = serde_json::to_value(tree.get_path(&prefix))?;
+            if own_value != none {
+                values.insert(String::new(), own_value);
+            }
+
+            for (relative_path, value) in values.into_iter() {
+                let path = match relative_path.is_empty() {
+                    true => prefix.clone(),
+                    false => format!("{}.{}", prefix, relative_path)
+                };
+                let value: ValueType = serde_json::from_value(value)?;
+                messages.push(self.make_message(&path, &value)?);
+            }
+        }
+
+        return Ok(messages);
+    }
+
+    /// Applies a change made by another site.
+    /// Returns false if the change lost against a write already applied to the same path.
+    pub fn apply_remote_change(
+        &mut self,
+        tree: &mut ObservableKVTree<ValueType>,
+        message: &SyncMessage
+    ) -> Result<bool, serde_json::Error> {
+        if message.site_id == self.site_id || !self.is_synced(&message.path) {
+            return Ok(false);
+        }
+
+        self.clock = self.clock.max(message.timestamp);
+
+        if let Some(latest_write) = self.latest_writes.get(&message.path) {
+            if !message.wins_over(latest_write) {
+                return Ok(false);
+            }
+        }
+
+        let value: ValueType = serde_json::from_value(message.value.clone())?;
+        tree.set_path_untracked(&message.path, value);
+        self.latest_writes.insert(message.path.clone(), message.clone());
+
+        return Ok(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExampleValueType;
+
+    fn exchange(
+        from: &mut SyncSession<ExampleValueType>,
+        to: &mut SyncSession<ExampleValueType>,
+        to_tree: &mut ObservableKVTree<ExampleValueType>
+    ) {
+        for message in from.collect_local_changes().unwrap() {
+            to.apply_remote_change(to_tree, &message).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_write_made_after_seeing_another_wins() {
+        let mut alice = ObservableKVTree::<ExampleValueType>::default();
+        let mut bob = ObservableKVTree::<ExampleValueType>::default();
+        let mut alice_session = SyncSession::new("alice", &mut alice, &["scene"]);
+        let mut bob_session = SyncSession::new("bob", &mut bob, &["scene"]);
+
+        // Alice makes many writes elsewhere, bob hears about them.
+        for i in 0..10 {
+            alice.set_path("scene.other", ExampleValueType::from(i));
+        }
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+        alice.set_path("scene.property", ExampleValueType::from(1));
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+
+        // Bob writes after seeing alice's write: his write is newer, even though he wrote less.
+        bob.set_path("scene.property", ExampleValueType::from(2));
+        exchange(&mut bob_session, &mut alice_session, &mut alice);
+        assert_eq!(alice.get_path("scene.property").unwrap_i32(), 2);
+        assert!(bob_session.clock() > 11);
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let mut alice = ObservableKVTree::<ExampleValueType>::default();
+        let mut bob = ObservableKVTree::<ExampleValueType>::default();
+        let mut alice_session = SyncSession::new("alice", &mut alice, &["scene"]);
+        let mut bob_session = SyncSession::new("bob", &mut bob, &["scene"]);
+
+        // Both edit the same path before hearing from each other.
+        alice.set_path("scene.property", ExampleValueType::from(1));
+        bob.set_path("scene.property", ExampleValueType::from(2));
+        let alice_messages = alice_session.collect_local_changes().unwrap();
+        let bob_messages = bob_session.collect_local_changes().unwrap();
+
+        for message in bob_messages.iter() {
+            alice_session.apply_remote_change(&mut alice, message).unwrap();
+        }
+        for message in alice_messages.iter() {
+            bob_session.apply_remote_change(&mut bob, message).unwrap();
+        }
+
+        assert_eq!(alice.get_path("scene.property").unwrap_i32(), 2);
+        assert_eq!(bob.get_path("scene.property").unwrap_i32(), 2);
+
+        // A write made after seeing the other one wins, whatever the site id.
+        alice.set_path("scene.property", ExampleValueType::from(3));
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+        assert_eq!(bob.get_path("scene.property").unwrap_i32(), 3);
+
+        // Paths outside synced prefixes stay local.
+        alice.set_path("editor.property", ExampleValueType::from(4));
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+        assert!(bob.get_path("editor.property").is_none());
+    }
+
+    #[test]
+    fn concurrent_edits_of_different_objects_are_kept() {
+        let mut alice = ObservableKVTree::<ExampleValueType>::default();
+        let mut bob = ObservableKVTree::<ExampleValueType>::default();
+        let mut alice_session = SyncSession::new("alice", &mut alice, &["scene.objects"]);
+        let mut bob_session = SyncSession::new("bob", &mut bob, &["scene.objects"]);
+
+        // Each object has its own path, so edits of different objects don't overwrite each other.
+        alice.set_path("scene.objects.a", ExampleValueType::from(1));
+        bob.set_path("scene.objects.b", ExampleValueType::from(2));
+        let alice_messages = alice_session.collect_local_changes().unwrap();
+        let bob_messages = bob_session.collect_local_changes().unwrap();
+
+        for message in bob_messages.iter() {
+            alice_session.apply_remote_change(&mut alice, message).unwrap();
+        }
+        for message in alice_messages.iter() {
+            bob_session.apply_remote_change(&mut bob, message).unwrap();
+        }
+
+        assert_eq!(alice.get_path("scene.objects.b").unwrap_i32(), 2);
+        assert_eq!(bob.get_path("scene.objects.a").unwrap_i32(), 1);
+        assert!(alice.diff(&bob).unwrap().is_empty());
+    }
+
+    #[test]
+    fn undo_only_reverts_own_changes() {
+        let mut alice = ObservableKVTree::<ExampleValueType>::default();
+        let mut bob = ObservableKVTree::<ExampleValueType>::default();
+        let mut alice_session = SyncSession::new("alice", &mut alice, &["scene"]);
+        let mut bob_session = SyncSession::new("bob", &mut bob, &["scene"]);
+
+        alice.make_undo_redo_snapshot();
+        alice.set_path("scene.alice", ExampleValueType::from(1));
+        alice.make_undo_redo_snapshot();
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+
+        bob.make_undo_redo_snapshot();
+        bob.set_path("scene.bob", ExampleValueType::from(2));
+        bob.make_undo_redo_snapshot();
+        exchange(&mut bob_session, &mut alice_session, &mut alice);
+
+        assert_eq!(alice.get_path("scene.bob").unwrap_i32(), 2);
+
+        alice.undo();
+        assert!(alice.get_path("scene.alice").is_none());
+        assert_eq!(alice.get_path("scene.bob").unwrap_i32(), 2);
+
+        // The undo is a local change, so it is sent to bob.
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+        assert!(bob.get_path("scene.alice").is_none());
+        assert_eq!(bob.get_path("scene.bob").unwrap_i32(), 2);
+
+        bob.undo();
+        assert!(bob.get_path("scene.bob").is_none());
+    }
+
+    #[test]
+    fn loaded_subtrees_reach_other_sites() {
+        let mut alice = ObservableKVTree::<ExampleValueType>::default();
+        let mut bob = ObservableKVTree::<ExampleValueType>::default();
+        let mut alice_session = SyncSession::new("alice", &mut alice, &["scene"]);
+        let mut bob_session = SyncSession::new("bob", &mut bob, &["scene"]);
+
+        alice.set_path("scene.old", ExampleValueType::from(1));
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+
+        // Like opening a file: the whole scene is replaced.
+        let mut file = ObservableKVTree::<ExampleValueType>::default();
+        file.set_path("a.b", ExampleValueType::from(2));
+        file.set_path("c", ExampleValueType::from(3));
+        alice.set_tree("scene", file);
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+
+        assert_eq!(bob.get_path("scene.a.b").unwrap_i32(), 2);
+        assert_eq!(bob.get_path("scene.c").unwrap_i32(), 3);
+        assert!(bob.get_path("scene.old").is_none());
+        assert!(alice.diff(&bob).unwrap().is_empty());
+    }
+
+    #[test]
+    fn changes_of_other_sites_are_notified_but_not_sent_back() {
+        let mut alice = ObservableKVTree::<ExampleValueType>::default();
+        let mut bob = ObservableKVTree::<ExampleValueType>::default();
+        let mut alice_session = SyncSession::new("alice", &mut alice, &["scene"]);
+        let mut bob_session = SyncSession::new("bob", &mut bob, &["scene"]);
+        let bob_updates = bob.create_update_channel();
+
+        alice.set_path("scene.property", ExampleValueType::from(1));
+        exchange(&mut alice_session, &mut bob_session, &mut bob);
+
+        let update = bob_updates.try_recv().unwrap();
+        assert_eq!(update.path, "scene.property");
+        assert_eq!(update.value.unwrap_i32(), 1);
+        assert!(update.untracked);
+        assert!(bob_session.collect_local_changes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn late_joiner_gets_current_state() {
+        let mut alice = ObservableKVTree::<ExampleValueType>::default();
+        alice.set_path("scene.a.b", ExampleValueType::from(1));
+        alice.set_path("scene.c", ExampleValueType::from(2));
+        let mut alice_session = SyncSession::new("alice", &mut alice, &["scene"]);
+
+        let mut bob = ObservableKVTree::<ExampleValueType>::default();
+        let mut bob_session = SyncSession::new("bob", &mut bob, &["scene"]);
+
+        for message in alice_session.publish_current_state(&alice).unwrap() {
+            bob_session.apply_remote_change(&mut bob, &message).unwrap();
+        }
+
+        assert!(alice.diff(&bob).unwrap().is_empty());
+    }
+}
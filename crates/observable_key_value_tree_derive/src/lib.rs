@@ -0,0 +1,196 @@
+//! `#[derive(TreeBinding)]` for observable_key_value_tree.
+//!
+//! Maps each field of a struct to a child of a subtree. The value type of the tree is given with
+//! `#[tree_value(ValueType)]`, and field types must implement `TreeValue<ValueType>`.
+//! Children are named after fields, unless renamed with `#[tree(rename = "name")]`.
+//!
+//! For each field `name: Type`, the following associated items are generated:
+//!  - `NAME`: the name of the child
+//!  - `name_path(path)`: the full path of the child
+//!  - `get_name(&tree, path) -> Option<Type>`
+//!  - `set_name(&mut tree, path, value)`
+//!  - `was_name_updated(&tree, path) -> bool`
+//!
+//! Use the crate with the `derive` feature of observable_key_value_tree rather than directly.
+//!
+//! ```
+//! use observable_key_value_tree::{ObservableKVTree, ExampleValueType, TreeBinding};
+//!
+//! #[derive(TreeBinding)]
+//! #[tree_value(ExampleValueType)]
+//! struct Settings {
+//!     count: i32,
+//!     #[tree(rename = "ratio_value")]
+//!     ratio: f32,
+//! }
+//!
+//! let mut tree = ObservableKVTree::<ExampleValueType>::default();
+//! // Writes "editor.settings.count"
+//! Settings::set_count(&mut tree, "editor.settings", 3);
+//! let count: Option<i32> = Settings::get_count(&tree, "editor.settings");
+//! let updated: bool = Settings::was_count_updated(&tree, "editor.settings");
+//! // Reads all fields. Returns None if a field is missing.
+//! let settings: Option<Settings> = Settings::read_from(&tree, "editor.settings");
+//! assert!(settings.is_none());
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{quote, format_ident};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitStr, Type};
+
+struct BoundField {
+    ident: Ident,
+    name: String,
+    field_type: Type,
+}
+
+#[proc_macro_derive(TreeBinding, attributes(tree_value, tree))]
+pub fn derive_tree_binding(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    return match expand_tree_binding(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    };
+}
+
+fn expand_tree_binding(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let struct_name = &input.ident;
+    let value_type = find_value_type(input)?;
+    let fields = find_fields(input)?;
+
+    let tree_type = quote! { ::observable_key_value_tree::ObservableKVTree<#value_type> };
+    let field_names: Vec<&String> = fields.iter().map(|field| &field.name).collect();
+    let field_idents: Vec<&Ident> = fields.iter().map(|field| &field.ident).collect();
+
+    let mut accessors = proc_macro2::TokenStream::new();
+    let mut path_fns: Vec<Ident> = Vec::new();
+    let mut getters: Vec<Ident> = Vec::new();
+    let mut update_checks: Vec<Ident> = Vec::new();
+
+    for field in fields.iter() {
+        let ident = &field.ident;
+        let name = &field.name;
+        let field_type = &field.field_type;
+        let constant = Ident::new(&ident.to_string().to_uppercase(), ident.span());
+        let path_fn = format_ident!("{}_path", ident);
+        let getter = format_ident!("get_{}", ident);
+        let setter = format_ident!("set_{}", ident);
+        let update_check = format_ident!("was_{}_updated", ident);
+
+        accessors.extend(quote! {
+            pub const #constant: &'static str = #name;
+
+            pub fn #path_fn(path: &str) -> String {
+                return ::observable_key_value_tree::binding_field_path(path, #name);
+            }
+
+            pub fn #getter(tree: &#tree_type, path: &str) -> Option<#field_type> {
+                return tree.get_ref(&Self::#path_fn(path)).and_then(
+                    <#field_type as ::observable_key_value_tree::TreeValue<#value_type>>::from_tree_value
+                );
+            }
+
+            pub fn #setter(tree: &mut #tree_type, path: &str, value: #field_type) {
+                tree.set_path(
+                    &Self::#path_fn(path),
+                    ::observable_key_value_tree::TreeValue::<#value_type>::to_tree_value(&value)
+                );
+            }
+
+            pub fn #update_check(tree: &#tree_type, path: &str) -> bool {
+                return tree.was_path_updated(&Self::#path_fn(path));
+            }
+        });
+
+        path_fns.push(path_fn);
+        getters.push(getter);
+        update_checks.push(update_check);
+    }
+
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    return Ok(quote! {
+        impl #impl_generics #struct_name #type_generics #where_clause {
+            #accessors
+        }
+
+        impl #impl_generics ::observable_key_value_tree::TreeBinding<#value_type> for #struct_name #type_generics #where_clause {
+            const FIELDS: &'static [&'static str] = &[#(#field_names),*];
+
+            fn read_from(tree: &#tree_type, path: &str) -> Option<Self> {
+                return Some(Self {
+                    #(#field_idents: Self::#getters(tree, path)?,)*
+                });
+            }
+
+            fn write_to(&self, tree: &mut #tree_type, path: &str) {
+                #(tree.set_path(
+                    &Self::#path_fns(path),
+                    ::observable_key_value_tree::TreeValue::<#value_type>::to_tree_value(&self.#field_idents)
+                );)*
+            }
+
+            fn was_updated_in(tree: &#tree_type, path: &str) -> bool {
+                return false #(|| Self::#update_checks(tree, path))*;
+            }
+        }
+    });
+}
+
+fn find_value_type(input: &DeriveInput) -> syn::Result<Type> {
+    for attribute in input.attrs.iter() {
+        if attribute.path().is_ident("tree_value") {
+            return attribute.parse_args::<Type>();
+        }
+    }
+
+    return Err(syn::Error::new(
+        Span::call_site(),
+        "TreeBinding needs the value type of the tree: #[tree_value(ValueType)]"
+    ));
+}
+
+fn find_fields(input: &DeriveInput) -> syn::Result<Vec<BoundField>> {
+    let named_fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(&input.ident, "TreeBinding needs a struct with named fields"));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(&input.ident, "TreeBinding can only be derived for structs"));
+        }
+    };
+
+    let mut fields: Vec<BoundField> = Vec::new();
+
+    for field in named_fields.iter() {
+        let ident = field.ident.clone().unwrap();
+        let mut name = ident.to_string();
+
+        for attribute in field.attrs.iter().filter(|attribute| attribute.path().is_ident("tree")) {
+            attribute.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                    return Ok(());
+                }
+                return Err(meta.error("unknown tree attribute, expected rename"));
+            })?;
+        }
+
+        if name.is_empty() || name.contains('.') {
+            return Err(syn::Error::new_spanned(field, "tree paths can't be empty or contain dots"));
+        }
+
+        fields.push(BoundField {
+            ident,
+            name,
+            field_type: field.ty.clone(),
+        });
+    }
+
+    return Ok(fields);
+}
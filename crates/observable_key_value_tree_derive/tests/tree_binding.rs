@@ -0,0 +1,61 @@
+use observable_key_value_tree::{ObservableKVTree, ExampleValueType, TreeBinding};
+
+#[derive(TreeBinding, Debug, PartialEq)]
+#[tree_value(ExampleValueType)]
+struct Settings {
+    count: i32,
+    #[tree(rename = "ratio_value")]
+    ratio: f32,
+}
+
+#[test]
+fn it_reads_and_writes_fields() {
+    let mut data = ObservableKVTree::<ExampleValueType>::default();
+
+    Settings::set_count(&mut data, "editor.settings", 3);
+    Settings::set_ratio(&mut data, "editor.settings", 0.5);
+
+    assert_eq!(data.get_path("editor.settings.count").unwrap_i32(), 3);
+    assert_eq!(data.get_path("editor.settings.ratio_value").unwrap_f32(), 0.5);
+    assert_eq!(Settings::get_count(&data, "editor.settings"), Some(3));
+    assert_eq!(Settings::ratio_path("editor.settings"), "editor.settings.ratio_value");
+    assert_eq!(Settings::RATIO, "ratio_value");
+    assert_eq!(Settings::FIELDS, &["count", "ratio_value"]);
+
+    let settings = Settings::read_from(&data, "editor.settings").unwrap();
+    assert_eq!(settings, Settings { count: 3, ratio: 0.5 });
+
+    Settings { count: 4, ratio: 1.0 }.write_to(&mut data, "other");
+    assert_eq!(data.get_path("other.count").unwrap_i32(), 4);
+}
+
+#[test]
+fn it_returns_none_on_missing_or_mismatched_values() {
+    let mut data = ObservableKVTree::<ExampleValueType>::default();
+    assert_eq!(Settings::get_count(&data, "settings"), None);
+
+    // Wrong type
+    data.set_path("settings.count", ExampleValueType::from(1.0));
+    data.set_path("settings.ratio_value", ExampleValueType::from(1.0));
+    assert_eq!(Settings::get_count(&data, "settings"), None);
+    assert!(Settings::read_from(&data, "settings").is_none());
+
+    // Bindings at the root of the tree
+    Settings::set_count(&mut data, "", 2);
+    assert_eq!(data.get_path("count").unwrap_i32(), 2);
+}
+
+#[test]
+fn it_detects_updates_per_field() {
+    let mut data = ObservableKVTree::<ExampleValueType>::default();
+    Settings { count: 1, ratio: 1.0 }.write_to(&mut data, "settings");
+    data.reset_update_cycle();
+
+    assert!(!Settings::was_updated_in(&data, "settings"));
+
+    Settings::set_ratio(&mut data, "settings", 2.0);
+
+    assert!(Settings::was_ratio_updated(&data, "settings"));
+    assert!(!Settings::was_count_updated(&data, "settings"));
+    assert!(Settings::was_updated_in(&data, "settings"));
+}
@@ -0,0 +1,163 @@
+//! Edits a scene in a running claydash instance through its remote control, without mouse or keyboard.
+//!
+//! Start claydash with `CLAYDASH_REMOTE_CONTROL=127.0.0.1:9002 cargo run` (or run "Toggle remote control"),
+//! then run the client. It spawns, moves, duplicates and deletes objects, then undoes the deletion,
+//! and prints every step.
+//!
+//! Usage: `cargo run -p remote_client -- [address]` (default: 127.0.0.1:9002)
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use serde_json::{json, Value};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9002";
+
+/// JSON-RPC client, over plain TCP with one message per line.
+struct RemoteClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+    /// Notifications received while waiting for responses.
+    notifications: VecDeque<Value>,
+}
+
+impl RemoteClient {
+    fn connect(address: &str) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        return Ok(RemoteClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_id: 1,
+            notifications: VecDeque::new(),
+        });
+    }
+
+    fn read_message(&mut self) -> Result<Value, String> {
+        let mut line = String::new();
+        let length = self.reader.read_line(&mut line).map_err(|error| error.to_string())?;
+        if length == 0 {
+            return Err("Connection closed.".to_string());
+        }
+        return serde_json::from_str(&line).map_err(|error| error.to_string());
+    }
+
+    /// Calls a method and waits for its result.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        writeln!(self.writer, "{}", request).map_err(|error| error.to_string())?;
+
+        loop {
+            let message = self.read_message()?;
+            if message.get("id").is_none() {
+                self.notifications.push_back(message);
+                continue;
+            }
+            if message["id"] != json!(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                return Err(format!("{} failed: {}", method, error["message"].as_str().unwrap_or("")));
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+
+    fn run_command(&mut self, name: &str, args: Value) -> Result<Value, String> {
+        println!("Running {}", name);
+        return self.call("commands.run", json!({ "name": name, "args": args }));
+    }
+}
+
+fn run_editing_session(client: &mut RemoteClient) -> Result<(), String> {
+    let commands = client.call("commands.list", Value::Null)?;
+    let commands = commands.as_array().cloned().unwrap_or_default();
+    let available: Vec<&str> = commands.iter()
+        .filter(|command| command["available"] == json!(true))
+        .filter_map(|command| command["name"].as_str())
+        .collect();
+    println!("{} commands, available now: {}", commands.len(), available.join(", "));
+
+    let subscription = client.call("tree.subscribe", json!({ "pattern": "scene.selected_uuids" }))?;
+
+    let sphere = client.run_command("spawn-sphere", json!({ "position": [0.0, 0.5, 0.0], "radius": 0.3 }))?;
+    println!("Spawned sphere {}", sphere);
+
+    client.call("tree.set", json!({ "path": "editor.colorpicker.color", "value": [0.2, 0.4, 0.9, 1.0] }))?;
+    // Spawned boxes follow the mouse until the grab is finished.
+    client.run_command("spawn-box", json!({}))?;
+    client.run_command("finish", json!({}))?;
+
+    let box_selection = client.call("tree.get", json!({ "path": "scene.selected_uuids" }))?;
+    let mut selection = vec!(sphere);
+    selection.extend(box_selection.as_array().cloned().unwrap_or_default());
+    client.call("tree.set", json!({ "path": "scene.selected_uuids", "value": selection }))?;
+
+    client.run_command("duplicate", json!({}))?;
+    client.run_command("finish", json!({}))?;
+    let duplicates = client.call("tree.get", json!({ "path": "scene.selected_uuids" }))?;
+    println!("Duplicated into {}", duplicates);
+
+    client.run_command("delete", json!({}))?;
+    client.run_command("undo", json!({}))?;
+
+    let objects = client.call("tree.query", json!({ "pattern": "scene.objects.*" }))?;
+    let object_count = objects.as_array().map(|objects| objects.len());
+    println!("Scene has {} objects after undoing the deletion.", object_count.unwrap_or(0));
+
+    client.call("tree.unsubscribe", json!({ "subscription": subscription }))?;
+    for notification in client.notifications.drain(..) {
+        let params = &notification["params"];
+        println!("Notified: {} = {}", params["path"], params["value"]);
+    }
+
+    return Ok(());
+}
+
+fn main() -> std::io::Result<()> {
+    let address = std::env::args().nth(1).unwrap_or(DEFAULT_ADDRESS.to_string());
+    let mut client = RemoteClient::connect(&address)?;
+    println!("Connected to {}", address);
+
+    if let Err(error) = run_editing_session(&mut client) {
+        println!("Editing session failed: {}", error);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn it_waits_for_responses_and_keeps_notifications() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // Answers each request with its own method, after a notification.
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                let notification = json!({ "jsonrpc": "2.0", "method": "tree.changed", "params": {} });
+                writeln!(writer, "{}", notification).unwrap();
+                let response = match request["method"].as_str() {
+                    Some("fail") => json!({ "jsonrpc": "2.0", "id": request["id"], "error": { "code": -32000, "message": "Nope" } }),
+                    _ => json!({ "jsonrpc": "2.0", "id": request["id"], "result": request["method"] }),
+                };
+                writeln!(writer, "{}", response).unwrap();
+            }
+        });
+
+        let mut client = RemoteClient::connect(&address).unwrap();
+        assert_eq!(client.call("tree.get", Value::Null).unwrap(), json!("tree.get"));
+        assert_eq!(client.call("fail", Value::Null), Err("fail failed: Nope".to_string()));
+        assert_eq!(client.notifications.len(), 2);
+    }
+}
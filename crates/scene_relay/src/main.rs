@@ -0,0 +1,179 @@
+//! Relays scene changes between claydash instances over WebSocket.
+//!
+//! Every message received from a client is forwarded to all other clients.
+//! The relay also keeps the winning write of each path, so clients joining later
+//! receive the current scene.
+//!
+//! Usage: `cargo run -p scene_relay -- [address]` (default: 127.0.0.1:9001)
+
+use std::collections::BTreeMap;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+use observable_key_value_tree::SyncMessage;
+use tungstenite::Message;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9001";
+/// How long a client thread waits for a message before checking messages to send.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Client {
+    id: usize,
+    outgoing: Sender<String>,
+}
+
+#[derive(Default)]
+struct RelayState {
+    clients: Vec<Client>,
+    latest_writes: BTreeMap<String, SyncMessage>,
+    next_client_id: usize,
+}
+
+impl RelayState {
+    /// Registers a client and queues the current scene for it.
+    fn join(&mut self, outgoing: Sender<String>) -> usize {
+        let id = self.next_client_id;
+        self.next_client_id += 1;
+
+        for message in self.latest_writes.values() {
+            if let Ok(text) = serde_json::to_string(message) {
+                _ = outgoing.send(text);
+            }
+        }
+
+        self.clients.push(Client { id, outgoing });
+        return id;
+    }
+
+    fn leave(&mut self, id: usize) {
+        self.clients.retain(|client| client.id != id);
+    }
+
+    /// Forwards a message to every other client and remembers it if it wins over the
+    /// previous write to the same path.
+    fn relay(&mut self, from: usize, text: String) -> Result<(), serde_json::Error> {
+        let message: SyncMessage = serde_json::from_str(&text)?;
+
+        let wins = match self.latest_writes.get(&message.path) {
+            Some(latest_write) => message.wins_over(latest_write),
+            None => true
+        };
+        if wins {
+            self.latest_writes.insert(message.path.clone(), message);
+        }
+
+        for client in self.clients.iter().filter(|client| client.id != from) {
+            _ = client.outgoing.send(text.clone());
+        }
+
+        return Ok(());
+    }
+}
+
+fn handle_client(stream: TcpStream, state: Arc<Mutex<RelayState>>) -> tungstenite::Result<()> {
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(error) => {
+            println!("Handshake failed: {}", error);
+            return Ok(());
+        }
+    };
+    // Reads time out so messages from other clients are sent without waiting.
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let (tx, rx) = channel::<String>();
+    let id = state.lock().unwrap().join(tx);
+    println!("Client {} joined.", id);
+
+    let result = 'relay: loop {
+        while let Ok(text) = rx.try_recv() {
+            if let Err(error) = socket.send(Message::Text(text)) {
+                break 'relay Err(error);
+            }
+        }
+
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if let Err(error) = state.lock().unwrap().relay(id, text) {
+                    println!("Ignoring invalid message from client {}: {}", id, error);
+                }
+            },
+            Ok(Message::Close(_)) => { break Ok(()); },
+            Ok(_) => {},
+            Err(tungstenite::Error::Io(error))
+                if error.kind() == std::io::ErrorKind::WouldBlock || error.kind() == std::io::ErrorKind::TimedOut => {},
+            Err(error) => { break Err(error); }
+        }
+    };
+
+    state.lock().unwrap().leave(id);
+    println!("Client {} left.", id);
+
+    return result;
+}
+
+fn main() -> std::io::Result<()> {
+    let address = std::env::args().nth(1).unwrap_or(DEFAULT_ADDRESS.to_string());
+    let listener = TcpListener::bind(&address)?;
+    let state = Arc::new(Mutex::new(RelayState::default()));
+
+    println!("Scene relay listening on ws://{}", address);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                println!("Connection failed: {}", error);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        std::thread::spawn(move || {
+            if let Err(error) = handle_client(stream, state) {
+                println!("Client error: {}", error);
+            }
+        });
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(site_id: &str, timestamp: u64, value: i32) -> String {
+        return serde_json::to_string(&SyncMessage {
+            site_id: site_id.to_string(),
+            timestamp,
+            path: "scene.property".to_string(),
+            value: serde_json::json!(value),
+        }).unwrap();
+    }
+
+    #[test]
+    fn it_relays_to_other_clients_and_late_joiners() {
+        let mut state = RelayState::default();
+        let (alice_tx, alice_rx) = channel::<String>();
+        let (bob_tx, bob_rx) = channel::<String>();
+        let alice = state.join(alice_tx);
+        let bob = state.join(bob_tx);
+
+        state.relay(alice, message("alice", 2, 1)).unwrap();
+        state.relay(bob, message("bob", 1, 2)).unwrap();
+
+        assert_eq!(bob_rx.try_recv().unwrap(), message("alice", 2, 1));
+        assert_eq!(alice_rx.try_recv().unwrap(), message("bob", 1, 2));
+        assert!(alice_rx.try_recv().is_err());
+
+        // Alice's write has seen more changes, so it is the one kept for new clients.
+        let (carol_tx, carol_rx) = channel::<String>();
+        state.join(carol_tx);
+        assert_eq!(carol_rx.try_recv().unwrap(), message("alice", 2, 1));
+        assert!(carol_rx.try_recv().is_err());
+
+        assert!(state.relay(bob, "not a message".to_string()).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::Instant};
 use serde::{Serialize, Deserialize};
 use sdf_consts::*;
 
@@ -6,13 +6,21 @@ use observable_key_value_tree::{
     ObservableKVTree,
     CanBeNone,
     Update,
-    Snapshot
+    Snapshot,
+    UndoHistory,
+    SharedKVTree,
+    TreeValue,
+    TreeValueRef,
+    Schema,
+    UpdateCursor,
+    OperationLog
 };
 
-use std::sync::{Arc, Mutex};
+use command_central::ParamValue;
 use lazy_static::lazy_static;
 
 use crate::bevy_sdf_object::{SDFObjectMaterial, SDFObject, ControlPointType};
+use crate::command_macros::{CommandMacro, MACROS_PATH};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum EditorState {
@@ -35,9 +43,9 @@ pub enum ClaydashValue {
     Vec4(Vec4),
     String(String),
     Transform(Transform),
+    /// Scenes used to store every object in one vector. Only read to migrate older files.
     VecSDFObject(Vec<SDFObject>),
-    #[serde(skip)]
-    Fn(fn(&mut ObservableKVTree<ClaydashValue>)),
+    SDFObject(SDFObject),
     #[serde(skip)]
     VecUpdate(Vec<Update<ClaydashValue>>),
     #[serde(skip)]
@@ -47,6 +55,7 @@ pub enum ClaydashValue {
     #[serde(skip)]
     Snapshot(Snapshot<ClaydashValue>),
     ControlPointType(ControlPointType),
+    Macro(CommandMacro),
     None,
 }
 
@@ -60,19 +69,14 @@ impl CanBeNone<ClaydashValue> for ClaydashValue {
     fn none() -> Self {
         ClaydashValue::None
     }
+
+    fn is_none_value(value: &ClaydashValue) -> bool {
+        return value.is_none();
+    }
 }
 
 macro_rules! define_unwrap_methods {
-    ($unwrap_method_name:ident, $unwrap_or_default_method_name:ident, $unwrap_or_method_name:ident, $variant:ident, $type:ty, $default: expr) => {
-        pub fn $unwrap_method_name(&self) -> $type {
-            match &self {
-                Self::$variant(value) => *value,
-                _ => {
-                    panic!("No {} value stored.", stringify!($type));
-                }
-            }
-        }
-
+    ($unwrap_or_default_method_name:ident, $unwrap_or_method_name:ident, $variant:ident, $type:ty, $default: expr) => {
         pub fn $unwrap_or_default_method_name(&self) -> $type {
             match &self {
                 Self::$variant(value) => *value,
@@ -90,16 +94,7 @@ macro_rules! define_unwrap_methods {
 }
 
 macro_rules! define_unwrap_methods_for_vec {
-    ($unwrap_method_name:ident, $unwrap_or_method_name:ident, $variant:ident, $type:ty) => {
-        pub fn $unwrap_method_name(&self) -> &$type {
-            match &self {
-                Self::$variant(value) => value,
-                _ => {
-                    panic!("No {} value stored.", stringify!($type));
-                }
-            }
-        }
-
+    ($unwrap_or_method_name:ident, $variant:ident, $type:ty) => {
         /// Warning: this method creates a new value.
         pub fn $unwrap_or_method_name(&self, default_value: $type) -> $type {
             match &self {
@@ -114,8 +109,8 @@ macro_rules! define_unwrap_methods_for_vec {
 
 impl ClaydashValue {
     // Add a few methods to help with unwrapping.
+    // There are no panicking variants: use `get_as` on the tree to handle wrong kinds.
     define_unwrap_methods!(
-        unwrap_uuid,
         unwrap_uuid_or_default,
         unwrap_uuid_or,
         Uuid,
@@ -124,7 +119,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_i32,
         unwrap_i32_or_default,
         unwrap_i32_or,
         I32,
@@ -133,7 +127,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_f32,
         unwrap_f32_or_default,
         unwrap_f32_or,
         F32,
@@ -142,7 +135,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_vec2,
         unwrap_vec2_or_default,
         unwrap_vec2_or,
         Vec2,
@@ -151,7 +143,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_vec3,
         unwrap_vec3_or_default,
         unwrap_vec3_or,
         Vec3,
@@ -160,7 +151,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_vec4,
         unwrap_vec4_or_default,
         unwrap_vec4_or,
         Vec4,
@@ -169,7 +159,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_transform,
         unwrap_transform_or_default,
         unwrap_transform_or,
         Transform,
@@ -178,16 +167,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_fn,
-        unwrap_fn_or_default,
-        unwrap_fn_or,
-        Fn,
-        fn(&mut ObservableKVTree<ClaydashValue>),
-        panic!("No Fn value stored.")
-    );
-
-    define_unwrap_methods!(
-        unwrap_bool,
         unwrap_bool_or_default,
         unwrap_bool_or,
         Bool,
@@ -196,7 +175,6 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods!(
-        unwrap_control_point_type,
         unwrap_control_point_type_or_default,
         unwrap_control_point_type_or,
         ControlPointType,
@@ -205,42 +183,36 @@ impl ClaydashValue {
     );
 
     define_unwrap_methods_for_vec!(
-        unwrap_editor_state,
         unwrap_editor_state_or,
         EditorState,
         EditorState
     );
 
     define_unwrap_methods_for_vec!(
-        unwrap_vec_uuid,
         unwrap_vec_uuid_or,
         VecUuid,
         Vec<uuid::Uuid>
     );
 
     define_unwrap_methods_for_vec!(
-        unwrap_vec_sdf_object,
         unwrap_vec_sdf_object_or,
         VecSDFObject,
         Vec<SDFObject>
     );
 
     define_unwrap_methods_for_vec!(
-        unwrap_vec_update,
         unwrap_vec_update_or,
         VecUpdate,
         Vec<Update<ClaydashValue>>
     );
 
     define_unwrap_methods_for_vec!(
-        unwrap_vec_snapshot,
         unwrap_vec_snapshot_or,
         VecSnapshot,
         Vec<Snapshot<ClaydashValue>>
     );
 
     define_unwrap_methods_for_vec!(
-        unwrap_vec_i32,
         unwrap_vec_i32_or,
         VecI32,
         Vec<i32>
@@ -252,11 +224,245 @@ impl ClaydashValue {
             _ => false,
         }
     }
+
+    /// Name of the variant, used by the tree schema and command parameter types.
+    pub fn kind(&self) -> &'static str {
+        return match &self {
+            Self::Uuid(_) => "Uuid",
+            Self::VecUuid(_) => "VecUuid",
+            Self::VecI32(_) => "VecI32",
+            Self::I32(_) => "I32",
+            Self::F32(_) => "F32",
+            Self::Vec2(_) => "Vec2",
+            Self::Vec3(_) => "Vec3",
+            Self::Vec4(_) => "Vec4",
+            Self::String(_) => "String",
+            Self::Transform(_) => "Transform",
+            Self::VecSDFObject(_) => "VecSDFObject",
+            Self::SDFObject(_) => "SDFObject",
+            Self::VecUpdate(_) => "VecUpdate",
+            Self::VecSnapshot(_) => "VecSnapshot",
+            Self::EditorState(_) => "EditorState",
+            Self::Bool(_) => "Bool",
+            Self::Snapshot(_) => "Snapshot",
+            Self::ControlPointType(_) => "ControlPointType",
+            Self::Macro(_) => "Macro",
+            Self::None => "None",
+        };
+    }
+
+    /// Rough estimate of the memory used by a value, including vector contents.
+    /// Used to bound the undo history.
+    pub fn estimated_size(&self) -> usize {
+        let heap_size = match &self {
+            Self::VecUuid(values) => values.len() * std::mem::size_of::<uuid::Uuid>(),
+            Self::VecI32(values) => values.len() * std::mem::size_of::<i32>(),
+            Self::String(value) => value.len(),
+            Self::VecSDFObject(objects) => objects.len() * std::mem::size_of::<SDFObject>(),
+            Self::VecUpdate(updates) => updates.len() * std::mem::size_of::<Update<ClaydashValue>>(),
+            Self::VecSnapshot(snapshots) => snapshots.len() * std::mem::size_of::<Snapshot<ClaydashValue>>(),
+            _ => 0
+        };
+
+        return std::mem::size_of::<Self>() + heap_size;
+    }
 }
 
-#[derive(Resource, Default)]
+// Typed access to values, used by tree bindings and typed getters.
+macro_rules! impl_tree_value {
+    ($variant:ident, $type:ty) => {
+        impl TreeValue<ClaydashValue> for $type {
+            fn from_tree_value(value: &ClaydashValue) -> Option<Self> {
+                match value {
+                    ClaydashValue::$variant(value) => Some(value.clone()),
+                    _ => None
+                }
+            }
+
+            fn to_tree_value(&self) -> ClaydashValue {
+                return ClaydashValue::$variant(self.clone());
+            }
+        }
+
+        impl TreeValueRef<ClaydashValue> for $type {
+            fn from_tree_value_ref(value: &ClaydashValue) -> Option<&Self> {
+                match value {
+                    ClaydashValue::$variant(value) => Some(value),
+                    _ => None
+                }
+            }
+        }
+    };
+}
+
+impl_tree_value!(Uuid, uuid::Uuid);
+impl_tree_value!(VecUuid, Vec<uuid::Uuid>);
+impl_tree_value!(VecI32, Vec<i32>);
+impl_tree_value!(I32, i32);
+impl_tree_value!(F32, f32);
+impl_tree_value!(Vec2, Vec2);
+impl_tree_value!(Vec3, Vec3);
+impl_tree_value!(Vec4, Vec4);
+impl_tree_value!(String, String);
+impl_tree_value!(Transform, Transform);
+impl_tree_value!(VecSDFObject, Vec<SDFObject>);
+impl_tree_value!(SDFObject, SDFObject);
+impl_tree_value!(EditorState, EditorState);
+impl_tree_value!(Bool, bool);
+impl_tree_value!(ControlPointType, ControlPointType);
+impl_tree_value!(Macro, CommandMacro);
+
+// Values passed to and returned by commands.
+impl ParamValue for ClaydashValue {
+    fn type_name(&self) -> &'static str {
+        return self.kind();
+    }
+
+    fn numeric_components(&self) -> Vec<f32> {
+        return match &self {
+            Self::I32(value) => vec!(*value as f32),
+            Self::F32(value) => vec!(*value),
+            Self::Vec2(value) => value.to_array().to_vec(),
+            Self::Vec3(value) => value.to_array().to_vec(),
+            Self::Vec4(value) => value.to_array().to_vec(),
+            _ => Vec::new(),
+        };
+    }
+}
+
+/// Content of a `.claydash` file.
+#[derive(Serialize, Deserialize)]
+pub struct ClaydashDocument {
+    pub scene: ObservableKVTree<ClaydashValue>,
+    /// Only saved when `editor.save_undo_history` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undo_history: Option<UndoHistory<ClaydashValue>>,
+    /// Command macros recorded in this document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub macros: Option<ObservableKVTree<ClaydashValue>>,
+}
+
+impl ClaydashDocument {
+    pub fn from_tree(tree: &ObservableKVTree<ClaydashValue>) -> Self {
+        let save_undo_history = tree.get_path("editor.save_undo_history").unwrap_bool_or(false);
+
+        return Self {
+            scene: tree.get_tree("scene").unwrap_or_default(),
+            undo_history: match save_undo_history {
+                // Editor state is not part of the document.
+                true => Some(tree.export_undo_history_under("scene")),
+                false => None
+            },
+            macros: tree.get_tree(MACROS_PATH),
+        };
+    }
+
+    /// Older files only contain the scene, they are also accepted.
+    /// If the data is neither, the error of parsing it as a document is returned.
+    /// Scenes storing objects in one vector are migrated, and their undo history is dropped
+    /// since it refers to the old paths.
+    pub fn from_slice(data: &[u8]) -> Result<Self, serde_json::Error> {
+        let document = match serde_json::from_slice::<ClaydashDocument>(data) {
+            Ok(document) => document,
+            Err(document_error) => {
+                // When the file is not a scene either, the document error is the relevant one.
+                match serde_json::from_slice::<ObservableKVTree<ClaydashValue>>(data) {
+                    Ok(scene) => Self { scene, undo_history: None, macros: None },
+                    Err(_) => { return Err(document_error); }
+                }
+            }
+        };
+
+        if document.scene.get_ref("sdf_objects").is_none() {
+            return Ok(document);
+        }
+        return Ok(Self {
+            scene: migrate_scene(document.scene),
+            undo_history: None,
+            macros: document.macros,
+        });
+    }
+
+    /// The document is not loaded if its scene does not match the schema.
+    pub fn load_into(self, tree: &mut ObservableKVTree<ClaydashValue>) -> Result<(), String> {
+        if let Err(errors) = tree.try_set_tree("scene", self.scene) {
+            let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            return Err(messages.join("\n"));
+        }
+
+        // Undo steps of the previous document don't apply to this one.
+        match self.undo_history {
+            Some(undo_history) => tree.import_undo_history(undo_history),
+            None => tree.clear_undo_history(),
+        }
+
+        // Macros are added to the ones already recorded, and are not part of undo history.
+        if let Some(macros) = self.macros {
+            for (name, command_macro) in macros.children("") {
+                if let ClaydashValue::Macro(_) = command_macro.value() {
+                    tree.set_path_untracked(&format!("{}.{}", MACROS_PATH, name), command_macro.value().clone());
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Kinds of values expected in the tree.
+/// Values of the wrong kind are refused, so reading these paths can't fail on a type mismatch.
+pub fn claydash_schema() -> Schema<ClaydashValue> {
+    return Schema::new(ClaydashValue::kind)
+        .optional("scene.objects.*", "SDFObject")
+        .optional("scene.object_order", "VecUuid")
+        .required("scene.selected_uuids", "VecUuid", ClaydashValue::VecUuid(Vec::new()))
+        .required("editor.state", "EditorState", ClaydashValue::EditorState(EditorState::Start))
+        .required("editor.constrain_x", "Bool", ClaydashValue::Bool(false))
+        .required("editor.constrain_y", "Bool", ClaydashValue::Bool(false))
+        .required("editor.constrain_z", "Bool", ClaydashValue::Bool(false))
+        .optional("editor.initial_selection_transform", "Transform")
+        .optional("editor.initial_radius", "F32")
+        .optional("editor.initial_transforms.*.transform", "Transform")
+        .optional("editor.initial_transforms.*.relative_to_selection", "Transform")
+        .optional("editor.current_control_point_object_uuid", "Uuid")
+        .optional("editor.current_control_point_type", "ControlPointType")
+        .optional("editor.colorpicker.color", "Vec4")
+        .optional("editor.interaction_label", "String")
+        .optional("editor.save_undo_history", "Bool")
+        .optional("editor.sync.enabled", "Bool")
+        .optional("editor.sync.address", "String")
+        .optional("macros.*", "Macro");
+}
+
+#[derive(Resource)]
 pub struct ClaydashData {
-    pub tree: ObservableKVTree<ClaydashValue>
+    pub tree: ObservableKVTree<ClaydashValue>,
+    /// Handle for async tasks: reads the tree as of the start of the frame, queues writes for the next frame.
+    pub shared: SharedKVTree<ClaydashValue>,
+}
+
+impl Default for ClaydashData {
+    fn default() -> Self {
+        let mut tree = ObservableKVTree::<ClaydashValue>::default();
+        // The tree is empty, so there is nothing to refuse.
+        _ = tree.set_schema(claydash_schema());
+        // Computed from scene paths only, so there is no cycle.
+        tree.add_computed_from_subtrees(
+            ACTIVE_OBJECT_INDEX_PATH,
+            &[OBJECTS_PATH, "scene.selected_uuids", OBJECT_ORDER_PATH],
+            compute_active_object_index
+        ).unwrap();
+        tree.add_computed_from_subtrees(
+            SELECTION_CENTER_PATH,
+            &[OBJECTS_PATH, "scene.selected_uuids"],
+            compute_selection_center
+        ).unwrap();
+
+        return Self {
+            tree,
+            shared: SharedKVTree::default(),
+        };
+    }
 }
 
 pub struct ClaydashDataPlugin;
@@ -264,103 +470,291 @@ pub struct ClaydashDataPlugin;
 impl Plugin for ClaydashDataPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ClaydashData>()
-            .add_systems(Update, sync_to_bevy);
+            .init_resource::<SessionReplay>()
+            .add_systems(PreUpdate, (sync_shared_tree, advance_session_replay))
+            .add_systems(Last, reset_update_cycle)
+            .add_systems(Update, (sync_sdf_objects_to_bevy, sync_selection_to_bevy).chain());
     }
 }
 
 lazy_static! {
-    static ref LAST_SYNCED_SDF_OBJECTS_VERSION: Arc<Mutex<i32>> = Arc::new(Mutex::new(-1));
+    static ref SESSION_START: Instant = Instant::now();
 }
 
-pub fn get_active_object_index(tree: &ObservableKVTree<ClaydashValue>) -> Option<usize> {
-    let objects = tree.get_path("scene.sdf_objects");
-    let uuids = tree.get_path("scene.selected_uuids");
-    let uuids = uuids.unwrap_vec_uuid();
+/// Seconds since the session started.
+/// Used as the clock of the operation log.
+pub fn session_clock() -> f64 {
+    return SESSION_START.elapsed().as_secs_f64();
+}
 
-    // Last selected object is the active object
-    for (index, object) in objects.unwrap_vec_sdf_object().iter().enumerate().rev() {
-        if uuids.contains(&object.uuid) {
-            return Some(index);
-        }
+pub fn toggle_session_recording(tree: &mut ObservableKVTree<ClaydashValue>) {
+    match tree.operation_log {
+        Some(_) => { tree.stop_operation_log(); },
+        None => { tree.start_operation_log(session_clock); }
     }
+}
 
-    return None;
+/// Session log being replayed at the pace it was recorded.
+#[derive(Resource, Default)]
+pub struct SessionReplay {
+    log: Option<OperationLog<ClaydashValue>>,
+    next_operation: usize,
+    started_at: f64,
 }
 
-// Sync tree to bevy
-// Once the tree supports different update flags, we can split this in separate systems again.
-fn sync_to_bevy(
+impl SessionReplay {
+    /// Replaces the replay in progress, if any.
+    pub fn start(&mut self, log: OperationLog<ClaydashValue>) {
+        self.log = Some(log);
+        self.next_operation = 0;
+        self.started_at = session_clock();
+    }
+}
+
+/// Applies the operations of the replayed log that are due this frame.
+fn advance_session_replay(
+    mut session_replay: ResMut<SessionReplay>,
     mut data_resource: ResMut<ClaydashData>,
-    material_handle: Query<&Handle<SDFObjectMaterial>>,
-    mut materials: ResMut<Assets<SDFObjectMaterial>>,
 ) {
+    let replay = session_replay.as_mut();
+    let log = match &replay.log {
+        Some(log) => log,
+        None => { return; }
+    };
+
+    let elapsed = session_clock() - replay.started_at;
+    replay.next_operation = log.replay_until(&mut data_resource.tree, replay.next_operation, elapsed);
+
+    if log.is_replayed(replay.next_operation) {
+        println!("Replayed {} operations.", log.operations.len());
+        replay.log = None;
+    }
+}
+
+/// `was_updated` flags (and the `was_*_updated` methods of tree bindings) describe the changes
+/// made during the current frame. Systems that may miss a frame use an `UpdateCursor` instead.
+fn reset_update_cycle(mut data_resource: ResMut<ClaydashData>) {
+    data_resource.tree.reset_update_cycle();
+}
+
+/// Applies writes queued by async tasks and publishes the tree for them.
+fn sync_shared_tree(mut data_resource: ResMut<ClaydashData>) {
     let data = data_resource.as_mut();
+    data.shared.sync(&mut data.tree);
+}
+
+/// Objects are stored one per path, by uuid, so editing an object only stores that object
+/// in undo history, and users editing different objects don't overwrite each other.
+pub const OBJECTS_PATH: &str = "scene.objects";
+/// Uuids of objects, in the order they were added. Objects keep their index in the shader
+/// when other objects are added or removed.
+pub const OBJECT_ORDER_PATH: &str = "scene.object_order";
+
+pub fn object_path(uuid: &uuid::Uuid) -> String {
+    return format!("{}.{}", OBJECTS_PATH, uuid);
+}
 
-    let version = data.tree.path_version("scene.sdf_objects");
+/// Objects of a subtree stored like `OBJECTS_PATH`, in uuid order.
+fn objects_in(objects: &ObservableKVTree<ClaydashValue>) -> Vec<&SDFObject> {
+    return objects.children("").filter_map(|(_, node)| match node.value() {
+        ClaydashValue::SDFObject(object) => Some(object),
+        _ => None
+    }).collect();
+}
 
-    let last_updated_version = LAST_SYNCED_SDF_OBJECTS_VERSION.try_lock();
+/// Objects in the order of `order`. Objects missing from it, like ones added by another user
+/// at the same time, come after, in uuid order.
+fn ordered_objects<'a>(objects: &'a ObservableKVTree<ClaydashValue>, order: &[uuid::Uuid]) -> Vec<&'a SDFObject> {
+    let mut objects = objects_in(objects);
+    objects.sort_by_key(|object| order.iter().position(|uuid| *uuid == object.uuid).unwrap_or(order.len()));
+    return objects;
+}
 
-    let mut last_updated_version = match last_updated_version {
-        Ok(version) => { version  }
-        _ => { return }
+fn object_order(tree: &ObservableKVTree<ClaydashValue>) -> &[uuid::Uuid] {
+    return tree.get_ref_as::<Vec<uuid::Uuid>>(OBJECT_ORDER_PATH)
+        .map(|order| order.as_slice())
+        .unwrap_or(&[]);
+}
+
+/// Objects of the scene, in the order they were added. Indices in this list are indices in the shader.
+pub fn scene_objects(tree: &ObservableKVTree<ClaydashValue>) -> Vec<&SDFObject> {
+    return match tree.get_tree_ref(OBJECTS_PATH) {
+        Some(objects) => ordered_objects(objects, object_order(tree)),
+        None => Vec::new()
     };
+}
 
-    if version > *last_updated_version  {
-        // Potentially: move this block to bevy_sdf_object
-        // Update sdf objects
-        {
-            let handle = material_handle.single();
-            let material: &mut SDFObjectMaterial = materials.get_mut(handle).unwrap();
-            material.sdf_meta[0].w = TYPE_END;
+pub fn scene_object<'a>(tree: &'a ObservableKVTree<ClaydashValue>, uuid: &uuid::Uuid) -> Option<&'a SDFObject> {
+    return tree.get_ref_as::<SDFObject>(&object_path(uuid)).ok();
+}
 
-            let value = data.tree.get_path("scene.sdf_objects");
+/// Adds or replaces an object. New objects come after the others.
+pub fn set_scene_object(tree: &mut ObservableKVTree<ClaydashValue>, object: SDFObject) {
+    let order = object_order(tree);
+    if !order.contains(&object.uuid) {
+        let mut order = order.to_vec();
+        order.push(object.uuid);
+        tree.set_path(OBJECT_ORDER_PATH, ClaydashValue::VecUuid(order));
+    }
+    tree.set_path(&object_path(&object.uuid), ClaydashValue::SDFObject(object));
+}
+
+/// Setting none removes the object's node. Undo history keeps the object, so undo can bring it back.
+pub fn remove_scene_object(tree: &mut ObservableKVTree<ClaydashValue>, uuid: &uuid::Uuid) {
+    let order = object_order(tree);
+    if order.contains(uuid) {
+        let order: Vec<uuid::Uuid> = order.iter().filter(|order_uuid| *order_uuid != uuid).cloned().collect();
+        tree.set_path(OBJECT_ORDER_PATH, ClaydashValue::VecUuid(order));
+    }
+    tree.set_path(&object_path(uuid), ClaydashValue::None);
+}
 
-            for (index, object) in value.unwrap_vec_sdf_object().iter().enumerate() {
-                object.params.update_material(index, material);
+/// Scenes used to store every object in a `sdf_objects` vector. Moves them to one path per object.
+pub fn migrate_scene(scene: ObservableKVTree<ClaydashValue>) -> ObservableKVTree<ClaydashValue> {
+    let legacy_objects = match scene.get_ref("sdf_objects") {
+        Some(ClaydashValue::VecSDFObject(objects)) => objects.clone(),
+        _ => { return scene; }
+    };
 
-                material.sdf_meta[index].w = object.object_type;
-                material.sdf_colors[index] = object.color;
-                material.sdf_inverse_transforms[index] = object.inverse_transform_matrix();
-                material.sdf_meta[index + 1].w = TYPE_END;
-            }
+    let mut migrated = ObservableKVTree::<ClaydashValue>::default();
+    for (key, child) in scene.children("") {
+        if key != "sdf_objects" {
+            migrated.set_tree(key, child.clone());
         }
-
-        *last_updated_version = version;
+    }
+    let order: Vec<uuid::Uuid> = legacy_objects.iter().map(|object| object.uuid).collect();
+    migrated.set_path("object_order", ClaydashValue::VecUuid(order));
+    for object in legacy_objects {
+        migrated.set_path(&format!("objects.{}", object.uuid), ClaydashValue::SDFObject(object));
     }
 
-    if data.tree.was_path_updated("scene.selected_uuids") || data.tree.was_path_updated("scene.sdf_objects"){
-        let active_object_index = get_active_object_index(&data.tree);
-        let objects = data.tree.get_path("scene.sdf_objects");
-        let uuids = data.tree.get_path("scene.selected_uuids");
-        let uuids = uuids.unwrap_vec_uuid();
-
-        // Reset in case no material is selected
-        let handle = material_handle.single();
-        let material: &mut SDFObjectMaterial = materials.get_mut(handle).unwrap();
-        material.num_control_points[0] = 0;
-
-        for (index, object) in objects.unwrap_vec_sdf_object().iter().enumerate() {
-            if uuids.contains(&object.uuid) {
-                // Mark as selected
-                material.sdf_meta[index].x = 1;
-            } else {
-                // Mark as not-selected
-                material.sdf_meta[index].x = 0;
-            }
+    return migrated;
+}
+
+pub const ACTIVE_OBJECT_INDEX_PATH: &str = "editor.computed.active_object_index";
+pub const SELECTION_CENTER_PATH: &str = "editor.computed.selection_center";
+
+/// Inputs: sdf objects, selected uuids, object order.
+fn compute_active_object_index(inputs: &[&ObservableKVTree<ClaydashValue>]) -> ClaydashValue {
+    let order: &[uuid::Uuid] = match inputs[2].value() {
+        ClaydashValue::VecUuid(order) => order,
+        _ => &[]
+    };
+    let objects = ordered_objects(inputs[0], order);
+    let ClaydashValue::VecUuid(uuids) = inputs[1].value() else {
+        return ClaydashValue::None;
+    };
+
+    // Last selected object is the active object
+    for uuid in uuids.iter().rev() {
+        if let Some(index) = objects.iter().position(|object| object.uuid == *uuid) {
+            return ClaydashValue::I32(index as i32);
         }
+    }
 
-        match active_object_index  {
-            Some(index) => {
-                // Show control points
-                let object = &objects.unwrap_vec_sdf_object()[index];
-                show_control_points(material, index, object);
-            },
-            _ => {}
+    return ClaydashValue::None;
+}
+
+/// Inputs: sdf objects, selected uuids.
+/// Average position of the selected objects.
+fn compute_selection_center(inputs: &[&ObservableKVTree<ClaydashValue>]) -> ClaydashValue {
+    let objects = objects_in(inputs[0]);
+    let ClaydashValue::VecUuid(uuids) = inputs[1].value() else {
+        return ClaydashValue::None;
+    };
+
+    let mut selected_object_sum_position: Vec3 = Vec3::ZERO;
+    let mut selected_object_count: i32 = 0;
+
+    for object in objects.iter() {
+        if uuids.contains(&object.uuid) {
+            selected_object_sum_position += object.transform.translation;
+            selected_object_count += 1;
         }
     }
 
+    if selected_object_count == 0 {
+        return ClaydashValue::None;
+    }
+
+    return ClaydashValue::Vec3(selected_object_sum_position / (selected_object_count as f32));
+}
+
+pub fn get_active_object_index(tree: &ObservableKVTree<ClaydashValue>) -> Option<usize> {
+    return tree.get_as::<i32>(ACTIVE_OBJECT_INDEX_PATH).ok().map(|index| index as usize);
+}
+
+pub fn get_selection_center(tree: &ObservableKVTree<ClaydashValue>) -> Option<Vec3> {
+    return tree.get_as::<Vec3>(SELECTION_CENTER_PATH).ok();
+}
 
-    data.tree.reset_update_cycle();
+// Sync tree to bevy
+// Each system tracks its own changes with a cursor.
+fn sync_sdf_objects_to_bevy(
+    data: Res<ClaydashData>,
+    mut cursor: Local<UpdateCursor>,
+    material_handle: Query<&Handle<SDFObjectMaterial>>,
+    mut materials: ResMut<Assets<SDFObjectMaterial>>,
+) {
+    if !cursor.take_changes(&data.tree, &[OBJECTS_PATH, OBJECT_ORDER_PATH]) {
+        return;
+    }
+
+    // Potentially: move this system to bevy_sdf_object
+    let handle = material_handle.single();
+    let material: &mut SDFObjectMaterial = materials.get_mut(handle).unwrap();
+    material.sdf_meta[0].w = TYPE_END;
+
+    let objects = scene_objects(&data.tree);
+
+    for (index, object) in objects.iter().enumerate() {
+        object.params.update_material(index, material);
+
+        material.sdf_meta[index].w = object.object_type;
+        material.sdf_colors[index] = object.color;
+        material.sdf_inverse_transforms[index] = object.inverse_transform_matrix();
+        material.sdf_meta[index + 1].w = TYPE_END;
+    }
+}
+
+fn sync_selection_to_bevy(
+    data: Res<ClaydashData>,
+    mut cursor: Local<UpdateCursor>,
+    material_handle: Query<&Handle<SDFObjectMaterial>>,
+    mut materials: ResMut<Assets<SDFObjectMaterial>>,
+) {
+    if !cursor.take_changes(&data.tree, &["scene.selected_uuids", OBJECTS_PATH, OBJECT_ORDER_PATH]) {
+        return;
+    }
+
+    let active_object_index = get_active_object_index(&data.tree);
+    let objects = scene_objects(&data.tree);
+    let uuids: &[uuid::Uuid] = data.tree.get_ref_as::<Vec<uuid::Uuid>>("scene.selected_uuids")
+        .map(|uuids| uuids.as_slice())
+        .unwrap_or(&[]);
+
+    // Reset in case no material is selected
+    let handle = material_handle.single();
+    let material: &mut SDFObjectMaterial = materials.get_mut(handle).unwrap();
+    material.num_control_points[0] = 0;
+
+    for (index, object) in objects.iter().enumerate() {
+        if uuids.contains(&object.uuid) {
+            // Mark as selected
+            material.sdf_meta[index].x = 1;
+        } else {
+            // Mark as not-selected
+            material.sdf_meta[index].x = 0;
+        }
+    }
+
+    match active_object_index  {
+        Some(index) => {
+            // Show control points
+            show_control_points(material, index, objects[index]);
+        },
+        _ => {}
+    }
 }
 
 fn show_control_points(material: &mut SDFObjectMaterial, index: usize, object: &SDFObject) {
@@ -3,27 +3,28 @@ use bevy::{
     winit::WinitWindows,
     tasks::AsyncComputeTaskPool,
 };
-use crate::bevy_sdf_object::SDFObject;
-use crate::command_central_plugin::CommandCentralState;
+use crate::command_central_plugin::{CommandCentralState, ClaydashCommandBuilder, queue_command};
+use command_central::{CommandBuilder, CommandArgs, Menu, MenuItem};
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use egui::containers::Frame;
 use egui::Color32;
 use epaint::{Stroke, Pos2};
-use crate::claydash_data::{ClaydashValue, ClaydashData};
-use observable_key_value_tree::ObservableKVTree;
+use crate::claydash_data::{ClaydashValue, ClaydashData, ClaydashDocument, SessionReplay, scene_object, set_scene_object};
+use observable_key_value_tree::{ObservableKVTree, OperationLog, TreeError};
 use crate::command_central_egui::{CommandCentralUiState, command_ui};
+use crate::interactions::PendingShortcut;
+use crate::undo_redo::undo_redo_menu_label;
 use rfd::FileHandle;
+use std::future::Future;
 use std::sync::mpsc::{channel, Sender, Receiver};
 
-use crate::undo_redo::{UNDO_SHORTCUT, REDO_SHORTCUT};
-
 pub struct ClaydashUIPlugin;
 
 impl Plugin for ClaydashUIPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(EguiPlugin)
             .init_resource::<CommandCentralUiState>()
-            .add_systems(Startup, (setup_messages, color_picker_ui))
+            .add_systems(Startup, (setup_messages, register_file_commands, color_picker_ui))
             .add_systems(Update, (
                 claydash_ui,
                 handle_tasks
@@ -34,7 +35,10 @@ impl Plugin for ClaydashUIPlugin {
 enum UiMessage {
     SaveFileHandle(FileHandle),
     OpenFileHandle(FileHandle),
-    VecU8(Vec<u8>),
+    SaveSessionLogHandle(FileHandle),
+    OpenSessionLogHandle(FileHandle),
+    ReplaySessionLog(OperationLog<ClaydashValue>),
+    CompareFileHandle(FileHandle),
 }
 
 struct UiMessagesTxRxResource {
@@ -42,6 +46,21 @@ struct UiMessagesTxRxResource {
     rx: Receiver<UiMessage>,
 }
 
+/// Waits for a file dialog, then sends the picked file to the main thread.
+fn send_picked_file(
+    tx: Sender<UiMessage>,
+    dialog: impl Future<Output = Option<FileHandle>> + Send + 'static,
+    message: fn(FileHandle) -> UiMessage,
+) {
+    let thread_pool = AsyncComputeTaskPool::get();
+    let _task = thread_pool.spawn(async move {
+        if let Some(file) = dialog.await {
+            _ = tx.send(message(file));
+        }
+    });
+    _task.detach();
+}
+
 fn setup_messages(world: &mut World) {
     let (tx, rx) = channel::<UiMessage>();
     let ui_message: UiMessagesTxRxResource = UiMessagesTxRxResource { tx, rx };
@@ -49,15 +68,120 @@ fn setup_messages(world: &mut World) {
     world.insert_non_send_resource(ui_message);
 }
 
+fn save_file_dialog(ui_messages: NonSend<UiMessagesTxRxResource>) {
+    let dialog = rfd::AsyncFileDialog::new()
+        .add_filter("claydash workspace", &["claydash"])
+        .save_file();
+    send_picked_file(ui_messages.tx.clone(), dialog, UiMessage::SaveFileHandle);
+}
+
+fn open_file_dialog(ui_messages: NonSend<UiMessagesTxRxResource>) {
+    let dialog = rfd::AsyncFileDialog::new().pick_file();
+    send_picked_file(ui_messages.tx.clone(), dialog, UiMessage::OpenFileHandle);
+}
+
+fn compare_file_dialog(ui_messages: NonSend<UiMessagesTxRxResource>) {
+    let dialog = rfd::AsyncFileDialog::new()
+        .add_filter("claydash workspace", &["claydash"])
+        .pick_file();
+    send_picked_file(ui_messages.tx.clone(), dialog, UiMessage::CompareFileHandle);
+}
+
+fn save_session_log_dialog(
+    ui_messages: NonSend<UiMessagesTxRxResource>,
+    data_resource: Res<ClaydashData>,
+) {
+    if data_resource.tree.recorded_operation_log().is_none() {
+        println!("No session was recorded.");
+        return;
+    }
+    let dialog = rfd::AsyncFileDialog::new()
+        .add_filter("claydash session log", &["jsonl"])
+        .save_file();
+    send_picked_file(ui_messages.tx.clone(), dialog, UiMessage::SaveSessionLogHandle);
+}
+
+fn replay_session_log_dialog(ui_messages: NonSend<UiMessagesTxRxResource>) {
+    let dialog = rfd::AsyncFileDialog::new()
+        .add_filter("claydash session log", &["jsonl"])
+        .pick_file();
+    send_picked_file(ui_messages.tx.clone(), dialog, UiMessage::OpenSessionLogHandle);
+}
+
+/// File commands open dialogs, so they run as one-shot systems instead of tree callbacks.
+fn register_file_commands(world: &mut World) {
+    let save = world.register_system(save_file_dialog);
+    let open = world.register_system(open_file_dialog);
+    let compare = world.register_system(compare_file_dialog);
+    let save_session_log = world.register_system(save_session_log_dialog);
+    let replay_session_log = world.register_system(replay_session_log_dialog);
+
+    let mut bevy_command_central = world.resource_mut::<CommandCentralState>();
+    let state = bevy_command_central.as_mut();
+
+    CommandBuilder::new()
+        .title("Save")
+        .system_name("save")
+        .docs("Save the scene to a .claydash file.")
+        .category("File")
+        .mutates_document(false)
+        .menu("File/Save", 0)
+        .system_callback(save)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Open")
+        .system_name("open")
+        .docs("Open a .claydash file, replacing the current scene.")
+        .category("File")
+        // The file is loaded later, and loading it replaces the undo history.
+        .mutates_document(false)
+        .menu("File/Open", 1)
+        .system_callback(open)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Compare with file")
+        .system_name("compare-with-file")
+        .docs("Print the differences between the current scene and a .claydash file.")
+        .category("File")
+        .mutates_document(false)
+        .menu("File/Compare with file", 2)
+        .system_callback(compare)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Save session log")
+        .system_name("save-session-log")
+        .docs("Save the changes recorded since session recording started, or of the last recording.")
+        .category("File")
+        .mutates_document(false)
+        .menu("File/Save session log", 101)
+        .when_tree("No session was recorded", |tree| tree.recorded_operation_log().is_some())
+        .system_callback(save_session_log)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Replay session log")
+        .system_name("replay-session-log")
+        .docs("Apply the changes of a saved session log to the current scene, at the pace they were recorded.")
+        .category("File")
+        .menu("File/Replay session log", 102)
+        .system_callback(replay_session_log)
+        .register(state);
+}
+
 fn handle_tasks(
     ui_messages: NonSendMut<UiMessagesTxRxResource>,
     mut data_resource: ResMut<ClaydashData>,
+    mut session_replay: ResMut<SessionReplay>,
 ) {
-    let tree = &mut data_resource.as_mut().tree;
+    let data = data_resource.as_mut();
+    let tree = &mut data.tree;
 
     match ui_messages.rx.try_recv() {
         Ok(UiMessage::SaveFileHandle(file)) => {
-            match serde_json::to_vec(&tree.get_tree("scene")) {
+            match serde_json::to_vec(&ClaydashDocument::from_tree(tree)) {
                 Ok(serialized_tree) => {
                     let thread_pool = AsyncComputeTaskPool::get();
                     let _task = thread_pool.spawn(async move {
@@ -71,93 +195,205 @@ fn handle_tasks(
         },
         Ok(UiMessage::OpenFileHandle(file)) => {
             let thread_pool = AsyncComputeTaskPool::get();
-            let tx = ui_messages.tx.clone();
+            let shared = data.shared.clone();
             let _task = thread_pool.spawn(async move {
                 let data = file.read().await;
-                _ = tx.send(UiMessage::VecU8(data));
+                match ClaydashDocument::from_slice(&data) {
+                    Ok(document) => {
+                        let file_name = file.file_name();
+                        shared.queue_write(move |tree| {
+                            match document.load_into(tree) {
+                                Ok(()) => { println!("Updated tree! {}", tree.version()); },
+                                Err(error) => { println!("Could not load {}: {}", file_name, error); }
+                            }
+                        });
+                    },
+                    Err(error) => { println!("Could not load {}: {}", file.file_name(), error); }
+                }
             });
             _task.detach();
         },
-        Ok(UiMessage::VecU8(data)) => {
-            let tree = &mut data_resource.as_mut().tree;
-            let scene: Result<ObservableKVTree<ClaydashValue>, serde_json::Error> = serde_json::from_slice(&data);
-            match scene {
-                Ok(scene) => {
-                    tree.set_tree("scene", scene);
-                    println!("Updated tree! {}", tree.path_version("scene"));
+        Ok(UiMessage::SaveSessionLogHandle(file)) => {
+            let json_lines = match tree.recorded_operation_log() {
+                Some(operation_log) => operation_log.to_json_lines(),
+                None => { return; }
+            };
+            // A stopped recording is kept until it is saved.
+            if tree.operation_log.is_none() && json_lines.is_ok() {
+                tree.last_operation_log = None;
+            }
+            match json_lines {
+                Ok(json_lines) => {
+                    let thread_pool = AsyncComputeTaskPool::get();
+                    let _task = thread_pool.spawn(async move {
+                        let _ = file.write(json_lines.as_bytes()).await;
+                        println!("Saved session log {}", file.file_name());
+                    });
+                    _task.detach();
                 },
-                _ => {
-                    panic!("could not load data.");
-                }
+                Err(error) => { println!("Error serializing session log: {}", error); }
             }
-        }
+        },
+        Ok(UiMessage::OpenSessionLogHandle(file)) => {
+            let thread_pool = AsyncComputeTaskPool::get();
+            let tx = ui_messages.tx.clone();
+            let _task = thread_pool.spawn(async move {
+                let data = file.read().await;
+                let log = String::from_utf8(data)
+                    .map_err(|error| error.to_string())
+                    .and_then(|json_lines| {
+                        OperationLog::<ClaydashValue>::from_json_lines(&json_lines).map_err(|error| error.to_string())
+                    });
+                match log {
+                    Ok(log) => { _ = tx.send(UiMessage::ReplaySessionLog(log)); },
+                    Err(error) => { println!("Could not read session log: {}", error); }
+                }
+            });
+            _task.detach();
+        },
+        Ok(UiMessage::ReplaySessionLog(log)) => {
+            println!("Replaying {} operations.", log.operations.len());
+            session_replay.start(log);
+        },
+        Ok(UiMessage::CompareFileHandle(file)) => {
+            let thread_pool = AsyncComputeTaskPool::get();
+            let shared = data.shared.clone();
+            let _task = thread_pool.spawn(async move {
+                let data = file.read().await;
+                // Compare with the scene as it is when the file is read.
+                let current_scene = shared.read().get_tree("scene").unwrap_or_default();
+                let diff = ClaydashDocument::from_slice(&data)
+                    .and_then(|document| current_scene.diff(&document.scene));
+                match diff {
+                    Ok(diff) => {
+                        println!("Compared current scene with file:");
+                        for path in diff.added.keys() {
+                            println!("  added: scene.{}", path);
+                        }
+                        for path in diff.removed.keys() {
+                            println!("  removed: scene.{}", path);
+                        }
+                        for path in diff.changed.keys() {
+                            println!("  changed: scene.{}", path);
+                        }
+                    },
+                    Err(error) => { println!("Could not compare with file: {}", error); }
+                }
+            });
+            _task.detach();
+        },
         _ => {}
     }
 }
 
+/// Menus of the menu bar, from left to right. Other menus come after, by name.
+const MENU_BAR_ORDER: [&str; 6] = ["File", "Edit", "Add", "Object", "Script", "Help"];
+
+/// Items of a menu generated from commands. Unavailable commands are greyed out.
+fn command_menu_ui(
+    ui: &mut egui::Ui,
+    menu: &Menu,
+    command_central_state: &CommandCentralState,
+    tree: &ObservableKVTree<ClaydashValue>,
+    commands: &mut Commands,
+) {
+    for item in menu.items.iter() {
+        match item {
+            MenuItem::Command { system_name, label } => {
+                let label = &undo_redo_menu_label(system_name, tree).unwrap_or(label.clone());
+                let unavailable_reason = command_central_state.unavailable_commands.get(system_name);
+                let response = match command_central_state.checked_commands.get(system_name) {
+                    Some(checked) => {
+                        let mut checked = *checked;
+                        ui.add_enabled(unavailable_reason.is_none(), egui::Checkbox::new(&mut checked, label))
+                    },
+                    None => {
+                        let shortcut = command_central_state.keymap.shortcut(system_name).unwrap_or("");
+                        ui.add_enabled(unavailable_reason.is_none(), egui::Button::new(label).shortcut_text(shortcut))
+                    },
+                };
+                if let Some(reason) = unavailable_reason {
+                    response.clone().on_disabled_hover_text(reason);
+                }
+                if response.clicked() {
+                    queue_command(commands, system_name, CommandArgs::new());
+                    ui.close_menu();
+                }
+            },
+            MenuItem::Submenu(submenu) => {
+                ui.menu_button(&submenu.label, |ui| {
+                    command_menu_ui(ui, submenu, command_central_state, tree, commands);
+                });
+            },
+            MenuItem::Separator => {
+                ui.separator();
+            },
+        }
+    }
+}
+
 fn claydash_ui(
     mut contexts: EguiContexts,
     asset_server: Res<AssetServer>,
     assets: Res<Assets<Image>>,
     mut data_resource: ResMut<ClaydashData>,
     claydash_ui_state: ResMut<CommandCentralUiState>,
-    command_central_state: ResMut<CommandCentralState>,
+    mut command_central_state: ResMut<CommandCentralState>,
     mut _windows: NonSend<WinitWindows>,
-    ui_messages: NonSendMut<UiMessagesTxRxResource>
+    mut commands: Commands,
+    pending_shortcut: Res<PendingShortcut>,
+    mut schema_errors: Local<Vec<TreeError>>,
 ) {
     let tree = &mut data_resource.as_mut().tree;
     let ctx = contexts.ctx_mut();
 
+    // Writes refused by the schema are bugs, show them until dismissed.
+    for error in tree.take_schema_errors() {
+        println!("{}", error);
+        schema_errors.push(error);
+    }
+
     use egui::menu;
 
     egui::TopBottomPanel::top("top_panel")
         .show(ctx, |ui| {
             menu::bar(ui, |ui| {
-                ui.menu_button("File", |ui| {
-                    if ui.button("Save").clicked() {
-                        let task = rfd::AsyncFileDialog::new()
-                            .add_filter("claydash workspace", &["claydash"])
-                            .save_file();
-
-                        let thread_pool = AsyncComputeTaskPool::get();
-                        let tx = ui_messages.tx.clone();
-                        let _task = thread_pool.spawn(async move {
-                            let file = task.await;
-                            _ = tx.send(UiMessage::SaveFileHandle(file.unwrap()));
-                        });
-                        _task.detach();
-                    }
-                    if ui.button("Open").clicked() {
-                        let task = rfd::AsyncFileDialog::new().pick_file();
-
-                        let thread_pool = AsyncComputeTaskPool::get();
-                        let tx = ui_messages.tx.clone();
-                        let _task = thread_pool.spawn(async move {
-                            let file = task.await;
-                            _ = tx.send(UiMessage::OpenFileHandle(file.unwrap()));
-                        });
-                        _task.detach();
-                    }
-                });
-                ui.menu_button("Edit", |ui| {
-                    if ui
-                        .add(
-                            egui::Button::new("Undo")
-                                .shortcut_text(UNDO_SHORTCUT),
-                        )
-                        .clicked() {
-                        tree.undo();
-                    }
+                // Menus are made of commands, so everything in menus can also be searched and scripted.
+                let mut menus = command_central_state.commands.menus();
+                menus.sort_by_key(|menu| MENU_BAR_ORDER.iter().position(|label| *label == menu.label).unwrap_or(MENU_BAR_ORDER.len()));
+                for menu in menus.iter() {
+                    ui.menu_button(&menu.label, |ui| {
+                        command_menu_ui(ui, menu, &command_central_state, tree, &mut commands);
+                    });
+                }
+            });
+        });
 
-                    if ui
-                        .add(
-                            egui::Button::new("Redo")
-                                .shortcut_text(REDO_SHORTCUT),
-                        )
-                        .clicked() {
-                        tree.redo();
+    egui::TopBottomPanel::bottom("status_bar")
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if let Some(error) = schema_errors.last() {
+                    ui.colored_label(Color32::LIGHT_RED, error.to_string());
+                    if ui.small_button("Dismiss").clicked() {
+                        schema_errors.clear();
                     }
-                });
+                    ui.separator();
+                }
+                if let Some(error) = command_central_state.registration_errors.last() {
+                    ui.colored_label(Color32::LIGHT_RED, format!("Could not register command: {}", error));
+                    if ui.small_button("Dismiss").clicked() {
+                        command_central_state.registration_errors.clear();
+                    }
+                    ui.separator();
+                }
+                if let Some(recording) = &command_central_state.recording {
+                    ui.colored_label(Color32::LIGHT_RED, format!("Recording macro {}", recording.name));
+                    ui.separator();
+                }
+                // Shortcut sequence waiting for its next chord, like "Ctrl+K"
+                if !pending_shortcut.chords.is_empty() {
+                    ui.label(format!("{} …", pending_shortcut.chords.join(" ")));
+                }
             });
         });
 
@@ -194,7 +430,8 @@ fn claydash_ui(
             }
         });
 
-    command_ui(ctx, claydash_ui_state, command_central_state, data_resource);
+    command_central_state.text_input_focused = ctx.wants_keyboard_input();
+    command_ui(ctx, claydash_ui_state, command_central_state, &mut commands);
 }
 
 const IMAGE_WIDTH: f32 = 66.0;
@@ -292,18 +529,13 @@ fn update_selection_color(
     tree: &mut ObservableKVTree<ClaydashValue>,
     color: Vec4,
 ) {
-    let mut objects: Vec<SDFObject> = match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(data) => data,
-        _ => { return; }
-    };
-
     let selected_object_uuids = tree.get_path("scene.selected_uuids").unwrap_vec_uuid_or(Vec::new());
 
-    for object in objects.iter_mut() {
-        if selected_object_uuids.contains(&object.uuid) {
+    for uuid in selected_object_uuids.iter() {
+        if let Some(object) = scene_object(tree, uuid) {
+            let mut object = object.clone();
             object.color = color;
+            set_scene_object(tree, object);
         }
     }
-
-    tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(objects));
 }
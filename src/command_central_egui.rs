@@ -11,30 +11,57 @@ use epaint::{
     Rounding
 };
 use crate::command_central_plugin::*;
-use crate::claydash_data::{ClaydashData, ClaydashValue};
-use observable_key_value_tree::ObservableKVTree;
+use crate::claydash_data::ClaydashValue;
+use std::collections::BTreeMap;
+
+/// Number of commands shown in search results.
+const SEARCH_RESULTS: usize = 5;
 
 #[derive(Resource)]
 pub struct CommandCentralUiState {
     pub command_search_str: String,
+    /// Result run by Enter. Arrow keys change it.
+    pub selected_result: usize,
+    /// Text typed in parameter fields of the selected result, by parameter name.
+    pub param_inputs: BTreeMap<String, String>,
+    /// Why the last command typed in the search could not run.
+    pub error: Option<String>,
 }
 
 impl Default for CommandCentralUiState {
     fn default() -> Self {
         Self {
             command_search_str: "".to_string(),
+            selected_result: 0,
+            param_inputs: BTreeMap::new(),
+            error: None,
+        }
+    }
+}
+
+impl CommandCentralUiState {
+    fn select(&mut self, result: usize) {
+        if self.selected_result != result {
+            self.selected_result = result;
+            self.param_inputs.clear();
+            self.error = None;
         }
     }
+
+    fn clear(&mut self) {
+        self.command_search_str = "".to_string();
+        self.selected_result = 0;
+        self.param_inputs.clear();
+        self.error = None;
+    }
 }
 
 pub fn command_ui(
     ctx: &egui::Context,
     claydash_ui_state: ResMut<CommandCentralUiState>,
     command_central_state: ResMut<CommandCentralState>,
-    mut data_resource: ResMut<ClaydashData>,
+    bevy_commands: &mut Commands,
 ) {
-    let tree = &mut data_resource.as_mut().tree;
-
     egui::SidePanel::right("right_panel")
         .frame(Frame {
             outer_margin: egui::style::Margin::symmetric(20.0, 0.0),
@@ -45,7 +72,7 @@ pub fn command_ui(
         .resizable(false)
         .show(ctx, |ui| {
             ui.set_width(320.0);
-            command_search(ui, ctx.clone(), claydash_ui_state, command_central_state, tree);
+            command_search(ui, ctx.clone(), claydash_ui_state, command_central_state, bevy_commands);
         });
 }
 
@@ -54,7 +81,7 @@ fn command_search(
     ctx: egui::Context,
     mut claydash_ui_state: ResMut<CommandCentralUiState>,
     command_central_state: ResMut<CommandCentralState>,
-    tree: &mut ObservableKVTree<ClaydashValue>,
+    bevy_commands: &mut Commands,
 ) {
     let rounding: Rounding = Rounding::same(5.0);
     let widget_offset = egui::vec2(10.0, 20.0);
@@ -85,7 +112,7 @@ fn command_search(
 
     let bg_color = Color32::from_rgba_unmultiplied(200, 200, 200, 10);
     ui.style_mut().visuals.extreme_bg_color = bg_color;
-    ui.put(
+    let search_response = ui.put(
         widget_rect,
         egui::TextEdit::singleline(&mut claydash_ui_state.command_search_str)
             .hint_text("Search Commands...")
@@ -93,35 +120,67 @@ fn command_search(
     ui.end_row();
     ui.add_space(10.0);
 
-    let command_search_str: &mut String = &mut claydash_ui_state.command_search_str;
-    if command_search_str.len() > 0 {
-        egui::Frame::none()
-            .fill(Color32::from_rgba_unmultiplied(200, 200, 200, 10))
-            .rounding(rounding)
-            .outer_margin(egui::style::Margin::symmetric(0.0, 10.0))
-            .inner_margin(egui::style::Margin::symmetric(10.0, 0.0))
-            .show(ui, |ui| {
-                ui.set_width(280.0);
-                command_results_ui(ui, claydash_ui_state, command_central_state, tree);
-            });
+    if search_response.changed() {
+        claydash_ui_state.select(0);
     }
+
+    if claydash_ui_state.command_search_str.is_empty() {
+        return;
+    }
+
+    let results = command_central_state.commands.rank(
+        &claydash_ui_state.command_search_str,
+        &command_central_state.usage,
+        SEARCH_RESULTS
+    );
+
+    // Arrow keys choose a result while typing the search.
+    if search_response.has_focus() && !results.is_empty() {
+        let (up, down) = ctx.input(|input| (
+            input.key_pressed(egui::Key::ArrowUp),
+            input.key_pressed(egui::Key::ArrowDown)
+        ));
+        let selected_result = claydash_ui_state.selected_result;
+        if up {
+            claydash_ui_state.select(selected_result.saturating_sub(1));
+        }
+        if down {
+            claydash_ui_state.select((selected_result + 1).min(results.len() - 1));
+        }
+    }
+    // Typing Enter in the search leaves it.
+    let search_submitted = search_response.lost_focus() && ctx.input(|input| input.key_pressed(egui::Key::Enter));
+
+    egui::Frame::none()
+        .fill(Color32::from_rgba_unmultiplied(200, 200, 200, 10))
+        .rounding(rounding)
+        .outer_margin(egui::style::Margin::symmetric(0.0, 10.0))
+        .inner_margin(egui::style::Margin::symmetric(10.0, 0.0))
+        .show(ui, |ui| {
+            ui.set_width(280.0);
+            command_results_ui(ui, &results, search_submitted, claydash_ui_state, command_central_state, bevy_commands);
+        });
 }
 
 fn command_results_ui(
     ui: &mut egui::Ui,
+    results: &[String],
+    search_submitted: bool,
     mut claydash_ui_state: ResMut<CommandCentralUiState>,
-    mut bevy_command_central: ResMut<CommandCentralState>,
-    tree: &mut ObservableKVTree<ClaydashValue>
+    bevy_command_central: ResMut<CommandCentralState>,
+    bevy_commands: &mut Commands
 ) {
     let rounding = Rounding::same(5.0);
-    let command_search_str: &mut String = &mut claydash_ui_state.command_search_str;
-    let commands = match command_search_str.len() {
-        0 => { return },
-        _ => { bevy_command_central.commands.search(command_search_str, 5) }
-    };
+    let mut run_selected = search_submitted;
 
-    for (system_name, command) in commands.iter() {
-        let bg_color = Color32::from_rgba_unmultiplied(217, 217, 217, 10);
+    for (index, system_name) in results.iter().enumerate() {
+        let command = &bevy_command_central.commands.commands[system_name];
+        let is_selected = index == claydash_ui_state.selected_result;
+        let unavailable_reason = bevy_command_central.unavailable_commands.get(system_name);
+        let bg_color = match is_selected {
+            true => Color32::from_rgba_unmultiplied(217, 217, 217, 30),
+            false => Color32::from_rgba_unmultiplied(217, 217, 217, 10),
+        };
 
         egui::Frame::none()
             .fill(bg_color)
@@ -130,7 +189,14 @@ fn command_results_ui(
             .outer_margin(egui::style::Margin::symmetric(0.0, 10.0))
             .show(ui, |ui| {
                 ui.set_width(280.0);
-                ui.heading(&command.title);
+                if unavailable_reason.is_some() {
+                    ui.visuals_mut().override_text_color = Some(Color32::from_gray(90));
+                }
+                // Clicking the title selects the command, to type its parameters.
+                let title = egui::Label::new(egui::RichText::new(&command.title).heading()).sense(egui::Sense::click());
+                if ui.add(title).clicked() {
+                    claydash_ui_state.select(index);
+                }
                 ui.label(system_name) ;
                 ui.separator();
                 ui.label(&command.docs);
@@ -142,36 +208,149 @@ fn command_results_ui(
                     ui.heading("Parameters:");
                 }
 
-                for (param_name, param) in command.parameters.iter() {
+                for param in command.parameters.iter() {
                     ui.with_layout(egui::Layout::left_to_right(egui::Align::LEFT), |ui| {
-                        ui.label(param_name);
+                        ui.label(format!("{} ({})", param.name, param.param_type));
                         ui.label(":");
                         ui.label(&param.docs);
                         ui.end_row();
                     });
+                    // Parameters of the selected command can be typed before running it.
+                    if is_selected {
+                        let input = claydash_ui_state.param_inputs.entry(param.name.clone()).or_default();
+                        let hint = match &param.default {
+                            Some(default) => format_param_value(default),
+                            None => "Optional".to_string(),
+                        };
+                        let response = ui.add(egui::TextEdit::singleline(input).hint_text(hint).desired_width(260.0));
+                        if response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                            run_selected = true;
+                        }
+                    }
                 }
 
-                if !command.shortcut.is_empty() {
+                if let Some(returns) = &command.returns {
+                    ui.add_space(10.0);
+                    ui.heading("Returns:");
+                    ui.label(format!("{}: {}", returns.return_type, returns.docs));
+                    ui.end_row();
+                }
+
+                if let Some(shortcut) = bevy_command_central.keymap.shortcut(system_name) {
                     ui.add_space(10.0);
                     ui.heading("Shortcut:");
-                    ui.label(&command.shortcut);
+                    ui.label(shortcut);
                     ui.end_row();
                 }
 
+                if is_selected {
+                    if let Some(error) = &claydash_ui_state.error {
+                        ui.add_space(10.0);
+                        ui.colored_label(Color32::LIGHT_RED, error);
+                    }
+                }
+
                 ui.set_height(30.0);
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::RIGHT), |ui| {
                     ui.add_space(10.0);
-                    if ui.small_button("Run").clicked() {
-                        claydash_ui_state.command_search_str = "".to_string();
-                        match command.parameters["callback"].value.clone().unwrap() {
-                            ClaydashValue::Fn(callback) => {
-                                callback(tree);
-                            },
-                            _ => {}
-                        };
+                    let run_button = ui.add_enabled(unavailable_reason.is_none(), egui::Button::new("Run").small());
+                    if run_button.clicked() {
+                        claydash_ui_state.select(index);
+                        run_selected = true;
+                    }
+                    if let Some(reason) = unavailable_reason {
+                        ui.label(egui::RichText::new(reason).weak());
                     }
                 });
                 ui.add_space(10.0);
             });
     }
+
+    if !run_selected {
+        return;
+    }
+    let system_name = match results.get(claydash_ui_state.selected_result) {
+        Some(system_name) => system_name,
+        None => { return; }
+    };
+    let command = &bevy_command_central.commands.commands[system_name];
+    if let Some(reason) = bevy_command_central.unavailable_commands.get(system_name) {
+        claydash_ui_state.error = Some(reason.clone());
+        return;
+    }
+    match parse_param_inputs(command, &claydash_ui_state.param_inputs) {
+        Ok(args) => {
+            claydash_ui_state.clear();
+            queue_command(bevy_commands, system_name, args);
+        },
+        Err(error) => {
+            claydash_ui_state.error = Some(error);
+        }
+    }
+}
+
+/// Arguments typed in parameter fields. Empty fields use the parameter's default.
+/// Required parameters left empty are an error, shown instead of running the command.
+fn parse_param_inputs(
+    command: &ClaydashCommandInfo,
+    param_inputs: &BTreeMap<String, String>
+) -> Result<ClaydashCommandArgs, String> {
+    let mut args = ClaydashCommandArgs::new();
+    for param in command.parameters.iter() {
+        let input = match param_inputs.get(&param.name).map(|input| input.trim()) {
+            Some(input) if !input.is_empty() => input,
+            _ => { continue; }
+        };
+        let value = parse_param_value(input, &param.param_type)
+            .map_err(|error| format!("{}: {}", param.name, error))?;
+        param.validate(&value).map_err(|error| error.to_string())?;
+        args.insert(param.name.clone(), value);
+    }
+    // Reports required parameters left empty.
+    command.resolve_args(&args).map_err(|error| error.to_string())?;
+    return Ok(args);
+}
+
+/// Writes a value the way `parse_param_value` reads it.
+fn format_param_value(value: &ClaydashValue) -> String {
+    return match value {
+        ClaydashValue::F32(value) => value.to_string(),
+        ClaydashValue::I32(value) => value.to_string(),
+        ClaydashValue::Bool(value) => value.to_string(),
+        ClaydashValue::String(value) => value.clone(),
+        ClaydashValue::Uuid(value) => value.to_string(),
+        ClaydashValue::Vec2(value) => format!("{}, {}", value.x, value.y),
+        ClaydashValue::Vec3(value) => format!("{}, {}, {}", value.x, value.y, value.z),
+        ClaydashValue::Vec4(value) => format!("{}, {}, {}, {}", value.x, value.y, value.z, value.w),
+        _ => value.kind().to_string(),
+    };
+}
+
+/// Reads a typed value. Vectors are numbers separated by commas or spaces: "0.0, 1.0, 0.0".
+fn parse_param_value(input: &str, param_type: &str) -> Result<ClaydashValue, String> {
+    let numbers = || -> Result<Vec<f32>, String> {
+        return input.split(|character: char| character == ',' || character.is_whitespace())
+            .filter(|number| !number.is_empty())
+            .map(|number| number.parse::<f32>().map_err(|_| format!("{} is not a number", number)))
+            .collect();
+    };
+    let vector = |size: usize| -> Result<Vec<f32>, String> {
+        let numbers = numbers()?;
+        if numbers.len() != size {
+            return Err(format!("expected {} numbers", size));
+        }
+        return Ok(numbers);
+    };
+
+    return match param_type {
+        "F32" => input.parse::<f32>().map(ClaydashValue::F32).map_err(|_| "expected a number".to_string()),
+        "I32" => input.parse::<i32>().map(ClaydashValue::I32).map_err(|_| "expected an integer".to_string()),
+        "Bool" => input.parse::<bool>().map(ClaydashValue::Bool).map_err(|_| "expected true or false".to_string()),
+        "String" => Ok(ClaydashValue::String(input.to_string())),
+        "Uuid" => uuid::Uuid::parse_str(input).map(ClaydashValue::Uuid).map_err(|error| error.to_string()),
+        "Vec2" => vector(2).map(|numbers| ClaydashValue::Vec2(Vec2::from_slice(&numbers))),
+        "Vec3" => vector(3).map(|numbers| ClaydashValue::Vec3(Vec3::from_slice(&numbers))),
+        "Vec4" => vector(4).map(|numbers| ClaydashValue::Vec4(Vec4::from_slice(&numbers))),
+        _ => Err(format!("{} values can't be typed", param_type)),
+    };
 }
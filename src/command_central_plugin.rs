@@ -1,17 +1,263 @@
-use bevy::prelude::*;
-use command_central::CommandMap;
+use std::collections::BTreeMap;
 
-use crate::claydash_data::ClaydashValue;
+use bevy::{prelude::*, ecs::system::SystemId};
+use command_central::{CommandMap, CommandInfo, CommandBuilder, CommandArgs, CommandError, CommandUsage, Keymap};
+
+use observable_key_value_tree::ObservableKVTree;
+
+use crate::claydash_data::{ClaydashValue, ClaydashData, EditorState};
+use crate::command_macros::{MacroRecording, record_step, cursor_position};
 
 pub struct BevyCommandCentralPlugin;
 
+pub type ClaydashCommandMap = CommandMap<ClaydashValue, World>;
+pub type ClaydashCommandInfo = CommandInfo<ClaydashValue, World>;
+pub type ClaydashCommandArgs = CommandArgs<ClaydashValue>;
+
 #[derive(Resource, Default)]
 pub struct CommandCentralState {
-    pub commands: CommandMap<ClaydashValue>,
+    /// Commands act on the Bevy world, so they can use any resource, spawn entities or run systems.
+    pub commands: ClaydashCommandMap,
+    /// Shortcuts of commands, including the user's changes. Built once commands are registered,
+    /// then defaults follow commands registered, overridden or unregistered later.
+    pub keymap: Keymap,
+    /// Commands that could not be registered, like when their system name is taken. Shown in the status bar.
+    pub registration_errors: Vec<CommandError>,
+    /// Commands run so far this session, to rank search results.
+    pub usage: CommandUsage,
+    /// Macro being recorded, if any.
+    pub recording: Option<MacroRecording>,
+    /// Macros being played, innermost last. A macro playing one of them would replay forever.
+    pub playing_macros: Vec<String>,
+    /// Commands that can't run right now, with the reason. Updated every frame, for UI that can't read the world.
+    pub unavailable_commands: BTreeMap<String, String>,
+    /// Whether what toggle commands toggle is on, updated with `unavailable_commands`.
+    pub checked_commands: BTreeMap<String, bool>,
+    /// A text field has keyboard focus, so keys are text, not shortcuts.
+    pub text_input_focused: bool,
+    /// Number of commands currently running. Above 1, commands are run by other commands.
+    running_commands: usize,
 }
 
 impl Plugin for BevyCommandCentralPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<CommandCentralState>();
+        app.init_resource::<CommandCentralState>()
+            .add_systems(Update, update_command_availability);
+    }
+}
+
+/// Callbacks for commands that only need the data tree, or that run a Bevy system.
+pub trait ClaydashCommandBuilder {
+    /// Registers the command. Failures are bugs, kept in `registration_errors` so they are noticed.
+    fn register(&mut self, state: &mut CommandCentralState);
+
+    fn tree_callback(&mut self, callback: fn(&mut ObservableKVTree<ClaydashValue>)) -> &mut Self;
+
+    fn tree_callback_with_args(
+        &mut self,
+        callback: fn(&mut ObservableKVTree<ClaydashValue>, &ClaydashCommandArgs) -> Option<ClaydashValue>
+    ) -> &mut Self;
+
+    /// Runs a one-shot system, registered with `World::register_system`.
+    fn system_callback(&mut self, system_id: SystemId) -> &mut Self;
+
+    /// The command is only available when `predicate` holds for the data tree.
+    fn when_tree(&mut self, reason: &str, predicate: fn(&ObservableKVTree<ClaydashValue>) -> bool) -> &mut Self;
+
+    /// Menus show a checkbox, checked when `predicate` holds for the data tree.
+    fn checked_tree(&mut self, predicate: fn(&ObservableKVTree<ClaydashValue>) -> bool) -> &mut Self;
+}
+
+impl ClaydashCommandBuilder for CommandBuilder<ClaydashValue, World> {
+    fn register(&mut self, state: &mut CommandCentralState) {
+        if let Err(error) = self.write(&mut state.commands) {
+            println!("Could not register command: {}", error);
+            state.registration_errors.push(error);
+        }
+    }
+
+    fn tree_callback(&mut self, callback: fn(&mut ObservableKVTree<ClaydashValue>)) -> &mut Self {
+        return self.closure(move |world, _args| {
+            callback(&mut world.resource_mut::<ClaydashData>().tree);
+            return None;
+        });
     }
+
+    fn tree_callback_with_args(
+        &mut self,
+        callback: fn(&mut ObservableKVTree<ClaydashValue>, &ClaydashCommandArgs) -> Option<ClaydashValue>
+    ) -> &mut Self {
+        return self.closure(move |world, args| {
+            return callback(&mut world.resource_mut::<ClaydashData>().tree, args);
+        });
+    }
+
+    fn system_callback(&mut self, system_id: SystemId) -> &mut Self {
+        return self.closure(move |world, _args| {
+            if let Err(error) = world.run_system(system_id) {
+                println!("Could not run command system: {:?}", error);
+            }
+            return None;
+        });
+    }
+
+    fn when_tree(&mut self, reason: &str, predicate: fn(&ObservableKVTree<ClaydashValue>) -> bool) -> &mut Self {
+        return self.when(reason, move |world| predicate(&world.resource::<ClaydashData>().tree));
+    }
+
+    fn checked_tree(&mut self, predicate: fn(&ObservableKVTree<ClaydashValue>) -> bool) -> &mut Self {
+        return self.checked(move |world| predicate(&world.resource::<ClaydashData>().tree));
+    }
+}
+
+fn update_command_availability(world: &mut World) {
+    let state = world.resource::<CommandCentralState>();
+    let unavailable_commands: BTreeMap<String, String> = state.commands.commands.iter()
+        .filter_map(|(system_name, command)| {
+            let reason = command.unavailable_reason(world)?;
+            return Some((system_name.clone(), reason.to_string()));
+        })
+        .collect();
+    let checked_commands: BTreeMap<String, bool> = state.commands.commands.iter()
+        .filter_map(|(system_name, command)| Some((system_name.clone(), command.is_checked(world)?)))
+        .collect();
+
+    let mut state = world.resource_mut::<CommandCentralState>();
+    state.unavailable_commands = unavailable_commands;
+    state.checked_commands = checked_commands;
+    let state = state.as_mut();
+    state.keymap.update_defaults(&state.commands);
+}
+
+/// Whether the tree has changes since the last snapshot, other than editor state.
+fn has_document_changes(tree: &ObservableKVTree<ClaydashValue>) -> bool {
+    return tree.pending_change_paths().any(|path| !path.starts_with("editor."));
+}
+
+/// Label of an undo step for changes made outside commands, like selecting with the mouse.
+pub fn pending_changes_label(tree: &ObservableKVTree<ClaydashValue>) -> &'static str {
+    let paths: Vec<&String> = tree.pending_change_paths().filter(|path| !path.starts_with("editor.")).collect();
+    if paths.is_empty() {
+        return "Edit";
+    }
+    if paths.iter().all(|path| path.starts_with("scene.selected_uuids")) {
+        return "Select";
+    }
+    if paths.iter().all(|path| path.starts_with("scene.objects")) {
+        return "Edit objects";
+    }
+    return "Edit";
+}
+
+/// Title of the command that started the current interaction, like "Grab".
+/// The interaction's undo step is made when it ends, and is labeled with it.
+pub const INTERACTION_LABEL_PATH: &str = "editor.interaction_label";
+
+/// Grabbing, scaling, rotating or dragging a control point.
+fn is_interacting(tree: &ObservableKVTree<ClaydashValue>) -> bool {
+    return match tree.get_path("editor.state").unwrap_editor_state_or(EditorState::Start) {
+        EditorState::Start => false,
+        _ => true,
+    };
+}
+
+/// Makes an undo step for changes made since the last step, so they are not part of the next command's step.
+pub fn make_pending_changes_undo_step(world: &mut World) {
+    let tree = &mut world.resource_mut::<ClaydashData>().tree;
+    let label = pending_changes_label(tree);
+    make_tree_undo_step(tree, label);
+}
+
+/// Makes an undo step, labeled with the command title, for the changes of a command.
+/// Commands starting an interaction, like grab, don't: the interaction makes its own step when it ends.
+pub fn make_command_undo_step(world: &mut World, title: &str) {
+    make_tree_undo_step(&mut world.resource_mut::<ClaydashData>().tree, title);
+}
+
+/// Makes an undo step labeled `label` if the document changed and no interaction is in progress.
+/// Steps ending an interaction are labeled like the command that started it.
+/// For systems that only have the tree, like mouse handlers.
+pub fn make_tree_undo_step(tree: &mut ObservableKVTree<ClaydashValue>, label: &str) {
+    if is_interacting(tree) {
+        return;
+    }
+    let interaction_label = match tree.get_path(INTERACTION_LABEL_PATH) {
+        ClaydashValue::String(interaction_label) => Some(interaction_label),
+        _ => None,
+    };
+    if has_document_changes(tree) {
+        tree.make_labeled_undo_redo_snapshot(interaction_label.as_deref().unwrap_or(label));
+    }
+    if interaction_label.is_some() {
+        // The label is not part of the undo history, so undo doesn't bring it back.
+        tree.set_path_untracked(INTERACTION_LABEL_PATH, ClaydashValue::None);
+    }
+}
+
+/// Runs a command with access to the whole world.
+/// The command is copied out of `CommandCentralState` first, so it may run other commands.
+/// Commands that are not run by other commands are added to the macro being recorded,
+/// and count as used in search.
+/// They are also an undo step if they change the document. Commands run by other commands
+/// (like by a macro or a script) are part of the step of the command that ran them.
+pub fn run_command(
+    world: &mut World,
+    system_name: &str,
+    args: &ClaydashCommandArgs
+) -> Result<Option<ClaydashValue>, CommandError> {
+    let mut state = world.resource_mut::<CommandCentralState>();
+    let command = match state.commands.commands.get(system_name) {
+        Some(command) => command.clone(),
+        None => { return Err(CommandError::NotFound(system_name.to_string())); }
+    };
+    let is_top_level = state.running_commands == 0;
+    // Commands starting or stopping a recording are not part of it.
+    let was_recording = state.recording.is_some();
+    state.running_commands += 1;
+
+    // Read before the command runs, since a step replays from the cursor position it started at.
+    let cursor_position = match was_recording && is_top_level {
+        true => cursor_position(world),
+        false => None,
+    };
+
+    let makes_undo_step = is_top_level && command.mutates_document;
+    if makes_undo_step {
+        // Earlier changes are not part of this command's step.
+        make_pending_changes_undo_step(world);
+    }
+
+    let was_interacting = is_interacting(&world.resource::<ClaydashData>().tree);
+    let result = command.run(system_name, world, args);
+
+    if makes_undo_step {
+        let tree = &mut world.resource_mut::<ClaydashData>().tree;
+        if !was_interacting && is_interacting(tree) {
+            tree.set_path_untracked(INTERACTION_LABEL_PATH, ClaydashValue::String(command.title.clone()));
+        }
+        make_command_undo_step(world, &command.title);
+    }
+
+    let mut state = world.resource_mut::<CommandCentralState>();
+    state.running_commands -= 1;
+    let is_recording = state.recording.is_some();
+
+    if result.is_ok() && is_top_level {
+        state.usage.record(system_name);
+        if was_recording && is_recording {
+            record_step(world, system_name, args, cursor_position);
+        }
+    }
+
+    return result;
+}
+
+/// Runs a command once the current system is done, for systems without world access (like egui menus).
+pub fn queue_command(commands: &mut Commands, system_name: &str, args: ClaydashCommandArgs) {
+    let system_name = system_name.to_string();
+    commands.add(move |world: &mut World| {
+        if let Err(error) = run_command(world, &system_name, &args) {
+            println!("{}", error);
+        }
+    });
 }
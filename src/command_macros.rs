@@ -0,0 +1,197 @@
+//! Command macros: record command invocations, then replay them.
+//!
+//! While recording, every command run from shortcuts, the command search, menus or scripts is added
+//! to the macro with its arguments and the cursor position it started from.
+//! Grab, scale and rotate follow the cursor between commands, so before replaying a step,
+//! a transformation in progress is moved to the step's cursor position, as it was when the step
+//! ran. For example, "grab" then "finish" replays the move that was made before finishing.
+//! Commands run by other commands (for example, by a script or a macro) are not recorded
+//! separately, since replaying their parent runs them again.
+//!
+//! Macros are stored at `macros.<name>` in the tree and saved with the document.
+//! They are not part of undo history, but replaying a macro is a single undo step.
+
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+use command_central::{CommandBuilder, CommandParam};
+
+use crate::claydash_data::{ClaydashValue, ClaydashData};
+use crate::command_central_plugin::{CommandCentralState, ClaydashCommandArgs, ClaydashCommandBuilder, run_command};
+use crate::interactions::apply_transformations;
+
+pub const MACROS_PATH: &str = "macros";
+const DEFAULT_MACRO_NAME: &str = "macro";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MacroStep {
+    pub system_name: String,
+    pub args: ClaydashCommandArgs,
+    /// Cursor position when the command was run, read before running it.
+    #[serde(alias = "initial_mouse_position")]
+    pub cursor_position: Option<Vec2>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CommandMacro {
+    pub steps: Vec<MacroStep>,
+}
+
+pub struct MacroRecording {
+    pub name: String,
+    pub command_macro: CommandMacro,
+}
+
+pub struct CommandMacrosPlugin;
+
+impl Plugin for CommandMacrosPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, register_macro_commands);
+    }
+}
+
+pub fn macro_path(name: &str) -> String {
+    // Dots would create nested paths
+    return format!("{}.{}", MACROS_PATH, name.replace(".", "-"));
+}
+
+fn register_macro_commands(mut bevy_command_central: ResMut<CommandCentralState>) {
+    let state = bevy_command_central.as_mut();
+
+    CommandBuilder::new()
+        .title("Start recording macro")
+        .system_name("start-macro-recording")
+        .docs("Record the next commands into a macro, until recording is stopped.")
+        .category("Script")
+        .mutates_document(false)
+        .menu("Script/Record macro", 100)
+        .when("Already recording a macro", |world: &World| world.resource::<CommandCentralState>().recording.is_none())
+        .param(CommandParam::new("name", "String", "Name of the macro").default(ClaydashValue::String(DEFAULT_MACRO_NAME.to_string())))
+        .callback_with_args(start_macro_recording)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Stop recording macro")
+        .system_name("stop-macro-recording")
+        .docs("Stop recording and save the macro with the document.")
+        .category("Script")
+        .mutates_document(false)
+        .menu("Script/Stop recording macro", 101)
+        .when("No macro is being recorded", |world: &World| world.resource::<CommandCentralState>().recording.is_some())
+        .callback(stop_macro_recording)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Play macro")
+        .system_name("play-macro")
+        .docs("Replay the commands of a recorded macro. Undo reverts the whole macro at once.")
+        .category("Script")
+        .menu("Script/Play macro", 102)
+        .param(CommandParam::new("name", "String", "Name of the macro").default(ClaydashValue::String(DEFAULT_MACRO_NAME.to_string())))
+        .callback_with_args(play_macro)
+        .register(state);
+}
+
+fn start_macro_recording(world: &mut World, args: &ClaydashCommandArgs) -> Option<ClaydashValue> {
+    let name = match &args["name"] {
+        ClaydashValue::String(name) => name.clone(),
+        _ => { return None; }
+    };
+    println!("Recording macro {}", name);
+    world.resource_mut::<CommandCentralState>().recording = Some(MacroRecording {
+        name,
+        command_macro: CommandMacro::default(),
+    });
+    return None;
+}
+
+fn stop_macro_recording(world: &mut World) {
+    let recording = match world.resource_mut::<CommandCentralState>().recording.take() {
+        Some(recording) => recording,
+        None => { return; }
+    };
+    println!("Recorded macro {} ({} commands)", recording.name, recording.command_macro.steps.len());
+
+    // Recording a macro is not an action to undo
+    let tree = &mut world.resource_mut::<ClaydashData>().tree;
+    tree.set_path_untracked(&macro_path(&recording.name), ClaydashValue::Macro(recording.command_macro));
+}
+
+fn play_macro(world: &mut World, args: &ClaydashCommandArgs) -> Option<ClaydashValue> {
+    let name = match &args["name"] {
+        ClaydashValue::String(name) => name.clone(),
+        _ => { return None; }
+    };
+    let command_macro = match world.resource::<ClaydashData>().tree.get_path(&macro_path(&name)) {
+        ClaydashValue::Macro(command_macro) => command_macro,
+        _ => {
+            println!("No macro named {}", name);
+            return None;
+        }
+    };
+
+    // Macros playing each other, directly or not, would replay forever.
+    if world.resource::<CommandCentralState>().playing_macros.contains(&name) {
+        println!("Macro {} is already playing", name);
+        return None;
+    }
+    world.resource_mut::<CommandCentralState>().playing_macros.push(name.clone());
+
+    for step in command_macro.steps.iter() {
+        if let Some(position) = step.cursor_position {
+            move_cursor(world, position);
+        }
+        if let Err(error) = run_command(world, &step.system_name, &step.args) {
+            println!("Stopped macro {}: {}", name, error);
+            break;
+        }
+    }
+
+    world.resource_mut::<CommandCentralState>().playing_macros.pop();
+
+    // Commands run by the macro don't make undo steps: the whole macro is one step.
+    return None;
+}
+
+/// Replays the cursor moving to a position: a transformation in progress follows it,
+/// and commands using `editor.initial_mouse_position` start from it.
+fn move_cursor(world: &mut World, position: Vec2) {
+    let camera = world.query::<(&Camera, &GlobalTransform)>()
+        .iter(world)
+        .next()
+        .map(|(camera, camera_global_transform)| (camera.clone(), *camera_global_transform));
+
+    let tree = &mut world.resource_mut::<ClaydashData>().tree;
+    tree.set_path("editor.initial_mouse_position", ClaydashValue::Vec2(position));
+    if let Some((camera, camera_global_transform)) = camera {
+        apply_transformations(tree, &camera, &camera_global_transform, position);
+    }
+}
+
+/// Position of the cursor in the window, if it is over the window.
+pub fn cursor_position(world: &mut World) -> Option<Vec2> {
+    return world.query::<&Window>()
+        .iter(world)
+        .next()
+        .and_then(|window| window.cursor_position());
+}
+
+/// Adds a command that just ran to the macro being recorded, with the cursor position
+/// it started from.
+pub fn record_step(world: &mut World, system_name: &str, args: &ClaydashCommandArgs, cursor_position: Option<Vec2>) {
+    if let Some(recording) = world.resource_mut::<CommandCentralState>().recording.as_mut() {
+        // The macro would replay itself forever
+        let played_macro = match args.get("name") {
+            Some(ClaydashValue::String(name)) => name.as_str(),
+            _ => DEFAULT_MACRO_NAME,
+        };
+        if system_name == "play-macro" && played_macro == recording.name {
+            return;
+        }
+
+        recording.command_macro.steps.push(MacroStep {
+            system_name: system_name.to_string(),
+            args: args.clone(),
+            cursor_position,
+        });
+    }
+}
@@ -3,19 +3,21 @@ use bevy::{
     input::{keyboard::KeyCode, Input},
 };
 use bevy_mod_picking::{backend::HitData, prelude::*};
-use crate::claydash_data::get_active_object_index;
+use crate::claydash_data::{get_active_object_index, scene_objects, scene_object, set_scene_object};
 use crate::bevy_sdf_object::{SDFObject, control_points_hit, ControlPoint, SDFObjectParams, ControlPointType};
 use crate::claydash_data::{ClaydashData, ClaydashValue, EditorState::*};
-use observable_key_value_tree::ObservableKVTree;
+use crate::command_central_plugin::{queue_command, pending_changes_label, make_tree_undo_step};
+use command_central::CommandArgs;
+use observable_key_value_tree::{ObservableKVTree, TreeBinding, UpdateCursor};
 mod interaction_commands_and_shortcuts;
-use lazy_static::lazy_static;
-use std::sync::{Arc, Mutex};
+pub use interaction_commands_and_shortcuts::PendingShortcut;
 
 pub struct ClaydashInteractionPlugin;
 
 impl Plugin for ClaydashInteractionPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ClaydashData>()
+            .init_resource::<PendingShortcut>()
             .add_systems(Startup, (
                 interaction_commands_and_shortcuts::register_interaction_commands,
             ))
@@ -26,45 +28,57 @@ impl Plugin for ClaydashInteractionPlugin {
     }
 }
 
+pub const EDITOR_PATH: &str = "editor";
+
+/// Editor state used while grabbing, scaling or rotating the selection.
+/// Stored at EDITOR_PATH.
+#[derive(TreeBinding)]
+#[tree_value(ClaydashValue)]
+pub struct TransformationState {
+    pub initial_selection_transform: Transform,
+    pub initial_radius: f32,
+    pub constrain_x: bool,
+    pub constrain_y: bool,
+    pub constrain_z: bool,
+}
+
+/// Transform of a selected object when the transformation started.
+/// Stored at `initial_object_transform_path(uuid)`.
+#[derive(TreeBinding)]
+#[tree_value(ClaydashValue)]
+pub struct InitialObjectTransform {
+    pub transform: Transform,
+    pub relative_to_selection: Transform,
+}
+
+pub fn initial_object_transform_path(uuid: &uuid::Uuid) -> String {
+    return format!("editor.initial_transforms.{}", uuid);
+}
+
 #[derive(Component)]
 struct ControlPointText {
     position: Vec3,
 }
 
-lazy_static! {
-    static ref LAST_SYNCED_TEXT_VERSION: Arc<Mutex<i32>> = Arc::new(Mutex::new(-1));
-}
-
 fn update_control_points_text(
-    mut data_resource: ResMut<ClaydashData>,
+    data: Res<ClaydashData>,
+    mut cursor: Local<UpdateCursor>,
     mut commands: Commands,
     query: Query<Entity, With<ControlPointText>>,
     asset_server: Res<AssetServer>,
 ) {
-    let data = data_resource.as_mut();
-
-    let last_updated_version = LAST_SYNCED_TEXT_VERSION.try_lock();
-
-    let version = data.tree.path_version("scene");
-
-    let mut last_updated_version = match last_updated_version {
-        Ok(version) => { version  }
-        _ => { return }
-    };
-
-    if version > *last_updated_version  {
+    if cursor.take_change(&data.tree, "scene") {
         // Remove previous text
         for text in &query {
             commands.entity(text).despawn();
         }
 
         let active_object_index = get_active_object_index(&data.tree);
-        let objects = data.tree.get_path("scene.sdf_objects");
+        let objects = scene_objects(&data.tree);
 
-        match active_object_index  {
-            Some(index) => {
+        match active_object_index.and_then(|index| objects.get(index))  {
+            Some(object) => {
                 // Show control points
-                let object: &SDFObject = &objects.unwrap_vec_sdf_object()[index];
 
                 for point in object.get_control_points().iter() {
                     let label = &point.label;
@@ -98,8 +112,6 @@ fn update_control_points_text(
             },
             _ => {}
         }
-
-        *last_updated_version = version;
     }
 }
 
@@ -154,15 +166,10 @@ fn update_control_points(
     let uuid = tree.get_path("editor.current_control_point_object_uuid").unwrap_uuid_or_default();
     let control_point_type = tree.get_path("editor.current_control_point_type").unwrap_control_point_type_or_default();
 
-    let mut objects: Vec<SDFObject> = match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(data) => data,
-        _ => { return; }
-    };
-
-    let active_object = objects.iter_mut().find(|obj| { obj.uuid == uuid });
+    let active_object = scene_object(tree, &uuid).cloned();
 
     match active_object {
-        Some(active_object) => {
+        Some(mut active_object) => {
             let control_points = active_object.get_control_points();
 
             let mut control_point: Option<ControlPoint> = None;
@@ -211,7 +218,7 @@ fn update_control_points(
                 },
             };
 
-            tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(objects));
+            set_scene_object(tree, active_object);
         }
         _ => {
             return;
@@ -225,19 +232,29 @@ fn update_transformations(
     camera_global_transforms: Query<&mut GlobalTransform, With<Camera>>,
     camera: Query<&Camera>,
 ) {
-    // Based on camera rotation, find what direction mouse moves corresponds to in
-    // 3D space.
     let camera = camera.single();
     let camera_global_transform = camera_global_transforms.single();
 
-    let tree = &mut data_resource.as_mut().tree;
-
-    let state = tree.get_path("editor.state").unwrap_editor_state_or(Start);
-
     // Find cursor info
     let window = windows.single();
     let cursor_position = window.cursor_position().unwrap_or(Vec2::ZERO);
 
+    apply_transformations(&mut data_resource.as_mut().tree, camera, camera_global_transform, cursor_position);
+}
+
+/// Moves, scales or rotates the selection (or moves a control point) to follow the cursor,
+/// depending on `editor.state`. Does nothing when no transformation is in progress.
+/// Macros also use it, to replay a transformation at its recorded cursor position.
+pub fn apply_transformations(
+    tree: &mut ObservableKVTree<ClaydashValue>,
+    camera: &Camera,
+    camera_global_transform: &GlobalTransform,
+    cursor_position: Vec2,
+) {
+    // Based on camera rotation, find what direction mouse moves corresponds to in
+    // 3D space.
+    let state = tree.get_path("editor.state").unwrap_editor_state_or(Start);
+
     // Return early if not editing
     match state {
         Start => { return; },
@@ -248,28 +265,18 @@ fn update_transformations(
         _ => {}
     }
 
-    let mut objects: Vec<SDFObject> = match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(data) => data,
-        _ => { return; }
-    };
-
     let selected_object_uuids = match tree.get_path("scene.selected_uuids") {
         ClaydashValue::VecUuid(uuids) => uuids,
         _ => { return default(); }
     };
+    // Only selected objects are written, so other objects are not stored again in undo history.
+    let mut objects: Vec<SDFObject> = selected_object_uuids.iter()
+        .filter_map(|uuid| scene_object(tree, uuid).cloned())
+        .collect();
 
-    let constrain_x = match tree.get_path("editor.constrain_x") {
-        ClaydashValue::Bool(value) => value,
-        _ => false
-    };
-    let constrain_y = match tree.get_path("editor.constrain_y") {
-        ClaydashValue::Bool(value) => value,
-        _ => false
-    };
-    let constrain_z = match tree.get_path("editor.constrain_z") {
-        ClaydashValue::Bool(value) => value,
-        _ => false
-    };
+    let constrain_x = TransformationState::get_constrain_x(tree, EDITOR_PATH).unwrap_or(false);
+    let constrain_y = TransformationState::get_constrain_y(tree, EDITOR_PATH).unwrap_or(false);
+    let constrain_z = TransformationState::get_constrain_z(tree, EDITOR_PATH).unwrap_or(false);
 
     let has_constraints = constrain_x || constrain_y || constrain_z;
     let constraints = if has_constraints { Vec3::new(
@@ -278,8 +285,8 @@ fn update_transformations(
         if constrain_z { 1.0 } else { 0.0 },
     )} else { Vec3::ONE };
 
-    let initial_selection_transform = tree.get_path("editor.initial_selection_transform")
-        .unwrap_transform_or(Transform::IDENTITY);
+    let initial_selection_transform = TransformationState::get_initial_selection_transform(tree, EDITOR_PATH)
+        .unwrap_or(Transform::IDENTITY);
 
     let selection_translation: Vec3 = match camera.viewport_to_world(camera_global_transform, cursor_position) {
          Some(ray) => {
@@ -292,48 +299,44 @@ fn update_transformations(
     match state {
         Grabbing => {
             for object in objects.iter_mut() {
-                if selected_object_uuids.contains(&object.uuid) {
-                    let initial_transform = tree
-                        .get_path(&format!("editor.initial_transform_relative_to_selection.{}", object.uuid))
-                        .unwrap_transform_or(Transform::IDENTITY);
+                let initial_transform = InitialObjectTransform::get_relative_to_selection(
+                    tree,
+                    &initial_object_transform_path(&object.uuid)
+                ).unwrap_or(Transform::IDENTITY);
 
-                    object.transform.translation = initial_transform.translation + selection_translation * constraints;
-                }
+                object.transform.translation = initial_transform.translation + selection_translation * constraints;
             }
-            tree.set_path_without_notifying("scene.sdf_objects", ClaydashValue::VecSDFObject(objects));
+            set_selected_objects(tree, objects);
         },
         Scaling => {
             for object in objects.iter_mut() {
-                if selected_object_uuids.contains(&object.uuid) {
-                    let cursor_position_near_object = get_cursor_position_at_selection_dist(
-                        camera,
-                        camera_global_transform,
-                        cursor_position,
-                        selection_translation
-                    ).unwrap_or(Vec3::ZERO);
-
-                    let initial_radius = tree.get_path("editor.initial_radius").unwrap_f32();
-                    let current_radius = (cursor_position_near_object - initial_selection_transform.translation).length();
-                    let scale = current_radius / initial_radius - 1.0;
-
-                    let initial_transform = tree.get_path(&format!("editor.initial_transform.{}", object.uuid))
-                        .unwrap_transform_or(Transform::IDENTITY);
-                    let initial_transform_relative_to_selection = tree
-                        .get_path(&format!("editor.initial_transform_relative_to_selection.{}", object.uuid))
-                        .unwrap_transform_or(Transform::IDENTITY);
-
-                    object.transform = initial_transform;
-                    object.transform.scale += scale * constraints;
-                    object.transform.translation += scale * constraints * initial_transform_relative_to_selection.translation;
-                }
+                let cursor_position_near_object = get_cursor_position_at_selection_dist(
+                    camera,
+                    camera_global_transform,
+                    cursor_position,
+                    selection_translation
+                ).unwrap_or(Vec3::ZERO);
+
+                let initial_radius = TransformationState::get_initial_radius(tree, EDITOR_PATH).unwrap_or(1.0);
+                let current_radius = (cursor_position_near_object - initial_selection_transform.translation).length();
+                let scale = current_radius / initial_radius - 1.0;
+
+                let initial_transform = InitialObjectTransform::read_from(
+                    tree,
+                    &initial_object_transform_path(&object.uuid)
+                ).unwrap_or(InitialObjectTransform {
+                    transform: Transform::IDENTITY,
+                    relative_to_selection: Transform::IDENTITY,
+                });
+
+                object.transform = initial_transform.transform;
+                object.transform.scale += scale * constraints;
+                object.transform.translation += scale * constraints * initial_transform.relative_to_selection.translation;
             }
-            tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(objects));
+            set_selected_objects(tree, objects);
         },
         Rotating => {
             for object in objects.iter_mut() {
-                if !selected_object_uuids.contains(&object.uuid) {
-                    continue;
-                }
                 match get_object_angle_relative_to_camera_ray(
                     camera,
                     camera_global_transform,
@@ -341,8 +344,10 @@ fn update_transformations(
                     &initial_selection_transform,
                 ) {
                     Some((axis, angle)) => {
-                        let initial_transform = tree.get_path(&format!("editor.initial_transform.{}", object.uuid))
-                            .unwrap_transform_or(Transform::IDENTITY);
+                        let initial_transform = InitialObjectTransform::get_transform(
+                            tree,
+                            &initial_object_transform_path(&object.uuid)
+                        ).unwrap_or(Transform::IDENTITY);
 
                         let selection_center = initial_selection_transform.translation;
 
@@ -355,12 +360,18 @@ fn update_transformations(
                     _ => {}
                 };
             }
-            tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(objects));
+            set_selected_objects(tree, objects);
         },
         _ => {}
     };
 }
 
+fn set_selected_objects(tree: &mut ObservableKVTree<ClaydashValue>, objects: Vec<SDFObject>) {
+    for object in objects {
+        set_scene_object(tree, object);
+    }
+}
+
 fn get_object_angle_relative_to_camera_ray(
     camera: &Camera,
     camera_global_transform: &GlobalTransform,
@@ -403,23 +414,32 @@ pub fn on_mouse_down(
     keys: Res<Input<KeyCode>>,
     mut data_resource: ResMut<ClaydashData>,
     camera_transforms: Query<&mut Transform, With<Camera>>,
+    mut commands: Commands,
 ) {
     let tree = &mut data_resource.as_mut().tree;
     let state = tree.get_path("editor.state").unwrap_editor_state_or(Start);
 
     match state {
         Start => { },
+        Grabbing | Scaling | Rotating => {
+            // Clicking finishes the transformation like the finish command,
+            // which is also recorded in macros.
+            queue_command(&mut commands, "finish", CommandArgs::new());
+            return;
+        },
         _ => {
             // Exit grab/scale on click
             tree.set_path("editor.state", ClaydashValue::EditorState(Start));
-            tree.make_undo_redo_snapshot();
+            let label = pending_changes_label(tree);
+            make_tree_undo_step(tree, label);
             return;
         }
     }
 
     let tree = &mut data_resource.as_mut().tree;
-    match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(objects) => {
+    let objects: Vec<SDFObject> = scene_objects(tree).into_iter().cloned().collect();
+    match objects.is_empty() {
+        false => {
             let camera_transform: &Transform = camera_transforms.single();
             let camera_position = camera_transform.translation;
 
@@ -520,5 +540,6 @@ pub fn on_mouse_up(
 ) {
     let tree = &mut data_resource.as_mut().tree;
     tree.set_path("editor.state", ClaydashValue::EditorState(Start));
-    tree.make_undo_redo_snapshot();
+    let label = pending_changes_label(tree);
+    make_tree_undo_step(tree, label);
 }
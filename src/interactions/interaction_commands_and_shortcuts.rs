@@ -2,156 +2,211 @@ use bevy::{
     prelude::*,
     input::keyboard::KeyCode, ecs::system::SystemState
 };
-use crate::claydash_data::{ClaydashValue, ClaydashData};
-use crate::command_central_plugin::CommandCentralState;
+use crate::claydash_data::{
+    ClaydashValue, ClaydashData, get_selection_center,
+    scene_objects, scene_object, set_scene_object, remove_scene_object,
+};
+use crate::command_central_plugin::{CommandCentralState, ClaydashCommandBuilder, run_command};
 use observable_key_value_tree::{
     ObservableKVTree,
+    TreeBinding,
+};
+use super::{
+    EDITOR_PATH,
+    TransformationState,
+    InitialObjectTransform,
+    initial_object_transform_path,
 };
-use crate::bevy_sdf_object::SDFObject;
-use command_central::CommandBuilder;
+use crate::bevy_sdf_object::{SDFObject, SDFObjectParams, SphereParams};
+use command_central::{CommandBuilder, CommandParam, CommandArgs, CommandError, ShortcutMatch};
 use crate::claydash_data::EditorState::*;
 use sdf_consts::TYPE_BOX;
 
 pub fn register_interaction_commands(mut bevy_command_central: ResMut<CommandCentralState>) {
-    let commands = &mut bevy_command_central.commands;
+    let state = bevy_command_central.as_mut();
     CommandBuilder::new()
         .title("Grab")
         .system_name("grab")
         .docs("Start moving selection.")
         .shortcut("G")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(start_grab)))
-        .write(commands);
+        .category("Object")
+        .menu("Object/Grab", 0)
+        .when_tree("Nothing is selected", has_selection)
+        .tree_callback(start_grab)
+        .register(state);
 
     CommandBuilder::new()
         .title("Constrain editing to X axis")
         .system_name("constrain_x")
         .docs("Add a X constraint to current editing mode.")
         .shortcut("X")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(constrain_x)))
-        .write(commands);
+        .category("Transform")
+        .when_tree("No transformation in progress", is_transforming)
+        .tree_callback(constrain_x)
+        .register(state);
 
     CommandBuilder::new()
         .title("Constrain editing to Y axis")
         .system_name("constrain_y")
         .docs("Add a Y constraint to current editing mode.")
         .shortcut("Y")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(constrain_y)))
-        .write(commands);
+        .category("Transform")
+        .when_tree("No transformation in progress", is_transforming)
+        .tree_callback(constrain_y)
+        .register(state);
 
     CommandBuilder::new()
         .title("Constrain editing to Z axis")
         .system_name("constrain_z")
         .docs("Add a Z constraint to current editing mode.")
         .shortcut("Z")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(constrain_z)))
-        .write(commands);
+        .category("Transform")
+        .when_tree("No transformation in progress", is_transforming)
+        .tree_callback(constrain_z)
+        .register(state);
 
     CommandBuilder::new()
         .title("Scale")
         .system_name("scale")
         .docs("Start scaling selection.")
         .shortcut("S")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(start_scale)))
-        .write(commands);
+        .category("Object")
+        .menu("Object/Scale", 1)
+        .when_tree("Nothing is selected", has_selection)
+        .tree_callback(start_scale)
+        .register(state);
 
     CommandBuilder::new()
         .title("Rotate")
         .system_name("rotate")
         .docs("Start rotating selection.")
         .shortcut("R")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(start_rotate)))
-        .write(commands);
+        .category("Object")
+        .menu("Object/Rotate", 2)
+        .when_tree("Nothing is selected", has_selection)
+        .tree_callback(start_rotate)
+        .register(state);
 
     CommandBuilder::new()
         .title("Quit")
         .system_name("quit")
         .docs("Quit and cancel current editing state.")
         .shortcut("Escape")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(escape)))
-        .write(commands);
+        .category("Transform")
+        .when_tree("No transformation in progress", is_transforming)
+        .tree_callback(escape)
+        .register(state);
 
     CommandBuilder::new()
         .title("Finish")
         .system_name("finish")
         .docs("Finish and apply current editing state.")
         .shortcut("Return")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(finish)))
-        .write(commands);
+        .category("Transform")
+        .when_tree("No transformation in progress", is_transforming)
+        .tree_callback(finish)
+        .register(state);
 
     CommandBuilder::new()
         .title("Delete")
         .system_name("delete")
         .docs("Delete/Remove selection.")
         .shortcut("Back")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(delete)))
-        .write(commands);
+        .category("Object")
+        .menu("Object/Delete", 101)
+        .when_tree("Nothing is selected", has_selection)
+        .tree_callback(delete)
+        .register(state);
 
     CommandBuilder::new()
         .title("Select all/none")
         .system_name("select_all_or_none")
         .docs("Toggle selecting all objects.")
         .shortcut("Shift+A")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(select_all_or_none)))
-        .write(commands);
+        .category("Object")
+        .menu("Object/Select all or none", 200)
+        .tree_callback(select_all_or_none)
+        .register(state);
 
     CommandBuilder::new()
         .title("Duplicate")
         .system_name("duplicate")
         .docs("Duplicate selection.")
         .shortcut("Shift+D")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(duplicate)))
-        .write(commands);
+        .category("Object")
+        .menu("Object/Duplicate", 100)
+        .when_tree("Nothing is selected", has_selection)
+        .tree_callback(duplicate)
+        .register(state);
 
     CommandBuilder::new()
         .title("Spawn Sphere")
         .system_name("spawn-sphere")
-        .docs("Add a sphere at the given position")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(spawn_sphere)))
-        .write(commands);
+        .docs("Add a sphere at the given position. Without position, the new sphere follows the mouse.")
+        .category("Add")
+        .menu("Add/Sphere", 0)
+        .param(CommandParam::new("position", "Vec3", "Center of the sphere").optional())
+        .param(CommandParam::new("radius", "F32", "Radius of the sphere").default(ClaydashValue::F32(0.2)).range(0.01, 10.0))
+        .param(CommandParam::new("color", "Vec4", "Color of the sphere. Defaults to the color picker's color.").optional().range(0.0, 1.0))
+        .returns("Uuid", "Uuid of the new sphere")
+        .tree_callback_with_args(spawn_sphere)
+        .register(state);
 
     CommandBuilder::new()
         .title("Spawn Box")
         .system_name("spawn-box")
         .docs("Adds a cube at the given position")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(spawn_box)))
-        .write(commands);
+        .category("Add")
+        .menu("Add/Box", 1)
+        .tree_callback(spawn_box)
+        .register(state);
 }
 
 fn set_objects_initial_properties(
     tree: &mut  ObservableKVTree<ClaydashValue>
 ) {
-    let mut objects: Vec<SDFObject> = match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(data) => data,
-        _ => { return; }
-    };
-
     let selected_object_uuids = tree.get_path("scene.selected_uuids").unwrap_vec_uuid_or(Vec::new());
+    let objects: Vec<SDFObject> = selected_object_uuids.iter()
+        .filter_map(|uuid| scene_object(tree, uuid).cloned())
+        .collect();
 
-    let mut selected_object_sum_position: Vec3 = Vec3::ZERO;
-    let mut selected_object_count: i32 = 0;
-
-    // Find center of all selected objects
-    // It will be the reference point when transforming objects.
-    for object in objects.iter_mut() {
-        if selected_object_uuids.contains(&object.uuid) {
-            selected_object_sum_position += object.transform.translation;
-            selected_object_count += 1;
-        }
-    }
+    // The center of all selected objects will be the reference point when transforming objects.
+    let selection_center = get_selection_center(tree).unwrap_or(Vec3::ZERO);
     let mut initial_selection_transform = Transform::IDENTITY;
-    initial_selection_transform.translation = selected_object_sum_position / (selected_object_count as f32);
-    tree.set_path("editor.initial_selection_transform", ClaydashValue::Transform(initial_selection_transform));
+    initial_selection_transform.translation = selection_center;
+    TransformationState::set_initial_selection_transform(tree, EDITOR_PATH, initial_selection_transform);
 
-    tree.set_path("editor.initial_radius", ClaydashValue::F32(0.3));
+    TransformationState::set_initial_radius(tree, EDITOR_PATH, 0.3);
 
     // Find position of all objects relative to that center
-    for object in objects.iter_mut() {
-        if selected_object_uuids.contains(&object.uuid) {
-            let mut transform_relative_to_center = object.transform;
-            transform_relative_to_center.translation -= initial_selection_transform.translation;
-            tree.set_path(&format!("editor.initial_transform.{}", object.uuid), ClaydashValue::Transform(object.transform));
-            tree.set_path(&format!("editor.initial_transform_relative_to_selection.{}", object.uuid), ClaydashValue::Transform(transform_relative_to_center));
-        }
+    for object in objects.iter() {
+        let mut transform_relative_to_center = object.transform;
+        transform_relative_to_center.translation -= initial_selection_transform.translation;
+        InitialObjectTransform {
+            transform: object.transform,
+            relative_to_selection: transform_relative_to_center,
+        }.write_to(tree, &initial_object_transform_path(&object.uuid));
+    }
+}
+
+/// After this long without input, a pending shortcut sequence runs the command bound to
+/// the chords typed so far, if any, and is cleared.
+const SEQUENCE_TIMEOUT_SECONDS: f32 = 1.0;
+
+/// Chords of a shortcut sequence being typed, like `Ctrl+K` while waiting for `Ctrl+S`.
+#[derive(Resource, Default)]
+pub struct PendingShortcut {
+    pub chords: Vec<String>,
+    /// Command to run if the sequence times out.
+    command: Option<String>,
+    /// Time of the last chord, in seconds since startup.
+    last_chord_time: f32,
+}
+
+impl PendingShortcut {
+    fn clear(&mut self) {
+        self.chords.clear();
+        self.command = None;
     }
 }
 
@@ -159,112 +214,189 @@ pub fn run_shortcut_commands(
     world: &mut World,
 ){
     let mut system_state: SystemState<(
-        ResMut<CommandCentralState>,
-        ResMut<ClaydashData>,
-        Query<&Window>,
-        Res<Input<KeyCode>>
+        Res<CommandCentralState>,
+        ResMut<PendingShortcut>,
+        Res<Input<KeyCode>>,
+        Res<Time>,
     )> = SystemState::new(world);
 
-    let (mut bevy_command_central,
-         mut data_resource,
-         windows,
-         keys) = system_state.get_mut(world);
+    let (bevy_command_central,
+         mut pending,
+         keys,
+         time) = system_state.get_mut(world);
+
+    let now = time.elapsed_seconds();
+    let keymap = &bevy_command_central.keymap;
+    let mut system_names: Vec<String> = Vec::new();
 
+    if !pending.chords.is_empty() && now - pending.last_chord_time > SEQUENCE_TIMEOUT_SECONDS {
+        system_names.extend(pending.command.take());
+        pending.clear();
+    }
 
-    let commands = &mut bevy_command_central.commands.commands;
-    let tree = &mut data_resource.as_mut().tree;
-    let mut shortcut_sequence: String = String::new();
+    // Each key is its own chord, so keys pressed in the same frame don't make up a new shortcut.
     for key in keys.get_just_pressed() {
-        // Modifiers are not part of sequence themselves
-        match key {
-            KeyCode::ShiftLeft => { return }
-            KeyCode::SuperLeft => { return },
-            KeyCode::ControlLeft => { return },
-            _ => {}
+        let modifiers = held_modifiers(&keys);
+        // While typing in a text field, only shortcuts with Ctrl, Alt or Cmd are shortcuts.
+        if bevy_command_central.text_input_focused && (modifiers.is_empty() || modifiers == "Shift+") {
+            continue;
         }
-        let keyname = key_to_name(key);
-        // Mac command button is equivalent to shift in our system.
-        let has_shift = keys.any_pressed(vec!(KeyCode::ShiftLeft, KeyCode::SuperLeft));
-        let has_control = keys.pressed(KeyCode::ControlLeft);
-
-        let modifiers = match (has_control, has_shift) {
-            (true, true) => { "Ctrl+Shift+" },
-            (true, false) => { "Ctrl+" },
-            (false, true) => { "Shift+" },
-            _ => { "" }
+        let chord = match key_to_name(key) {
+            Some(key_name) => format!("{}{}", modifiers, key_name),
+            // Modifiers and unknown keys
+            None => { continue; }
         };
+        pending.chords.push(chord.clone());
+        pending.last_chord_time = now;
+
+        let mut shortcut_match = keymap.match_sequence(&pending.chords);
+        if shortcut_match == ShortcutMatch::None && pending.chords.len() > 1 {
+            // The sequence was abandoned: the chord may start a new one.
+            system_names.extend(pending.command.take());
+            pending.clear();
+            pending.chords.push(chord);
+            shortcut_match = keymap.match_sequence(&pending.chords);
+        }
 
-        let combo_name = format!("{}{}", modifiers, keyname);
+        match shortcut_match {
+            ShortcutMatch::Command(system_name) => {
+                system_names.push(system_name.to_string());
+                pending.clear();
+            },
+            ShortcutMatch::Pending { command } => {
+                pending.command = command.map(|command| command.to_string());
+            },
+            ShortcutMatch::None => {
+                // Conflicting shortcuts only run a command if the user bound one of them.
+                let shortcut = pending.chords.join(" ");
+                let conflicting_commands = keymap.commands_bound_to(&shortcut);
+                if conflicting_commands.len() > 1 {
+                    println!("{} is bound to several commands: {}.", shortcut, conflicting_commands.join(", "));
+                }
+                pending.clear();
+            },
+        }
+    }
 
-        shortcut_sequence += &combo_name;
+    if system_names.is_empty() {
+        return;
     }
 
-    for (_key, command) in commands.iter() {
-        if command.shortcut.is_empty() {
-            continue;
-        }
-        if shortcut_sequence == command.shortcut {
-            let window = windows.single();
-            tree.set_path(
-                "editor.initial_mouse_position",
-                ClaydashValue::Vec2(window.cursor_position().unwrap_or(Vec2::ZERO))
-            );
-            match command.parameters["callback"].value.clone().unwrap() {
-                ClaydashValue::Fn(callback) => callback(tree),
-                _ => {}
-            };
+    let cursor_position = world.query::<&Window>()
+        .iter(world)
+        .next()
+        .and_then(|window| window.cursor_position())
+        .unwrap_or(Vec2::ZERO);
+
+    // Commands need the world, which is borrowed by the system state until here.
+    for system_name in system_names.iter() {
+        world.resource_mut::<ClaydashData>().tree.set_path(
+            "editor.initial_mouse_position",
+            ClaydashValue::Vec2(cursor_position)
+        );
+        match run_command(world, system_name, &CommandArgs::new()) {
+            // Keys of unavailable commands do nothing, like X when nothing is being transformed.
+            Ok(_) | Err(CommandError::Unavailable { .. }) => {},
+            Err(error) => println!("{}", error),
         }
     }
 }
 
-fn key_to_name(key: &KeyCode) -> String {
+/// Modifiers held down, written like in shortcuts: "Ctrl+Shift+". Left and right modifiers are the same.
+fn held_modifiers(keys: &Input<KeyCode>) -> String {
+    let modifiers = [
+        ("Ctrl+", [KeyCode::ControlLeft, KeyCode::ControlRight]),
+        ("Alt+", [KeyCode::AltLeft, KeyCode::AltRight]),
+        ("Shift+", [KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+        // Command on macOS, Windows key elsewhere
+        ("Cmd+", [KeyCode::SuperLeft, KeyCode::SuperRight]),
+    ];
+    return modifiers.iter()
+        .filter(|(_, keycodes)| keys.any_pressed(*keycodes))
+        .map(|(name, _)| *name)
+        .collect();
+}
+
+/// Name of a key in shortcuts. None for modifiers, which are not keys of their own.
+fn key_to_name(key: &KeyCode) -> Option<&'static str> {
     return match key {
-        KeyCode::A => "A",
-        KeyCode::B => "B",
-        KeyCode::C => "C",
-        KeyCode::D => "D",
-        KeyCode::E => "E",
-        KeyCode::F => "F",
-        KeyCode::G => "G",
-        KeyCode::H => "H",
-        KeyCode::I => "I",
-        KeyCode::J => "J",
-        KeyCode::K => "K",
-        KeyCode::L => "L",
-        KeyCode::M => "M",
-        KeyCode::N => "N",
-        KeyCode::O => "O",
-        KeyCode::P => "P",
-        KeyCode::Q => "Q",
-        KeyCode::R => "R",
-        KeyCode::S => "S",
-        KeyCode::T => "T",
-        KeyCode::U => "U",
-        KeyCode::V => "V",
-        KeyCode::W => "W",
-        KeyCode::X => "X",
-        KeyCode::Y => "Y",
-        KeyCode::Z => "Z",
-        KeyCode::Escape => "Escape",
-        KeyCode::Return => "Return",
-        KeyCode::Back => "Back",
-        KeyCode::ShiftLeft => "Shift",
-        // Mac command button is equivalent to shift in our system.
-        // Shift+A == Command+A
-        KeyCode::SuperLeft => "Shift",
-        KeyCode::ControlLeft => "Ctrl",
+        KeyCode::A => Some("A"),
+        KeyCode::B => Some("B"),
+        KeyCode::C => Some("C"),
+        KeyCode::D => Some("D"),
+        KeyCode::E => Some("E"),
+        KeyCode::F => Some("F"),
+        KeyCode::G => Some("G"),
+        KeyCode::H => Some("H"),
+        KeyCode::I => Some("I"),
+        KeyCode::J => Some("J"),
+        KeyCode::K => Some("K"),
+        KeyCode::L => Some("L"),
+        KeyCode::M => Some("M"),
+        KeyCode::N => Some("N"),
+        KeyCode::O => Some("O"),
+        KeyCode::P => Some("P"),
+        KeyCode::Q => Some("Q"),
+        KeyCode::R => Some("R"),
+        KeyCode::S => Some("S"),
+        KeyCode::T => Some("T"),
+        KeyCode::U => Some("U"),
+        KeyCode::V => Some("V"),
+        KeyCode::W => Some("W"),
+        KeyCode::X => Some("X"),
+        KeyCode::Y => Some("Y"),
+        KeyCode::Z => Some("Z"),
+        KeyCode::Key0 => Some("0"),
+        KeyCode::Key1 => Some("1"),
+        KeyCode::Key2 => Some("2"),
+        KeyCode::Key3 => Some("3"),
+        KeyCode::Key4 => Some("4"),
+        KeyCode::Key5 => Some("5"),
+        KeyCode::Key6 => Some("6"),
+        KeyCode::Key7 => Some("7"),
+        KeyCode::Key8 => Some("8"),
+        KeyCode::Key9 => Some("9"),
+        KeyCode::Escape => Some("Escape"),
+        KeyCode::Return => Some("Return"),
+        KeyCode::Back => Some("Back"),
+        KeyCode::Delete => Some("Delete"),
+        KeyCode::Tab => Some("Tab"),
+        KeyCode::Space => Some("Space"),
+        KeyCode::Up => Some("Up"),
+        KeyCode::Down => Some("Down"),
+        KeyCode::Left => Some("Left"),
+        KeyCode::Right => Some("Right"),
+        KeyCode::ShiftLeft | KeyCode::ShiftRight |
+        KeyCode::ControlLeft | KeyCode::ControlRight |
+        KeyCode::AltLeft | KeyCode::AltRight |
+        KeyCode::SuperLeft | KeyCode::SuperRight => None,
         _ => {
             println!("note: last typed keycode not mapped to key.");
-            ""
+            None
         }
-    }.to_string();
+    };
 }
 
 
 fn reset_constraints(tree: &mut ObservableKVTree<ClaydashValue>) {
-    tree.set_path("editor.constrain_x", ClaydashValue::Bool(false));
-    tree.set_path("editor.constrain_y", ClaydashValue::Bool(false));
-    tree.set_path("editor.constrain_z", ClaydashValue::Bool(false));
+    TransformationState::set_constrain_x(tree, EDITOR_PATH, false);
+    TransformationState::set_constrain_y(tree, EDITOR_PATH, false);
+    TransformationState::set_constrain_z(tree, EDITOR_PATH, false);
+}
+
+fn has_selection(tree: &ObservableKVTree<ClaydashValue>) -> bool {
+    // Selected uuids can outlive their objects, e.g. when another user deletes them.
+    return tree.get_path("scene.selected_uuids").unwrap_vec_uuid_or(Vec::new())
+        .iter()
+        .any(|uuid| scene_object(tree, uuid).is_some());
+}
+
+/// Grabbing, scaling or rotating the selection.
+fn is_transforming(tree: &ObservableKVTree<ClaydashValue>) -> bool {
+    return match tree.get_path("editor.state").unwrap_editor_state_or(Start) {
+        Grabbing | Scaling | Rotating => true,
+        _ => false,
+    };
 }
 
 fn start_grab(tree: &mut ObservableKVTree<ClaydashValue>) {
@@ -273,21 +405,19 @@ fn start_grab(tree: &mut ObservableKVTree<ClaydashValue>) {
     tree.set_path("editor.state", ClaydashValue::EditorState(Grabbing));
 }
 
-fn toggle_path(tree: &mut ObservableKVTree<ClaydashValue>, path: String) {
-    let current_value = tree.get_path("editor.constrain_x").unwrap_bool_or(false);
-    tree.set_path(&path, ClaydashValue::Bool(!current_value));
-}
-
 fn constrain_x(tree: &mut ObservableKVTree<ClaydashValue>) {
-    toggle_path(tree, "editor.constrain_x".to_string());
+    let current_value = TransformationState::get_constrain_x(tree, EDITOR_PATH).unwrap_or(false);
+    TransformationState::set_constrain_x(tree, EDITOR_PATH, !current_value);
 }
 
 fn constrain_y(tree: &mut ObservableKVTree<ClaydashValue>) {
-    toggle_path(tree, "editor.constrain_y".to_string());
+    let current_value = TransformationState::get_constrain_y(tree, EDITOR_PATH).unwrap_or(false);
+    TransformationState::set_constrain_y(tree, EDITOR_PATH, !current_value);
 }
 
 fn constrain_z(tree: &mut ObservableKVTree<ClaydashValue>) {
-    toggle_path(tree, "editor.constrain_z".to_string());
+    let current_value = TransformationState::get_constrain_z(tree, EDITOR_PATH).unwrap_or(false);
+    TransformationState::set_constrain_z(tree, EDITOR_PATH, !current_value);
 }
 
 fn start_scale(tree: &mut ObservableKVTree<ClaydashValue>) {
@@ -318,20 +448,16 @@ fn escape(tree: &mut ObservableKVTree<ClaydashValue>) {
 
     let selected_object_uuids = tree.get_path("scene.selected_uuids").unwrap_vec_uuid_or(Vec::new());
 
-
-    let mut sdf_objects: Vec<SDFObject> = tree.get_path("scene.sdf_objects").unwrap_vec_sdf_object_or(Vec::new());
-
-    for object in sdf_objects.iter_mut() {
-        if !selected_object_uuids.contains(&object.uuid) {
-            continue;
-        }
-        let initial_transform = tree
-            .get_path(&format!("editor.initial_transform.{}", object.uuid))
-            .unwrap_transform_or(Transform::IDENTITY);
+    for uuid in selected_object_uuids.iter() {
+        let mut object = match scene_object(tree, uuid) {
+            Some(object) => object.clone(),
+            None => { continue; }
+        };
+        let initial_transform = InitialObjectTransform::get_transform(tree, &initial_object_transform_path(uuid))
+            .unwrap_or(Transform::IDENTITY);
         object.transform = initial_transform;
+        set_scene_object(tree, object);
     }
-
-    tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(sdf_objects));
 }
 
 fn finish(tree: &mut ObservableKVTree<ClaydashValue>) {
@@ -342,13 +468,8 @@ fn duplicate(tree: &mut ObservableKVTree<ClaydashValue>) {
     // Find selected objects
     let selected_object_uuids = tree.get_path("scene.selected_uuids").unwrap_vec_uuid_or(Vec::new());
 
-    let mut sdf_objects: Vec<SDFObject> = match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(objects) => { objects },
-        _ => { return; }
-    };
-
-    let mut duplicated_objects: Vec<SDFObject> = sdf_objects.iter().filter(| sdf_object | {
-        selected_object_uuids.contains(&sdf_object.uuid)
+    let duplicated_objects: Vec<SDFObject> = selected_object_uuids.iter().filter_map(|uuid| {
+        scene_object(tree, uuid)
     }).map(|object| {
         object.duplicate()
     }).collect();
@@ -359,8 +480,9 @@ fn duplicate(tree: &mut ObservableKVTree<ClaydashValue>) {
     }).collect();
 
     // Update the tree with duplicated objects
-    sdf_objects.append(&mut duplicated_objects);
-    tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(sdf_objects));
+    for object in duplicated_objects {
+        set_scene_object(tree, object);
+    }
     tree.set_path("scene.selected_uuids", ClaydashValue::VecUuid(duplicated_uuids));
 
     // Move these new objects
@@ -369,17 +491,16 @@ fn duplicate(tree: &mut ObservableKVTree<ClaydashValue>) {
 
 fn select_all_or_none(tree: &mut ObservableKVTree<ClaydashValue>) {
     let selected_uuids = tree.get_path("scene.selected_uuids").unwrap_vec_uuid_or(Vec::new());
-    let sdf_objects = tree.get_path("scene.sdf_objects").unwrap_vec_sdf_object_or(Vec::new());
+    let object_uuids: Vec<uuid::Uuid> = scene_objects(tree).iter().map(|object| { object.uuid }).collect();
 
-
-    if selected_uuids.len() == sdf_objects.len() {
+    if selected_uuids.len() == object_uuids.len() {
         // Everything is selected: now select none
         tree.set_path("scene.selected_uuids", ClaydashValue::VecUuid(default()));
     } else {
         // Select all
         tree.set_path(
             "scene.selected_uuids",
-            ClaydashValue::VecUuid(sdf_objects.iter().map(|object| { object.uuid }).collect())
+            ClaydashValue::VecUuid(object_uuids)
         );
         tree.set_path("editor.state", ClaydashValue::EditorState(Start));
     }
@@ -389,43 +510,48 @@ fn delete(tree: &mut ObservableKVTree<ClaydashValue>) {
     // Find selected objects
     let selected_object_uuids = tree.get_path("scene.selected_uuids").unwrap_vec_uuid_or(Vec::new());
 
-    let filtered_objects: Vec<SDFObject> = match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(objects) => {
-            objects.iter().filter(|object| {
-                !selected_object_uuids.contains(&object.uuid)
-            }).cloned().collect()
-        },
-        _ => { return; }
-    };
+    for uuid in selected_object_uuids.iter() {
+        remove_scene_object(tree, uuid);
+    }
 
-    tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(filtered_objects));
+    // Deleted objects can't stay selected.
+    tree.set_path("scene.selected_uuids", ClaydashValue::VecUuid(Vec::new()));
 }
 
-fn spawn_sphere(tree: &mut ObservableKVTree<ClaydashValue>) {
-    let color = match tree.get_path("editor.colorpicker.color") {
-        ClaydashValue::Vec4(data) => data,
-        _ => Vec4::new(0.4, 0.2, 0.0, 1.0),
+fn spawn_sphere(
+    tree: &mut ObservableKVTree<ClaydashValue>,
+    args: &CommandArgs<ClaydashValue>
+) -> Option<ClaydashValue> {
+    let color = match args.get("color") {
+        Some(ClaydashValue::Vec4(color)) => *color,
+        _ => tree.get_path("editor.colorpicker.color").unwrap_vec4_or(Vec4::new(0.4, 0.2, 0.0, 1.0)),
     };
-
-    let mut sdf_objects: Vec<SDFObject> = match tree.get_path("scene.sdf_objects") {
-        ClaydashValue::VecSDFObject(objects) => { objects },
-        _ => { vec!() }
+    let position = match args.get("position") {
+        Some(ClaydashValue::Vec3(position)) => Some(*position),
+        _ => None,
+    };
+    let radius = match args.get("radius") {
+        Some(ClaydashValue::F32(radius)) => *radius,
+        _ => 0.2,
     };
 
     let mut new_object = SDFObject::create(sdf_consts::TYPE_SPHERE);
     new_object.color = color;
+    new_object.params = SDFObjectParams::SphereParams(SphereParams { radius });
+    new_object.transform.translation = position.unwrap_or(Vec3::ZERO);
     let uuid = new_object.uuid;
 
-    sdf_objects.push(new_object);
-
-    // Update the tree with duplicated objects
-    tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(sdf_objects));
+    set_scene_object(tree, new_object);
     tree.set_path("editor.state", ClaydashValue::EditorState(Start));
 
     tree.set_path("scene.selected_uuids", ClaydashValue::VecUuid(vec!(uuid)));
 
     // Move new objects
-    start_grab(tree);
+    if position.is_none() {
+        start_grab(tree);
+    }
+
+    return Some(ClaydashValue::Uuid(uuid));
 }
 
 fn spawn_box(tree: &mut ObservableKVTree<ClaydashValue>) {
@@ -434,17 +560,12 @@ fn spawn_box(tree: &mut ObservableKVTree<ClaydashValue>) {
         _ => Vec4::new(0.4, 0.2, 0.0, 1.0),
     };
 
-    let mut sdf_objects: Vec<SDFObject> = tree.get_path("scene.sdf_objects").unwrap_vec_sdf_object_or(Vec::new());
-
     let mut new_object = SDFObject::create(TYPE_BOX);
     new_object.color = color;
 
     let uuid = new_object.uuid;
 
-    sdf_objects.push(new_object);
-
-    // Update the tree with duplicated objects
-    tree.set_path("scene.sdf_objects", ClaydashValue::VecSDFObject(sdf_objects));
+    set_scene_object(tree, new_object);
     tree.set_path("editor.state", ClaydashValue::EditorState(Start));
 
     tree.set_path("scene.selected_uuids", ClaydashValue::VecUuid(vec!(uuid)));
@@ -5,6 +5,11 @@ mod claydash_data;
 mod interactions;
 mod claydash_ui;
 mod undo_redo;
+mod scene_sync;
+mod scripting;
+mod command_macros;
+mod preferences;
+mod remote_control;
 
 // This is only for native builds
 #[allow(unused_imports)]
@@ -20,7 +25,7 @@ use smooth_bevy_cameras::{
     }
 };
 
-use command_central_plugin::{BevyCommandCentralPlugin, CommandCentralState};
+use command_central_plugin::{BevyCommandCentralPlugin, CommandCentralState, ClaydashCommandBuilder};
 
 use bevy::{
     input::{keyboard::KeyCode, Input},
@@ -32,12 +37,17 @@ use bevy_sdf_object::*;
 use bevy_mod_picking::prelude::*;
 
 use undo_redo::ClaydashUndoRedoPlugin;
+use scene_sync::SceneSyncPlugin;
+use scripting::ScriptingPlugin;
+use command_macros::CommandMacrosPlugin;
+use preferences::PreferencesPlugin;
+use remote_control::RemoteControlPlugin;
 #[allow(unused_imports)]
 use wasm_bindgen::prelude::*;
 
 use crate::interactions::ClaydashInteractionPlugin;
 
-use claydash_data::{ClaydashDataPlugin, ClaydashValue, ClaydashData};
+use claydash_data::{ClaydashDataPlugin, ClaydashValue, ClaydashData, toggle_session_recording, migrate_scene};
 
 
 fn main() {
@@ -61,7 +71,9 @@ fn main() {
             claydash_ui::ClaydashUIPlugin,
             ClaydashInteractionPlugin,
             MaterialPlugin::<GridMaterial>::default(),
-            ClaydashUndoRedoPlugin
+            ClaydashUndoRedoPlugin,
+            // Plugin tuples are limited to 15 elements
+            (SceneSyncPlugin, ScriptingPlugin, CommandMacrosPlugin, PreferencesPlugin, RemoteControlPlugin)
         ))
         .add_systems(Startup, (remove_picking_logs,
                                setup_frame_limit,
@@ -81,21 +93,33 @@ mod duck;
 pub fn default_duck(mut data_resource: ResMut<ClaydashData>) {
     let tree = &mut data_resource.as_mut().tree;
     let scene: Result<ObservableKVTree<ClaydashValue>, serde_json::Error> = serde_json::from_str(duck::DEFAULT_DUCK);
-    tree.set_tree("scene", scene.unwrap());
+    tree.set_tree("scene", migrate_scene(scene.unwrap()));
 
     // Add snapshot for initial state
     tree.make_undo_redo_snapshot();
 }
 
 pub fn register_debug_commands(mut bevy_command_central: ResMut<CommandCentralState>) {
-    let commands = &mut bevy_command_central.commands;
+    let state = bevy_command_central.as_mut();
     CommandBuilder::new()
         .title("Dump Tree")
         .system_name("dump-tree")
         .docs("Dump internal data tree to shell. This is a troubleshooting command for developers.")
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(dump_tree)))
-        .write(commands);
+        .category("Debug")
+        .mutates_document(false)
+        .tree_callback(dump_tree)
+        .register(state);
 
+    CommandBuilder::new()
+        .title("Toggle Session Recording")
+        .system_name("toggle-session-recording")
+        .docs("Start/stop recording every change to the data tree. The session log can be saved from the File menu and replayed to reproduce bugs.")
+        .category("File")
+        .mutates_document(false)
+        .menu("File/Record session", 100)
+        .checked_tree(|tree| tree.operation_log.is_some())
+        .tree_callback(toggle_session_recording)
+        .register(state);
 }
 
 pub fn dump_tree(tree: &mut ObservableKVTree<ClaydashValue>) {
@@ -0,0 +1,267 @@
+//! User preferences: for now, the keymap.
+//! The cheat sheet of commands and shortcuts is exported from here too, since it includes the user's shortcuts.
+//!
+//! Shortcuts chosen by the user are saved as JSON, by command system name:
+//! `{ "grab": "M", "duplicate": "", "dump-tree": "Ctrl+T" }`.
+//! An empty shortcut unbinds the command.
+//!
+//! Native builds read `$XDG_CONFIG_HOME/claydash/keymap.json` (or `~/.config/claydash/keymap.json`).
+//! On the web, changes made in the preferences panel only last for the session.
+
+use std::collections::BTreeMap;
+
+use bevy::{prelude::*, ecs::system::RunSystemOnce};
+use bevy_egui::{egui, EguiContexts};
+use command_central::{CommandBuilder, CommandArgs, CommandParam, Keymap, normalize_shortcut};
+use egui::Color32;
+
+use crate::claydash_data::ClaydashValue;
+use crate::command_central_plugin::{CommandCentralState, ClaydashCommandArgs, ClaydashCommandBuilder, queue_command};
+
+const DEFAULT_CHEAT_SHEET_PATH: &str = "claydash-cheat-sheet.md";
+
+pub struct PreferencesPlugin;
+
+impl Plugin for PreferencesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PreferencesState>()
+            .add_systems(Startup, register_preferences_commands)
+            // Commands are registered during startup, so their shortcuts are known after it.
+            .add_systems(PostStartup, load_keymap)
+            .add_systems(Update, preferences_ui);
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PreferencesState {
+    pub open: bool,
+    /// Problems found when loading the keymap.
+    pub keymap_errors: Vec<String>,
+    /// Text typed in shortcut fields, by system name.
+    shortcut_edits: BTreeMap<String, String>,
+}
+
+fn register_preferences_commands(mut bevy_command_central: ResMut<CommandCentralState>) {
+    let state = bevy_command_central.as_mut();
+
+    CommandBuilder::new()
+        .title("Preferences")
+        .system_name("toggle-preferences")
+        .docs("Show or hide preferences, where shortcuts can be changed. Keymap, keybindings, settings.")
+        .category("Preferences")
+        .mutates_document(false)
+        .menu("Edit/Preferences", 100)
+        .callback(toggle_preferences)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Save keymap")
+        .system_name("save-keymap")
+        .docs("Save shortcuts changed in preferences to the keymap file.")
+        .category("Preferences")
+        .mutates_document(false)
+        .callback(save_keymap)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Reload keymap")
+        .system_name("reload-keymap")
+        .docs("Read shortcuts from the keymap file again, and report conflicts.")
+        .category("Preferences")
+        .mutates_document(false)
+        .callback(reload_keymap)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Export cheat sheet")
+        .system_name("export-cheat-sheet")
+        .docs("Write every command with its shortcut, docs and parameters to a Markdown file, or HTML if the path ends with .html. Keyboard shortcuts list, help.")
+        .category("Help")
+        .mutates_document(false)
+        .menu("Help/Export cheat sheet", 0)
+        .param(CommandParam::new("path", "String", "File to write").default(ClaydashValue::String(DEFAULT_CHEAT_SHEET_PATH.to_string())))
+        .callback_with_args(export_cheat_sheet)
+        .register(state);
+}
+
+fn toggle_preferences(world: &mut World) {
+    let mut preferences = world.resource_mut::<PreferencesState>();
+    preferences.open = !preferences.open;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn keymap_file_path() -> Option<std::path::PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config_dir) => std::path::PathBuf::from(config_dir),
+        None => std::path::PathBuf::from(std::env::var_os("HOME").or_else(|| std::env::var_os("APPDATA"))?).join(".config"),
+    };
+    return Some(config_dir.join("claydash").join("keymap.json"));
+}
+
+/// Shortcuts saved by the user. No file means no changes.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_keymap_file() -> Result<BTreeMap<String, String>, String> {
+    let path = match keymap_file_path() {
+        Some(path) => path,
+        None => { return Ok(BTreeMap::new()); }
+    };
+    return match std::fs::read_to_string(&path) {
+        Ok(json) => serde_json::from_str(&json).map_err(|error| format!("Could not read {}: {}", path.display(), error)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(error) => Err(format!("Could not read {}: {}", path.display(), error)),
+    };
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_keymap_file() -> Result<BTreeMap<String, String>, String> {
+    return Ok(BTreeMap::new());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_keymap_file(overrides: &BTreeMap<String, String>) -> Result<(), String> {
+    let path = keymap_file_path().ok_or("No config directory.".to_string())?;
+    let json = serde_json::to_string_pretty(overrides).map_err(|error| error.to_string())?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|error| error.to_string())?;
+    }
+    std::fs::write(&path, json).map_err(|error| error.to_string())?;
+    println!("Saved keymap to {}", path.display());
+    return Ok(());
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_keymap_file(_overrides: &BTreeMap<String, String>) -> Result<(), String> {
+    return Err("Keymaps can't be saved in web builds yet.".to_string());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_cheat_sheet_file(path: &str, contents: &str) -> Result<(), String> {
+    return std::fs::write(path, contents).map_err(|error| error.to_string());
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_cheat_sheet_file(_path: &str, _contents: &str) -> Result<(), String> {
+    return Err("Files can't be written in web builds yet.".to_string());
+}
+
+fn export_cheat_sheet(world: &mut World, args: &ClaydashCommandArgs) -> Option<ClaydashValue> {
+    let path = match &args["path"] {
+        ClaydashValue::String(path) => path.clone(),
+        _ => { return None; }
+    };
+    let state = world.resource::<CommandCentralState>();
+    let contents = match path.ends_with(".html") || path.ends_with(".htm") {
+        true => state.commands.cheat_sheet_html(&state.keymap),
+        false => state.commands.cheat_sheet_markdown(&state.keymap),
+    };
+    match write_cheat_sheet_file(&path, &contents) {
+        Ok(()) => println!("Wrote cheat sheet to {}", path),
+        Err(error) => println!("Could not write cheat sheet: {}", error),
+    }
+    return None;
+}
+
+fn load_keymap(
+    mut bevy_command_central: ResMut<CommandCentralState>,
+    mut preferences: ResMut<PreferencesState>,
+) {
+    let mut keymap = Keymap::from_commands(&bevy_command_central.commands);
+    let mut errors: Vec<String> = Vec::new();
+
+    match read_keymap_file() {
+        Ok(overrides) => {
+            errors.extend(keymap.load_overrides(overrides).iter().map(|error| error.to_string()));
+        },
+        Err(error) => errors.push(error),
+    }
+
+    for error in errors.iter() {
+        println!("Keymap: {}", error);
+    }
+
+    bevy_command_central.keymap = keymap;
+    preferences.keymap_errors = errors;
+    preferences.shortcut_edits.clear();
+}
+
+fn reload_keymap(world: &mut World) {
+    _ = world.run_system_once(load_keymap);
+}
+
+fn save_keymap(world: &mut World) {
+    let overrides = world.resource::<CommandCentralState>().keymap.overrides().clone();
+    if let Err(error) = write_keymap_file(&overrides) {
+        println!("Could not save keymap: {}", error);
+    }
+}
+
+fn preferences_ui(
+    mut contexts: EguiContexts,
+    mut preferences: ResMut<PreferencesState>,
+    mut bevy_command_central: ResMut<CommandCentralState>,
+    mut commands: Commands,
+) {
+    if !preferences.open {
+        return;
+    }
+
+    let mut open = preferences.open;
+    let preferences = preferences.as_mut();
+    let state = bevy_command_central.as_mut();
+
+    egui::Window::new("Preferences")
+        .open(&mut open)
+        .default_width(420.0)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.heading("Shortcuts");
+
+            for error in preferences.keymap_errors.iter() {
+                ui.colored_label(Color32::LIGHT_RED, error);
+            }
+            for conflict in state.keymap.conflicts() {
+                ui.colored_label(Color32::YELLOW, conflict.to_string());
+            }
+
+            egui::ScrollArea::vertical()
+                .max_height(400.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("shortcuts").striped(true).show(ui, |ui| {
+                        for (system_name, command) in state.commands.commands.iter() {
+                            let edit = preferences.shortcut_edits
+                                .entry(system_name.clone())
+                                .or_insert_with(|| state.keymap.shortcut(system_name).unwrap_or("").to_string());
+
+                            ui.label(&command.title).on_hover_text(system_name);
+                            let response = ui.add(
+                                egui::TextEdit::singleline(edit)
+                                    .hint_text("None")
+                                    .desired_width(100.0)
+                            );
+                            if response.changed() {
+                                match state.keymap.default_shortcut(system_name).unwrap_or("") == normalize_shortcut(edit) {
+                                    true => state.keymap.reset(system_name),
+                                    false => state.keymap.set_override(system_name, edit),
+                                }
+                            }
+                            if ui.add_enabled(state.keymap.is_overridden(system_name), egui::Button::new("Reset")).clicked() {
+                                state.keymap.reset(system_name);
+                                *edit = state.keymap.shortcut(system_name).unwrap_or("").to_string();
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    queue_command(&mut commands, "save-keymap", CommandArgs::new());
+                }
+                if ui.button("Reload").clicked() {
+                    queue_command(&mut commands, "reload-keymap", CommandArgs::new());
+                }
+            });
+        });
+
+    preferences.open = open;
+}
@@ -0,0 +1,680 @@
+//! Remote control over JSON-RPC 2.0, to drive claydash from external tools and tests.
+//! Run "Toggle remote control", or start claydash with `CLAYDASH_REMOTE_CONTROL=127.0.0.1:9002`.
+//!
+//! Clients connect with WebSocket, or with plain TCP sending one JSON message per line.
+//! Methods:
+//!  - `commands.list`: every command, with its parameters, shortcut and whether it can run now.
+//!  - `commands.run` `{ "name": "spawn-sphere", "args": { "radius": 0.5 } }`: runs a command,
+//!    returns its return value.
+//!  - `tree.get` `{ "path": "scene.selected_uuids" }` and `tree.set` `{ "path": ..., "value": ... }`.
+//!  - `tree.query` `{ "pattern": "editor.**" }`: paths and values matching a pattern.
+//!    Values can be filtered like in scripts: `{ "pattern": "scene.objects.*[kind=SDFObject]" }`.
+//!  - `tree.subscribe` `{ "pattern": "scene.**" }`: returns a subscription id. Changes are then sent
+//!    as `tree.changed` notifications, with the subscription id, path and value.
+//!    `tree.unsubscribe` `{ "subscription": 1 }` stops them.
+//!
+//! Values are converted like in scripts: arrays of 2 to 4 numbers are vectors and uuids are strings.
+//! Other values use their serialized form, like `{ "Transform": ... }`.
+//! `cargo run -p remote_client` runs an editing session through the remote control.
+//!
+//! The remote control only listens on loopback addresses, unless `editor.remote_control.allow_remote`
+//! is set (or `CLAYDASH_REMOTE_CONTROL_ALLOW_REMOTE=1`). Web pages can't use it: WebSocket handshakes
+//! sent by browsers (with an `Origin` header) and other HTTP requests are refused.
+//! Changing the address or `allow_remote` while the remote control runs restarts it, and clients
+//! have to connect again.
+//!
+//! Only native builds can be remote controlled for now.
+
+use bevy::prelude::*;
+
+pub struct RemoteControlPlugin;
+
+impl Plugin for RemoteControlPlugin {
+    fn build(&self, _app: &mut App) {
+        #[cfg(not(target_arch = "wasm32"))]
+        _app.init_non_send_resource::<native::RemoteControlState>()
+            .add_systems(Startup, native::setup_remote_control_commands)
+            .add_systems(Update, native::serve_remote_control);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use bevy::prelude::*;
+    use std::collections::BTreeMap;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+    use command_central::{CommandBuilder, CommandArgs};
+    use observable_key_value_tree::{ObservableKVTree, Update, path_matches_pattern};
+    use serde_json::{json, Value};
+    use tungstenite::{Message, WebSocket};
+    use tungstenite::handshake::server::{Request, Response, ErrorResponse};
+    use tungstenite::http::StatusCode;
+
+    use crate::claydash_data::{ClaydashData, ClaydashValue};
+    use crate::command_central_plugin::{
+        CommandCentralState,
+        ClaydashCommandArgs,
+        ClaydashCommandBuilder,
+        run_command,
+        make_command_undo_step,
+        make_pending_changes_undo_step
+    };
+
+    const DEFAULT_ADDRESS: &str = "127.0.0.1:9002";
+    /// Starts the remote control at launch, on the address it contains.
+    const ADDRESS_VARIABLE: &str = "CLAYDASH_REMOTE_CONTROL";
+    /// Set to 1 to let the remote control listen on addresses other than loopback.
+    const ALLOW_REMOTE_VARIABLE: &str = "CLAYDASH_REMOTE_CONTROL_ALLOW_REMOTE";
+    /// How long client threads wait for a message before sending responses.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    // JSON-RPC error codes
+    const PARSE_ERROR: i64 = -32700;
+    const INVALID_REQUEST: i64 = -32600;
+    const METHOD_NOT_FOUND: i64 = -32601;
+    const INVALID_PARAMS: i64 = -32602;
+    /// Commands or tree writes that failed.
+    const APP_ERROR: i64 = -32000;
+
+    enum ClientEvent {
+        Connected(usize, Sender<String>),
+        Request(usize, String),
+        Disconnected(usize),
+    }
+
+    struct RemoteClient {
+        outgoing: Sender<String>,
+        /// Tree patterns, by subscription id.
+        subscriptions: BTreeMap<u64, String>,
+    }
+
+    struct RemoteControlServer {
+        events: Receiver<ClientEvent>,
+        clients: BTreeMap<usize, RemoteClient>,
+        updates: Receiver<Update<ClaydashValue>>,
+        next_subscription_id: u64,
+        /// Cleared when the server is dropped, to stop the listener thread.
+        running: Arc<AtomicBool>,
+        listener_thread: Option<JoinHandle<()>>,
+        /// Settings the server was started with. It is restarted when they change.
+        address: String,
+        allow_remote: bool,
+    }
+
+    impl Drop for RemoteControlServer {
+        fn drop(&mut self) {
+            self.running.store(false, Ordering::Relaxed);
+            // Waits for the listener to close, so its address can be bound again right away.
+            if let Some(listener_thread) = self.listener_thread.take() {
+                _ = listener_thread.join();
+            }
+        }
+    }
+
+    /// Not a regular resource, since the server holds channel receivers.
+    #[derive(Default)]
+    pub struct RemoteControlState {
+        server: Option<RemoteControlServer>,
+    }
+
+    struct RpcError {
+        code: i64,
+        message: String,
+    }
+
+    impl RpcError {
+        fn new(code: i64, message: &str) -> Self {
+            return RpcError { code, message: message.to_string() };
+        }
+    }
+
+    pub fn setup_remote_control_commands(
+        mut bevy_command_central: ResMut<CommandCentralState>,
+        mut data_resource: ResMut<ClaydashData>,
+    ) {
+        let state = bevy_command_central.as_mut();
+
+        CommandBuilder::new()
+            .title("Toggle remote control")
+            .system_name("toggle-remote-control")
+            .docs("Let external tools run commands and read or write the data tree over JSON-RPC (editor.remote_control.address, default 127.0.0.1:9002).")
+            .category("Script")
+            .mutates_document(false)
+            .menu("Script/Remote control", 200)
+            .checked_tree(|tree| tree.get_path("editor.remote_control.enabled").unwrap_bool_or(false))
+            .tree_callback(toggle_remote_control)
+            .register(state);
+
+        if let Ok(address) = std::env::var(ADDRESS_VARIABLE) {
+            let tree = &mut data_resource.as_mut().tree;
+            tree.set_path("editor.remote_control.address", ClaydashValue::String(address));
+            tree.set_path("editor.remote_control.enabled", ClaydashValue::Bool(true));
+        }
+        if std::env::var(ALLOW_REMOTE_VARIABLE).is_ok_and(|value| value == "1") {
+            let tree = &mut data_resource.as_mut().tree;
+            tree.set_path("editor.remote_control.allow_remote", ClaydashValue::Bool(true));
+        }
+    }
+
+    fn toggle_remote_control(tree: &mut ObservableKVTree<ClaydashValue>) {
+        let enabled = tree.get_path("editor.remote_control.enabled").unwrap_bool_or(false);
+        tree.set_path("editor.remote_control.enabled", ClaydashValue::Bool(!enabled));
+    }
+
+    /// Answers requests of remote clients and notifies them of tree changes.
+    pub fn serve_remote_control(world: &mut World) {
+        let tree = &mut world.resource_mut::<ClaydashData>().tree;
+        let enabled = tree.get_path("editor.remote_control.enabled").unwrap_bool_or(false);
+        let address = match tree.get_path("editor.remote_control.address") {
+            ClaydashValue::String(address) => address,
+            _ => DEFAULT_ADDRESS.to_string()
+        };
+        let allow_remote = tree.get_path("editor.remote_control.allow_remote").unwrap_bool_or(false);
+
+        // The server is taken out of its resource while requests run, since they need the whole world.
+        // A server started with other settings is dropped, and started again below.
+        let server = world.non_send_resource_mut::<RemoteControlState>().server.take()
+            .filter(|server| server.address == address && server.allow_remote == allow_remote);
+        let mut server = match server {
+            Some(server) => server,
+            None => {
+                if !enabled {
+                    return;
+                }
+                match start_server(&address, allow_remote, &mut world.resource_mut::<ClaydashData>().tree) {
+                    Ok(server) => server,
+                    Err(error) => {
+                        println!("Could not start remote control on {}: {}", address, error);
+                        disable(world);
+                        return;
+                    }
+                }
+            }
+        };
+
+        if !enabled {
+            // Dropping the server stops its threads and closes connections.
+            return;
+        }
+
+        loop {
+            match server.events.try_recv() {
+                Ok(ClientEvent::Connected(client_id, outgoing)) => {
+                    server.clients.insert(client_id, RemoteClient {
+                        outgoing,
+                        subscriptions: BTreeMap::new(),
+                    });
+                },
+                Ok(ClientEvent::Request(client_id, text)) => {
+                    let response = handle_message(world, &mut server, client_id, &text);
+                    if let (Some(response), Some(client)) = (response, server.clients.get(&client_id)) {
+                        _ = client.outgoing.send(response.to_string());
+                    }
+                },
+                Ok(ClientEvent::Disconnected(client_id)) => {
+                    server.clients.remove(&client_id);
+                },
+                Err(TryRecvError::Empty) => { break; },
+                Err(TryRecvError::Disconnected) => {
+                    // The listener thread stopped.
+                    disable(world);
+                    return;
+                }
+            }
+        }
+
+        send_tree_notifications(&server);
+        world.non_send_resource_mut::<RemoteControlState>().server = Some(server);
+    }
+
+    fn disable(world: &mut World) {
+        let tree = &mut world.resource_mut::<ClaydashData>().tree;
+        tree.set_path("editor.remote_control.enabled", ClaydashValue::Bool(false));
+    }
+
+    fn start_server(
+        address: &str,
+        allow_remote: bool,
+        tree: &mut ObservableKVTree<ClaydashValue>
+    ) -> std::io::Result<RemoteControlServer> {
+        // Anyone on the network could edit the document, so other interfaces need opting in.
+        let is_loopback = address.to_socket_addrs()?.all(|address| address.ip().is_loopback());
+        if !is_loopback && !allow_remote {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "not a loopback address, set editor.remote_control.allow_remote to listen on it"
+            ));
+        }
+
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        println!("Remote control listening on {}.", address);
+
+        let (events_tx, events_rx) = channel::<ClientEvent>();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let listener_thread = std::thread::spawn(move || accept_clients(listener, events_tx, thread_running));
+
+        return Ok(RemoteControlServer {
+            events: events_rx,
+            clients: BTreeMap::new(),
+            updates: tree.create_update_channel(),
+            next_subscription_id: 1,
+            running,
+            listener_thread: Some(listener_thread),
+            address: address.to_string(),
+            allow_remote,
+        });
+    }
+
+    /// Starts a thread per client until the server stops.
+    fn accept_clients(listener: TcpListener, events: Sender<ClientEvent>, running: Arc<AtomicBool>) {
+        let mut next_client_id = 0;
+
+        while running.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let client_id = next_client_id;
+                    next_client_id += 1;
+
+                    let (outgoing_tx, outgoing_rx) = channel::<String>();
+                    if events.send(ClientEvent::Connected(client_id, outgoing_tx)).is_err() {
+                        return;
+                    }
+
+                    let events = events.clone();
+                    std::thread::spawn(move || {
+                        match serve_client(stream, client_id, outgoing_rx, &events) {
+                            Ok(()) | Err(tungstenite::Error::ConnectionClosed) => {},
+                            Err(error) => { println!("Remote control client disconnected: {}", error); }
+                        }
+                        _ = events.send(ClientEvent::Disconnected(client_id));
+                    });
+                },
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(POLL_INTERVAL);
+                },
+                Err(error) => {
+                    println!("Remote control stopped: {}", error);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// A client talks WebSocket, or plain TCP with one message per line.
+    enum Connection {
+        WebSocket(WebSocket<TcpStream>),
+        Lines { stream: TcpStream, buffer: Vec<u8> },
+    }
+
+    fn is_timeout(error: &std::io::Error) -> bool {
+        return error.kind() == std::io::ErrorKind::WouldBlock || error.kind() == std::io::ErrorKind::TimedOut;
+    }
+
+    impl Connection {
+        /// Next received message, or None if there is none yet.
+        fn read(&mut self) -> tungstenite::Result<Option<String>> {
+            return match self {
+                Connection::WebSocket(socket) => match socket.read() {
+                    Ok(Message::Text(text)) => Ok(Some(text)),
+                    Ok(Message::Close(_)) => Err(tungstenite::Error::ConnectionClosed),
+                    Ok(_) => Ok(None),
+                    Err(tungstenite::Error::Io(error)) if is_timeout(&error) => Ok(None),
+                    Err(error) => Err(error),
+                },
+                Connection::Lines { stream, buffer } => {
+                    if let Some(line) = take_line(buffer) {
+                        return Ok(Some(line));
+                    }
+                    let mut chunk = [0u8; 4096];
+                    match stream.read(&mut chunk) {
+                        Ok(0) => Err(tungstenite::Error::ConnectionClosed),
+                        Ok(length) => {
+                            buffer.extend_from_slice(&chunk[..length]);
+                            Ok(take_line(buffer))
+                        },
+                        Err(error) if is_timeout(&error) => Ok(None),
+                        Err(error) => Err(error.into()),
+                    }
+                }
+            };
+        }
+
+        fn send(&mut self, text: String) -> tungstenite::Result<()> {
+            return match self {
+                Connection::WebSocket(socket) => socket.send(Message::Text(text)),
+                Connection::Lines { stream, .. } => {
+                    stream.write_all(text.as_bytes())?;
+                    stream.write_all(b"\n")?;
+                    Ok(())
+                }
+            };
+        }
+    }
+
+    /// Removes the first complete line from the buffer. Empty lines are skipped.
+    fn take_line(buffer: &mut Vec<u8>) -> Option<String> {
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                return Some(line);
+            }
+        }
+        return None;
+    }
+
+    /// Refuses WebSocket handshakes sent by web pages. Browsers always send an `Origin` header,
+    /// other clients don't need to.
+    fn refuse_browsers(request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        if request.headers().contains_key("Origin") {
+            let mut error = ErrorResponse::new(Some("Web pages can't use the remote control.".to_string()));
+            *error.status_mut() = StatusCode::FORBIDDEN;
+            return Err(error);
+        }
+        return Ok(response);
+    }
+
+    /// Forwards messages between a client and the app until either side disconnects.
+    fn serve_client(
+        stream: TcpStream,
+        client_id: usize,
+        outgoing: Receiver<String>,
+        events: &Sender<ClientEvent>
+    ) -> tungstenite::Result<()> {
+        // Some platforms make accepted streams non-blocking like their listener.
+        stream.set_nonblocking(false)?;
+
+        // WebSocket handshakes start with an HTTP GET request.
+        let mut start = [0u8; 3];
+        let length = stream.peek(&mut start)?;
+        let start = &start[..length];
+        let mut connection = if start == b"GET" {
+            match tungstenite::accept_hdr(stream, refuse_browsers) {
+                Ok(socket) => Connection::WebSocket(socket),
+                Err(error) => {
+                    println!("Remote control handshake failed: {}", error);
+                    return Ok(());
+                }
+            }
+        } else if start.first().is_some_and(u8::is_ascii_alphabetic) {
+            // Other HTTP requests, like a form posted by a web page, could smuggle messages in their body.
+            println!("Remote control refused an HTTP request.");
+            return Ok(());
+        } else {
+            Connection::Lines { stream, buffer: Vec::new() }
+        };
+
+        match &connection {
+            Connection::WebSocket(socket) => socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?,
+            Connection::Lines { stream, .. } => stream.set_read_timeout(Some(POLL_INTERVAL))?,
+        }
+
+        loop {
+            loop {
+                match outgoing.try_recv() {
+                    Ok(text) => { connection.send(text)?; },
+                    Err(TryRecvError::Empty) => { break; },
+                    Err(TryRecvError::Disconnected) => {
+                        if let Connection::WebSocket(socket) = &mut connection {
+                            socket.close(None)?;
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+
+            if let Some(text) = connection.read()? {
+                if events.send(ClientEvent::Request(client_id, text)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Response to a JSON-RPC message. Notifications (requests without id) get none.
+    fn handle_message(world: &mut World, server: &mut RemoteControlServer, client_id: usize, text: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(error) => { return Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, &error.to_string()))); }
+        };
+        let id = request.get("id").cloned();
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            None => {
+                return Some(error_response(id.unwrap_or(Value::Null), RpcError::new(INVALID_REQUEST, "Missing method.")));
+            }
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = call_method(world, server, client_id, method, &params);
+
+        return Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id?, "result": result }),
+            Err(error) => error_response(id?, error),
+        });
+    }
+
+    fn error_response(id: Value, error: RpcError) -> Value {
+        return json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": error.code, "message": error.message }
+        });
+    }
+
+    fn call_method(
+        world: &mut World,
+        server: &mut RemoteControlServer,
+        client_id: usize,
+        method: &str,
+        params: &Value
+    ) -> Result<Value, RpcError> {
+        return match method {
+            "commands.list" => Ok(list_commands(world)),
+            "commands.run" => run_remote_command(world, params),
+            "tree.get" => {
+                let path = string_param(params, "path")?;
+                let tree = &world.resource::<ClaydashData>().tree;
+                Ok(tree.get_ref(path).map(value_to_json).unwrap_or(Value::Null))
+            },
+            "tree.set" => set_tree_path(world, params),
+            "tree.query" => {
+                let pattern = string_param(params, "pattern")?;
+                let tree = &world.resource::<ClaydashData>().tree;
+                let results = tree.query_filtered(pattern, ClaydashValue::kind)
+                    .map_err(|error| RpcError::new(INVALID_PARAMS, &error.to_string()))?;
+                Ok(results.into_iter()
+                   .map(|(path, node)| json!({ "path": path, "value": value_to_json(node.value()) }))
+                   .collect())
+            },
+            "tree.subscribe" => {
+                let pattern = string_param(params, "pattern")?.to_string();
+                let subscription_id = server.next_subscription_id;
+                server.next_subscription_id += 1;
+                if let Some(client) = server.clients.get_mut(&client_id) {
+                    client.subscriptions.insert(subscription_id, pattern);
+                }
+                Ok(json!(subscription_id))
+            },
+            "tree.unsubscribe" => {
+                let subscription_id = params.get("subscription")
+                    .and_then(Value::as_u64)
+                    .ok_or(RpcError::new(INVALID_PARAMS, "Missing subscription."))?;
+                let removed = server.clients.get_mut(&client_id)
+                    .and_then(|client| client.subscriptions.remove(&subscription_id));
+                Ok(json!(removed.is_some()))
+            },
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, &format!("Method {} not found.", method))),
+        };
+    }
+
+    fn string_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+        return params.get(name)
+            .and_then(Value::as_str)
+            .ok_or(RpcError::new(INVALID_PARAMS, &format!("Missing string parameter {}.", name)));
+    }
+
+    fn list_commands(world: &World) -> Value {
+        let state = world.resource::<CommandCentralState>();
+        let commands: Vec<Value> = state.commands.commands.iter().map(|(system_name, command)| {
+            let parameters: Vec<Value> = command.parameters.iter().map(|param| json!({
+                "name": param.name,
+                "type": param.param_type,
+                "docs": param.docs,
+                "optional": param.optional,
+                "default": param.default.as_ref().map(value_to_json),
+                "range": param.range.map(|(min, max)| json!([min, max])),
+            })).collect();
+            let unavailable_reason = command.unavailable_reason(world);
+
+            return json!({
+                "name": system_name,
+                "title": command.title,
+                "docs": command.docs,
+                "category": command.category,
+                "shortcut": state.keymap.shortcut(system_name),
+                "parameters": parameters,
+                "returns": command.returns.as_ref().map(|returns| json!({
+                    "type": returns.return_type,
+                    "docs": returns.docs,
+                })),
+                "available": unavailable_reason.is_none(),
+                "unavailable_reason": unavailable_reason,
+                "checked": command.is_checked(world),
+                "mutates_document": command.mutates_document,
+            });
+        }).collect();
+
+        return Value::Array(commands);
+    }
+
+    fn run_remote_command(world: &mut World, params: &Value) -> Result<Value, RpcError> {
+        let system_name = string_param(params, "name")?;
+        let parameters = match world.resource::<CommandCentralState>().commands.commands.get(system_name) {
+            Some(command) => command.parameters.clone(),
+            None => { return Err(RpcError::new(APP_ERROR, &format!("Command {} not found.", system_name))); }
+        };
+
+        let mut command_args: ClaydashCommandArgs = CommandArgs::new();
+        if let Some(args) = params.get("args").and_then(Value::as_object) {
+            for (name, value) in args {
+                let expected = parameters.iter()
+                    .find(|param| param.name == *name)
+                    .map(|param| param.param_type.as_str());
+                let value = json_to_value(value, expected)
+                    .map_err(|error| RpcError::new(INVALID_PARAMS, &format!("{}: {}", name, error)))?;
+                command_args.insert(name.clone(), value);
+            }
+        }
+
+        return match run_command(world, system_name, &command_args) {
+            Ok(Some(value)) => Ok(value_to_json(&value)),
+            Ok(None) => Ok(Value::Null),
+            Err(error) => Err(RpcError::new(APP_ERROR, &error.to_string())),
+        };
+    }
+
+    /// Writes a value, as its own undo step like commands.
+    fn set_tree_path(world: &mut World, params: &Value) -> Result<Value, RpcError> {
+        let path = string_param(params, "path")?;
+        let value = params.get("value").ok_or(RpcError::new(INVALID_PARAMS, "Missing value."))?;
+
+        let tree = &world.resource::<ClaydashData>().tree;
+        let expected = tree.get_ref(path).map(|value| value.kind()).filter(|kind| *kind != "None");
+        let value = json_to_value(value, expected).map_err(|error| RpcError::new(INVALID_PARAMS, &error))?;
+
+        make_pending_changes_undo_step(world);
+        world.resource_mut::<ClaydashData>().tree.try_set_path(path, value)
+            .map_err(|error| RpcError::new(APP_ERROR, &error.to_string()))?;
+        make_command_undo_step(world, &format!("Set {}", path));
+
+        return Ok(Value::Null);
+    }
+
+    /// Sends tree changes to subscribed clients. Only the latest value of each path is sent.
+    fn send_tree_notifications(server: &RemoteControlServer) {
+        let mut changes: BTreeMap<String, ClaydashValue> = BTreeMap::new();
+        while let Ok(update) = server.updates.try_recv() {
+            changes.insert(update.path, update.value);
+        }
+
+        for client in server.clients.values() {
+            for (subscription_id, pattern) in client.subscriptions.iter() {
+                for (path, value) in changes.iter().filter(|(path, _)| path_matches_pattern(path, pattern)) {
+                    let notification = json!({
+                        "jsonrpc": "2.0",
+                        "method": "tree.changed",
+                        "params": { "subscription": subscription_id, "path": path, "value": value_to_json(value) }
+                    });
+                    _ = client.outgoing.send(notification.to_string());
+                }
+            }
+        }
+    }
+
+    fn value_to_json(value: &ClaydashValue) -> Value {
+        return match value {
+            ClaydashValue::I32(value) => json!(value),
+            ClaydashValue::F32(value) => json!(value),
+            ClaydashValue::Bool(value) => json!(value),
+            ClaydashValue::String(value) => json!(value),
+            ClaydashValue::Uuid(value) => json!(value.to_string()),
+            ClaydashValue::VecUuid(values) => json!(values.iter().map(|value| value.to_string()).collect::<Vec<String>>()),
+            ClaydashValue::Vec2(value) => json!(value.to_array()),
+            ClaydashValue::Vec3(value) => json!(value.to_array()),
+            ClaydashValue::Vec4(value) => json!(value.to_array()),
+            ClaydashValue::None => Value::Null,
+            // Values that can't be serialized, like snapshots, are null.
+            _ => serde_json::to_value(value).unwrap_or(Value::Null),
+        };
+    }
+
+    /// Converts a JSON value. `expected` is the kind of value wanted, if known.
+    fn json_to_value(value: &Value, expected: Option<&str>) -> Result<ClaydashValue, String> {
+        return match value {
+            Value::Null => Ok(ClaydashValue::None),
+            Value::Bool(value) => Ok(ClaydashValue::Bool(*value)),
+            Value::Number(number) => match number.as_i64() {
+                Some(integer) if expected != Some("F32") => i32::try_from(integer)
+                    .map(ClaydashValue::I32)
+                    .map_err(|_| format!("{} does not fit in a 32 bit integer.", integer)),
+                _ => Ok(ClaydashValue::F32(number.as_f64().unwrap_or(0.0) as f32)),
+            },
+            Value::String(value) => match expected {
+                Some("Uuid") => uuid::Uuid::parse_str(value)
+                    .map(ClaydashValue::Uuid)
+                    .map_err(|error| error.to_string()),
+                _ => Ok(ClaydashValue::String(value.clone())),
+            },
+            Value::Array(values) if expected == Some("VecUuid") => {
+                let uuids: Result<Vec<uuid::Uuid>, String> = values.iter().map(|value| {
+                    let value = value.as_str().ok_or("Expected a uuid string.".to_string())?;
+                    return uuid::Uuid::parse_str(value).map_err(|error| error.to_string());
+                }).collect();
+                uuids.map(ClaydashValue::VecUuid)
+            },
+            Value::Array(values) => {
+                let numbers: Result<Vec<f32>, String> = values.iter().map(|value| {
+                    return value.as_f64().map(|value| value as f32).ok_or(format!("Expected a number, found {}", value));
+                }).collect();
+
+                match numbers?.as_slice() {
+                    [x, y] => Ok(ClaydashValue::Vec2(Vec2::new(*x, *y))),
+                    [x, y, z] => Ok(ClaydashValue::Vec3(Vec3::new(*x, *y, *z))),
+                    [x, y, z, w] => Ok(ClaydashValue::Vec4(Vec4::new(*x, *y, *z, *w))),
+                    _ => Err("Only arrays of 2 to 4 numbers can be used as values.".to_string()),
+                }
+            },
+            // Other values use their serialized form, like {"Transform": ...}.
+            Value::Object(_) => serde_json::from_value(value.clone()).map_err(|error| error.to_string()),
+        };
+    }
+}
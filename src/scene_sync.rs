@@ -0,0 +1,194 @@
+//! Shares the scene with other claydash instances through a scene relay server.
+//! Start the relay with `make run-relay`, then run "Toggle scene sync" in each instance.
+//!
+//! Only native builds can sync for now.
+
+use bevy::prelude::*;
+
+pub struct SceneSyncPlugin;
+
+impl Plugin for SceneSyncPlugin {
+    fn build(&self, _app: &mut App) {
+        #[cfg(not(target_arch = "wasm32"))]
+        _app.init_non_send_resource::<native::SceneSyncState>()
+            .add_systems(Startup, native::setup_scene_sync_commands)
+            .add_systems(Update, native::exchange_scene_changes);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use bevy::prelude::*;
+    use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+    use std::time::Duration;
+    use command_central::CommandBuilder;
+    use observable_key_value_tree::{ObservableKVTree, SyncSession, SyncMessage};
+    use tungstenite::{Message, stream::MaybeTlsStream};
+
+    use crate::claydash_data::{ClaydashData, ClaydashValue, OBJECTS_PATH, OBJECT_ORDER_PATH};
+    use crate::command_central_plugin::{CommandCentralState, ClaydashCommandBuilder};
+
+    const DEFAULT_RELAY_ADDRESS: &str = "ws://127.0.0.1:9001";
+    /// Paths shared with other users. Selection and editor state stay local.
+    /// Objects are stored one per path, so concurrent edits of different objects are all kept.
+    /// Objects missing from a concurrently edited order still show, after the others.
+    const SYNCED_PATHS: [&str; 2] = [OBJECTS_PATH, OBJECT_ORDER_PATH];
+    /// How long the connection thread waits for a message before sending local changes.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    struct SceneSyncConnection {
+        session: SyncSession<ClaydashValue>,
+        outgoing: Sender<String>,
+        incoming: Receiver<String>,
+    }
+
+    /// Not a regular resource, since the session holds an update channel receiver.
+    /// Dropping the connection drops the receiver, and the tree then stops sending to it.
+    #[derive(Default)]
+    pub struct SceneSyncState {
+        connection: Option<SceneSyncConnection>,
+    }
+
+    pub fn setup_scene_sync_commands(mut bevy_command_central: ResMut<CommandCentralState>) {
+        let state = bevy_command_central.as_mut();
+
+        CommandBuilder::new()
+            .title("Toggle scene sync")
+            .system_name("toggle-scene-sync")
+            .docs("Share the scene with other users connected to the scene relay (editor.sync.address, default ws://127.0.0.1:9001).")
+            .category("File")
+            .mutates_document(false)
+            .menu("File/Scene sync", 200)
+            .checked_tree(|tree| tree.get_path("editor.sync.enabled").unwrap_bool_or(false))
+            .tree_callback(toggle_scene_sync)
+            .register(state);
+    }
+
+    fn toggle_scene_sync(tree: &mut ObservableKVTree<ClaydashValue>) {
+        let enabled = tree.get_path("editor.sync.enabled").unwrap_bool_or(false);
+        tree.set_path("editor.sync.enabled", ClaydashValue::Bool(!enabled));
+    }
+
+    /// Sends local changes to the relay and applies changes from other users.
+    pub fn exchange_scene_changes(
+        mut data_resource: ResMut<ClaydashData>,
+        mut sync_state: NonSendMut<SceneSyncState>,
+    ) {
+        let tree = &mut data_resource.as_mut().tree;
+        let enabled = tree.get_path("editor.sync.enabled").unwrap_bool_or(false);
+
+        if !enabled {
+            // Dropping the connection closes the socket.
+            sync_state.connection = None;
+            return;
+        }
+
+        if sync_state.connection.is_none() {
+            let address = match tree.get_path("editor.sync.address") {
+                ClaydashValue::String(address) => address,
+                _ => DEFAULT_RELAY_ADDRESS.to_string()
+            };
+            sync_state.connection = Some(connect(&address, tree));
+        }
+
+        let connection = sync_state.connection.as_mut().unwrap();
+
+        match connection.session.collect_local_changes() {
+            Ok(messages) => send_messages(&connection.outgoing, messages),
+            Err(error) => { println!("Could not serialize scene changes: {}", error); }
+        }
+
+        loop {
+            match connection.incoming.try_recv() {
+                Ok(text) => {
+                    let applied = serde_json::from_str::<SyncMessage>(&text)
+                        .and_then(|message| connection.session.apply_remote_change(tree, &message));
+                    if let Err(error) = applied {
+                        println!("Ignoring invalid scene change: {}", error);
+                    }
+                },
+                Err(TryRecvError::Empty) => { break; },
+                Err(TryRecvError::Disconnected) => {
+                    sync_state.connection = None;
+                    tree.set_path("editor.sync.enabled", ClaydashValue::Bool(false));
+                    break;
+                }
+            }
+        }
+    }
+
+    fn send_messages(outgoing: &Sender<String>, messages: Vec<SyncMessage>) {
+        for message in messages.iter() {
+            match serde_json::to_string(message) {
+                Ok(text) => { _ = outgoing.send(text); },
+                Err(error) => { println!("Could not serialize scene change: {}", error); }
+            }
+        }
+    }
+
+    /// Starts a connection thread and shares the current scene.
+    fn connect(address: &str, tree: &mut ObservableKVTree<ClaydashValue>) -> SceneSyncConnection {
+        let (outgoing_tx, outgoing_rx) = channel::<String>();
+        let (incoming_tx, incoming_rx) = channel::<String>();
+
+        let thread_address = address.to_string();
+        std::thread::spawn(move || {
+            match run_connection(&thread_address, outgoing_rx, incoming_tx) {
+                Ok(()) => { println!("Scene sync stopped."); },
+                Err(error) => { println!("Scene sync disconnected: {}", error); }
+            }
+        });
+
+        let site_id = uuid::Uuid::new_v4().to_string();
+        let mut session = SyncSession::new(&site_id, tree, &SYNCED_PATHS);
+        match session.publish_current_state(tree) {
+            Ok(messages) => send_messages(&outgoing_tx, messages),
+            Err(error) => { println!("Could not serialize scene: {}", error); }
+        }
+
+        return SceneSyncConnection {
+            session,
+            outgoing: outgoing_tx,
+            incoming: incoming_rx,
+        };
+    }
+
+    /// Forwards messages between the app and the relay until either side disconnects.
+    fn run_connection(
+        address: &str,
+        outgoing: Receiver<String>,
+        incoming: Sender<String>
+    ) -> tungstenite::Result<()> {
+        let (mut socket, _response) = tungstenite::connect(address)?;
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        }
+        println!("Scene sync connected to {}.", address);
+
+        loop {
+            loop {
+                match outgoing.try_recv() {
+                    Ok(text) => { socket.send(Message::Text(text))?; },
+                    Err(TryRecvError::Empty) => { break; },
+                    Err(TryRecvError::Disconnected) => {
+                        socket.close(None)?;
+                        return Ok(());
+                    }
+                }
+            }
+
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    if incoming.send(text).is_err() {
+                        return Ok(());
+                    }
+                },
+                Ok(Message::Close(_)) => { return Ok(()); },
+                Ok(_) => {},
+                Err(tungstenite::Error::Io(error))
+                    if error.kind() == std::io::ErrorKind::WouldBlock || error.kind() == std::io::ErrorKind::TimedOut => {},
+                Err(error) => { return Err(error); }
+            }
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Rhai scripting, to automate claydash and procedurally generate scenes.
+//!
+//! Scripts can:
+//!  - Run commands by system name, with named arguments:
+//!    `command("spawn-sphere", #{ position: [0.0, 1.0, 0.0], radius: 0.5 })`.
+//!    Commands are also functions, with dashes and namespace separators replaced by underscores:
+//!    `spawn_sphere(#{ radius: 0.5 })`, `teapots_spawn_teapot()`.
+//!  - Read and write the data tree: `get("editor.colorpicker.color")`, `set("editor.sync.enabled", true)`.
+//!  - List tree paths matching a pattern: `query("scene.**")`, optionally filtered by value:
+//!    `query("scene.objects.*[kind=SDFObject]")`, `query("editor.**[value=true]")`.
+//!  - Check whether a command can run now: `if is_available("grab") { grab() }`.
+//!    Running an unavailable command is an error.
+//!
+//! Numbers are converted to the type expected by the command parameter or the current value,
+//! and arrays of 2 to 4 numbers become vectors. Uuids are strings.
+//!
+//! Scripts run from the script console, the `run-script` command or the `run-script-file` command.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use command_central::{CommandBuilder, CommandParam, CommandArgs, NAMESPACE_SEPARATOR};
+use rhai::{Engine, Dynamic, EvalAltResult, Array, Map, INT, FLOAT};
+
+use crate::claydash_data::{ClaydashValue, ClaydashData};
+use crate::command_central_plugin::{CommandCentralState, ClaydashCommandArgs, ClaydashCommandBuilder, run_command, queue_command};
+
+/// Stops runaway scripts (like infinite loops) instead of freezing the app.
+const MAX_SCRIPT_OPERATIONS: u64 = 50_000_000;
+
+pub struct ScriptingPlugin;
+
+impl Plugin for ScriptingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScriptConsoleState>()
+            .add_systems(Startup, register_script_commands)
+            .add_systems(Update, script_console_ui);
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ScriptConsoleState {
+    pub open: bool,
+    pub code: String,
+    /// Printed text, results and errors of scripts.
+    pub output: Vec<String>,
+}
+
+fn register_script_commands(mut bevy_command_central: ResMut<CommandCentralState>) {
+    let state = bevy_command_central.as_mut();
+
+    CommandBuilder::new()
+        .title("Run script")
+        .system_name("run-script")
+        .docs("Run a Rhai script. Scripts can run commands and read or write the data tree.")
+        .category("Script")
+        .param(CommandParam::new("code", "String", "Rhai code"))
+        .returns("Any", "Value of the last expression of the script")
+        .callback_with_args(run_script_command)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Run script file")
+        .system_name("run-script-file")
+        .docs("Run a Rhai script from a file. Useful to generate scenes procedurally.")
+        .category("Script")
+        .param(CommandParam::new("path", "String", "Path of the .rhai file"))
+        .returns("Any", "Value of the last expression of the script")
+        .callback_with_args(run_script_file_command)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Toggle script console")
+        .system_name("toggle-script-console")
+        .docs("Show or hide the script console, where Rhai scripts can be typed and run.")
+        .category("Script")
+        .mutates_document(false)
+        .menu("Script/Script console", 0)
+        .callback(toggle_script_console)
+        .register(state);
+}
+
+fn run_script_command(world: &mut World, args: &ClaydashCommandArgs) -> Option<ClaydashValue> {
+    let code = match &args["code"] {
+        ClaydashValue::String(code) => code.clone(),
+        _ => { return None; }
+    };
+    return run_script(world, &code);
+}
+
+fn run_script_file_command(world: &mut World, args: &ClaydashCommandArgs) -> Option<ClaydashValue> {
+    let path = match &args["path"] {
+        ClaydashValue::String(path) => path.clone(),
+        _ => { return None; }
+    };
+    return match std::fs::read_to_string(&path) {
+        Ok(code) => run_script(world, &code),
+        Err(error) => {
+            log_script_output(world, format!("Could not read {}: {}", path, error));
+            None
+        }
+    };
+}
+
+fn toggle_script_console(world: &mut World) {
+    let mut console = world.resource_mut::<ScriptConsoleState>();
+    console.open = !console.open;
+}
+
+/// Runs a script and returns the value of its last expression.
+/// Errors are printed and shown in the script console.
+pub fn run_script(world: &mut World, code: &str) -> Option<ClaydashValue> {
+    // Script functions must own what they access, so the world is moved into a shared cell
+    // while the script runs, then moved back.
+    let shared_world = Rc::new(RefCell::new(std::mem::take(world)));
+    let printed = Rc::new(RefCell::new(Vec::<String>::new()));
+
+    let result = {
+        let engine = create_engine(&shared_world, &printed);
+        engine.eval::<Dynamic>(code)
+    };
+
+    *world = std::mem::take(&mut *shared_world.borrow_mut());
+
+    for text in printed.take() {
+        log_script_output(world, text);
+    }
+
+    return match result.map_err(|error| error.to_string()).and_then(|value| dynamic_to_value(value, None)) {
+        Ok(ClaydashValue::None) => None,
+        Ok(value) => {
+            log_script_output(world, format!("=> {}", value_to_dynamic(&value)));
+            Some(value)
+        },
+        Err(error) => {
+            log_script_output(world, format!("Script error: {}", error));
+            None
+        }
+    };
+}
+
+fn log_script_output(world: &mut World, text: String) {
+    println!("{}", text);
+    if let Some(mut console) = world.get_resource_mut::<ScriptConsoleState>() {
+        console.output.push(text);
+    }
+}
+
+fn create_engine(world: &Rc<RefCell<World>>, printed: &Rc<RefCell<Vec<String>>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+
+    let printed = printed.clone();
+    engine.on_print(move |text| printed.borrow_mut().push(text.to_string()));
+
+    let shared_world = world.clone();
+    engine.register_fn("command", move |name: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+        return call_command(&mut shared_world.borrow_mut(), name, Map::new());
+    });
+
+    let shared_world = world.clone();
+    engine.register_fn("command", move |name: &str, args: Map| -> Result<Dynamic, Box<EvalAltResult>> {
+        return call_command(&mut shared_world.borrow_mut(), name, args);
+    });
+
+    // Every command is also a function
+    let system_names: Vec<String> = world.borrow().resource::<CommandCentralState>().commands.commands.keys().cloned().collect();
+    for system_name in system_names {
+        let function_name = system_name.replace(['-', NAMESPACE_SEPARATOR], "_");
+
+        let shared_world = world.clone();
+        let name = system_name.clone();
+        engine.register_fn(&function_name, move || -> Result<Dynamic, Box<EvalAltResult>> {
+            return call_command(&mut shared_world.borrow_mut(), &name, Map::new());
+        });
+
+        let shared_world = world.clone();
+        let name = system_name.clone();
+        engine.register_fn(&function_name, move |args: Map| -> Result<Dynamic, Box<EvalAltResult>> {
+            return call_command(&mut shared_world.borrow_mut(), &name, args);
+        });
+    }
+
+    let shared_world = world.clone();
+    engine.register_fn("is_available", move |name: &str| -> bool {
+        let world = shared_world.borrow();
+        return match world.resource::<CommandCentralState>().commands.commands.get(name) {
+            Some(command) => command.is_available(&world),
+            None => false,
+        };
+    });
+
+    let shared_world = world.clone();
+    engine.register_fn("get", move |path: &str| -> Dynamic {
+        let world = shared_world.borrow();
+        return value_to_dynamic(world.resource::<ClaydashData>().tree.get_ref(path).unwrap_or(&ClaydashValue::None));
+    });
+
+    let shared_world = world.clone();
+    engine.register_fn("set", move |path: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        let mut world = shared_world.borrow_mut();
+        let tree = &mut world.resource_mut::<ClaydashData>().tree;
+        let expected = tree.get_ref(path).map(|value| value.kind()).filter(|kind| *kind != "None");
+        let value = dynamic_to_value(value, expected)?;
+        return tree.try_set_path(path, value).map_err(|error| error.to_string().into());
+    });
+
+    let shared_world = world.clone();
+    engine.register_fn("query", move |query: &str| -> Result<Array, Box<EvalAltResult>> {
+        let world = shared_world.borrow();
+        let results = world.resource::<ClaydashData>().tree.query_filtered(query, ClaydashValue::kind)
+            .map_err(|error| -> Box<EvalAltResult> { error.to_string().into() })?;
+        return Ok(results.into_iter().map(|(path, _)| Dynamic::from(path)).collect());
+    });
+
+    return engine;
+}
+
+fn call_command(world: &mut World, system_name: &str, args: Map) -> Result<Dynamic, Box<EvalAltResult>> {
+    let parameters = match world.resource::<CommandCentralState>().commands.commands.get(system_name) {
+        Some(command) => command.parameters.clone(),
+        None => { return Err(format!("Command {} not found.", system_name).into()); }
+    };
+
+    let mut command_args: ClaydashCommandArgs = CommandArgs::new();
+    for (name, value) in args {
+        let expected = parameters.iter()
+            .find(|param| param.name == name.as_str())
+            .map(|param| param.param_type.as_str());
+        command_args.insert(name.to_string(), dynamic_to_value(value, expected)?);
+    }
+
+    return match run_command(world, system_name, &command_args) {
+        Ok(Some(value)) => Ok(value_to_dynamic(&value)),
+        Ok(None) => Ok(Dynamic::UNIT),
+        Err(error) => Err(error.to_string().into()),
+    };
+}
+
+fn float_array(values: &[f32]) -> Dynamic {
+    return Dynamic::from_array(values.iter().map(|value| Dynamic::from(*value as FLOAT)).collect());
+}
+
+fn value_to_dynamic(value: &ClaydashValue) -> Dynamic {
+    return match value {
+        ClaydashValue::I32(value) => Dynamic::from(*value as INT),
+        ClaydashValue::F32(value) => Dynamic::from(*value as FLOAT),
+        ClaydashValue::Bool(value) => Dynamic::from(*value),
+        ClaydashValue::String(value) => Dynamic::from(value.clone()),
+        ClaydashValue::Uuid(value) => Dynamic::from(value.to_string()),
+        ClaydashValue::VecUuid(values) => {
+            Dynamic::from_array(values.iter().map(|value| Dynamic::from(value.to_string())).collect())
+        },
+        ClaydashValue::Vec2(value) => float_array(&value.to_array()),
+        ClaydashValue::Vec3(value) => float_array(&value.to_array()),
+        ClaydashValue::Vec4(value) => float_array(&value.to_array()),
+        ClaydashValue::None => Dynamic::UNIT,
+        // Other values are opaque to scripts, but can still be copied from one path to another.
+        _ => Dynamic::from(value.clone()),
+    };
+}
+
+/// Converts a script value. `expected` is the kind of value wanted, if known.
+fn dynamic_to_value(value: Dynamic, expected: Option<&str>) -> Result<ClaydashValue, String> {
+    if value.is_unit() {
+        return Ok(ClaydashValue::None);
+    }
+    if value.is::<ClaydashValue>() {
+        return Ok(value.cast::<ClaydashValue>());
+    }
+    if value.is::<INT>() {
+        let value = value.cast::<INT>();
+        return match expected {
+            Some("F32") => Ok(ClaydashValue::F32(value as f32)),
+            _ => i32::try_from(value)
+                .map(ClaydashValue::I32)
+                .map_err(|_| format!("{} does not fit in a 32 bit integer.", value)),
+        };
+    }
+    if value.is::<FLOAT>() {
+        return Ok(ClaydashValue::F32(value.cast::<FLOAT>() as f32));
+    }
+    if value.is::<bool>() {
+        return Ok(ClaydashValue::Bool(value.cast::<bool>()));
+    }
+    if value.is_string() {
+        let value = value.into_string()?;
+        return match expected {
+            Some("Uuid") => uuid::Uuid::parse_str(&value)
+                .map(ClaydashValue::Uuid)
+                .map_err(|error| error.to_string()),
+            _ => Ok(ClaydashValue::String(value)),
+        };
+    }
+    if value.is::<Array>() {
+        let values = value.cast::<Array>();
+
+        if expected == Some("VecUuid") {
+            let uuids: Result<Vec<uuid::Uuid>, String> = values.into_iter().map(|value| {
+                let value = value.into_string()?;
+                return uuid::Uuid::parse_str(&value).map_err(|error| error.to_string());
+            }).collect();
+            return uuids.map(ClaydashValue::VecUuid);
+        }
+
+        let numbers: Result<Vec<f32>, String> = values.into_iter().map(|value| {
+            if value.is::<INT>() {
+                return Ok(value.cast::<INT>() as f32);
+            }
+            return value.as_float().map(|value| value as f32).map_err(|type_name| {
+                format!("Expected a number, found {}", type_name)
+            });
+        }).collect();
+
+        return match numbers?.as_slice() {
+            [x, y] => Ok(ClaydashValue::Vec2(Vec2::new(*x, *y))),
+            [x, y, z] => Ok(ClaydashValue::Vec3(Vec3::new(*x, *y, *z))),
+            [x, y, z, w] => Ok(ClaydashValue::Vec4(Vec4::new(*x, *y, *z, *w))),
+            _ => Err("Only arrays of 2 to 4 numbers can be used as values.".to_string()),
+        };
+    }
+
+    return Err(format!("Unsupported script value of type {}", value.type_name()));
+}
+
+fn script_console_ui(
+    mut contexts: EguiContexts,
+    mut console: ResMut<ScriptConsoleState>,
+    mut commands: Commands,
+) {
+    if !console.open {
+        return;
+    }
+
+    let mut open = console.open;
+    egui::Window::new("Script console")
+        .open(&mut open)
+        .default_width(400.0)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.add(
+                egui::TextEdit::multiline(&mut console.code)
+                    .code_editor()
+                    .desired_rows(8)
+                    .desired_width(f32::INFINITY)
+                    .hint_text("command(\"spawn-sphere\", #{ position: [0.0, 1.0, 0.0] })")
+            );
+
+            ui.horizontal(|ui| {
+                if ui.button("Run").clicked() {
+                    let args = CommandArgs::from([("code".to_string(), ClaydashValue::String(console.code.clone()))]);
+                    queue_command(&mut commands, "run-script", args);
+                }
+                if ui.button("Clear output").clicked() {
+                    console.output.clear();
+                }
+            });
+
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in console.output.iter() {
+                        ui.monospace(line);
+                    }
+                });
+        });
+    console.open = open;
+}
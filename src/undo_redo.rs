@@ -1,44 +1,103 @@
 use bevy::prelude::*;
 use crate::claydash_data::{ClaydashData, ClaydashValue};
-use observable_key_value_tree::ObservableKVTree;
+use observable_key_value_tree::{ObservableKVTree, UndoHistoryLimits};
 use command_central::CommandBuilder;
-use crate::command_central_plugin::CommandCentralState;
+use crate::command_central_plugin::{CommandCentralState, ClaydashCommandBuilder};
 
 pub struct ClaydashUndoRedoPlugin;
 
 impl Plugin for ClaydashUndoRedoPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ClaydashData>()
-            .add_systems(Startup, setup_undo_redo_commands);
+            .add_systems(Startup, (setup_undo_redo_commands, setup_undo_history_limits));
     }
 }
 
+#[cfg(not(target_os = "macos"))]
 pub const UNDO_SHORTCUT: &str = "Shift+Z";
+#[cfg(not(target_os = "macos"))]
 pub const REDO_SHORTCUT: &str = "Shift+Y";
+// Command is its own modifier, so macOS gets the usual shortcuts.
+#[cfg(target_os = "macos")]
+pub const UNDO_SHORTCUT: &str = "Cmd+Z";
+#[cfg(target_os = "macos")]
+pub const REDO_SHORTCUT: &str = "Shift+Cmd+Z";
+
+const UNDO_HISTORY_MAX_STEPS: usize = 200;
+const UNDO_HISTORY_MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+fn setup_undo_history_limits(mut data_resource: ResMut<ClaydashData>) {
+    let tree = &mut data_resource.as_mut().tree;
+    tree.undo_history_limits = UndoHistoryLimits {
+        max_steps: Some(UNDO_HISTORY_MAX_STEPS),
+        max_memory: Some(UNDO_HISTORY_MAX_MEMORY),
+        estimate_value_size: ClaydashValue::estimated_size,
+    };
+}
 
 fn setup_undo_redo_commands(mut bevy_command_central: ResMut<CommandCentralState>) {
-    let commands = &mut bevy_command_central.commands;
+    let state = bevy_command_central.as_mut();
 
     CommandBuilder::new()
         .title("Undo")
         .system_name("undo")
         .docs("Undo last action.")
         .shortcut(&UNDO_SHORTCUT)
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(undo)))
-        .write(commands);
+        .category("Edit")
+        .mutates_document(false)
+        .menu("Edit/Undo", 0)
+        .tree_callback(undo)
+        .register(state);
 
     CommandBuilder::new()
         .title("Redo")
         .system_name("redo")
         .docs("Redo last action.")
         .shortcut(&REDO_SHORTCUT)
-        .insert_param("callback", "system callback", Some(ClaydashValue::Fn(redo)))
-        .write(commands);
+        .category("Edit")
+        .mutates_document(false)
+        .menu("Edit/Redo", 1)
+        .tree_callback(redo)
+        .register(state);
+
+    CommandBuilder::new()
+        .title("Toggle saving undo history")
+        .system_name("toggle-save-undo-history")
+        .docs("Save undo/redo history in .claydash files, so it can be restored when the file is opened again.")
+        .category("File")
+        .mutates_document(false)
+        .menu("File/Save undo history", 3)
+        .checked_tree(|tree| tree.get_path("editor.save_undo_history").unwrap_bool_or(false))
+        .tree_callback(toggle_save_undo_history)
+        .register(state);
+}
+
+fn toggle_save_undo_history(
+    tree: &mut ObservableKVTree<ClaydashValue>
+) {
+    let save_undo_history = tree.get_path("editor.save_undo_history").unwrap_bool_or(false);
+    tree.set_path("editor.save_undo_history", ClaydashValue::Bool(!save_undo_history));
+}
+
+/// Menu label of undo and redo, naming the step they would undo or redo, like "Undo Duplicate".
+pub fn undo_redo_menu_label(system_name: &str, tree: &ObservableKVTree<ClaydashValue>) -> Option<String> {
+    let (title, label) = match system_name {
+        "undo" => ("Undo", tree.undo_label()),
+        "redo" => ("Redo", tree.redo_label()),
+        _ => { return None; }
+    };
+    return match label.filter(|label| !label.is_empty()) {
+        Some(label) => Some(format!("{} {}", title, label)),
+        None => Some(title.to_string()),
+    };
 }
 
 fn undo(
     tree: &mut ObservableKVTree<ClaydashValue>
 ) {
+    if let Some(label) = tree.undo_label().filter(|label| !label.is_empty()) {
+        println!("Undo {}", label);
+    }
     tree.undo();
     tree.dump_undo_state();
 }
@@ -46,6 +105,9 @@ fn undo(
 fn redo(
     tree: &mut ObservableKVTree<ClaydashValue>
 ) {
+    if let Some(label) = tree.redo_label().filter(|label| !label.is_empty()) {
+        println!("Redo {}", label);
+    }
     tree.redo();
     tree.dump_undo_state();
 }